@@ -49,6 +49,10 @@ fn print_change(node: &DataTree, oper: ChangeOperation) {
                 println!(" after {}", previous);
             }
         }
+        _ => {
+            print!("UNKNOWN: ");
+            print_node(node);
+        }
     }
 }
 
@@ -115,23 +119,14 @@ fn main() -> std::result::Result<(), ()> {
     println!("\n ========== READING RUNNING CONFIG: ==========\n");
     print_current_config(&session, &mod_name).map_err(|_| ())?;
 
-    let module_change_cb = |session: &Session,
-                            _sub_id: u32,
-                            module_name: &str,
-                            xpath: Option<&str>,
-                            event: Event,
-                            _request_id: u32| {
+    let module_change_cb = |ctx: &ChangeContext| {
+        let event = ctx.event();
         println!(
             "\n\n ========== EVENT {} CHANGES: ====================================\n",
             event,
         );
 
-        let path = if let Some(xpath) = xpath {
-            format!("{}//.", xpath)
-        } else {
-            format!("/{}:*//.", module_name)
-        };
-        let changes = match session.get_changes_iter(&path) {
+        let changes = match ctx.changes() {
             Ok(iter) => iter,
             Err(_) => return Ok(()),
         };
@@ -147,7 +142,7 @@ fn main() -> std::result::Result<(), ()> {
 
         if event == Event::Done {
             println!("\n\n ========== CONFIG HAS CHANGED, CURRENT RUNNING CONFIG: ==========\n");
-            print_current_config(&session, module_name)?;
+            print_current_config(ctx.session(), ctx.module_name())?;
         }
 
         Ok(())
@@ -45,11 +45,7 @@ fn main() -> std::result::Result<(), ()> {
         .map_err(|_| ())?;
 
     // Callback function.
-    let notif_cb = |_sess: &Session,
-                    _sub_id: u32,
-                    _notif_type: NotificationType,
-                    tree: &DataTree,
-                    _timestamp: time::SystemTime| {
+    let notif_cb = |_ctx: &NotifContext, tree: &DataTree| {
         let node = tree.reference().unwrap();
         println!(
             "\n\n ========== NOTIFICATION \"{}\" RECEIVED =======================\n",
@@ -66,8 +62,7 @@ fn main() -> std::result::Result<(), ()> {
         .new_notification_subscription(
             &mod_name,
             xpath.map(String::as_str),
-            None,
-            None,
+            ReplayWindow::LiveOnly,
             notif_cb,
             Default::default(),
         )
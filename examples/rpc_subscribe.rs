@@ -38,18 +38,13 @@ fn main() -> std::result::Result<(), ()> {
         .map_err(|_| ())?;
 
     // Callback function.
-    let f = |_sess: &Session,
-             _sub_id: u32,
-             path: &str,
-             input: &DataTree,
-             _event: Event,
-             _request_id: u32,
-             output: &mut DataTree| {
+    let f = |ctx: &RpcContext, output: &mut DataTree| {
+        let path = ctx.op_path();
         println!(
             "\n\n ========== RPC \"{}\" RECEIVED: =======================\n",
             path
         );
-        if let Some(input_node) = input.reference() {
+        if let Some(input_node) = ctx.input().reference() {
             for node in input_node.traverse() {
                 print_node(node);
             }
@@ -0,0 +1,48 @@
+//! The async-std [`Runtime`].
+
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd, RawFd};
+use std::task::{Context, Poll};
+
+use async_io::Async;
+
+use crate::runtime::{ReadableFd, Runtime};
+
+/// The global async-std executor.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AsyncStdRuntime;
+
+impl Runtime for AsyncStdRuntime {
+    type Fd = AsyncStdFd;
+
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // Dropping the handle detaches the task.
+        drop(::async_std::task::spawn(future));
+    }
+
+    fn register(&self, fd: RawFd) -> io::Result<AsyncStdFd> {
+        Async::new(Fd(fd)).map(AsyncStdFd)
+    }
+}
+
+/// A file descriptor registered with an [`AsyncStdRuntime`].
+pub struct AsyncStdFd(Async<Fd>);
+
+impl ReadableFd for AsyncStdFd {
+    fn poll_readable(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.poll_readable(cx)
+    }
+}
+
+struct Fd(RawFd);
+
+impl AsFd for Fd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // The file descriptor outlives its registration.
+        unsafe { BorrowedFd::borrow_raw(self.0) }
+    }
+}
@@ -0,0 +1,563 @@
+//! Backups of the data of modules and of whole repositories.
+//!
+//! A module backup is text with a header of metadata, followed by an empty
+//! line and the data in the libyang JSON format:
+//!
+//! ```text
+//! module: ietf-interfaces
+//! revision: 2018-02-20
+//! datastore: running
+//! timestamp: 1700000000.000000000
+//!
+//! {"ietf-interfaces:interfaces": ...}
+//! ```
+//!
+//! A repository backup adds the installed modules and their schemas, so the
+//! data can be restored on a repository without them. It is saved as a
+//! directory:
+//!
+//! ```text
+//! modules                       installed modules, one per line
+//! schemas/<module>@<rev>.yang   schemas of all modules and submodules
+//! data/<datastore>/<module>     module backups
+//! ```
+
+use std::ffi::{CString, OsString};
+use std::fmt;
+use std::fs;
+use std::os::raw::c_char;
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+use crate::yang::context::Context;
+use crate::yang::data::{
+    Data, DataFormat, DataParserFlags, DataPrinterFlags, DataTree, DataValidationFlags,
+};
+use crate::yang::schema::{SchemaOutputFormat, SchemaPrinterFlags};
+use crate::{
+    ffi, str_to_cstring, sys_err, Connection, Datastore, Error, GetOptions, ModuleInfo, Result,
+    Session,
+};
+
+/// The data of a module in a datastore at some point in time.
+#[derive(Clone, Debug)]
+pub struct ModuleBackup {
+    pub module: String,
+    /// Revision of the module the data was created with.
+    pub revision: Option<String>,
+    pub datastore: Datastore,
+    pub timestamp: SystemTime,
+    /// The data in the libyang JSON format, empty if there was none.
+    pub data: String,
+}
+
+impl ModuleBackup {
+    /// Parse the data of the backup.
+    ///
+    /// Returns `None` if the module had no data.
+    pub fn tree<'c>(&self, ctx: &'c Context) -> Result<Option<DataTree<'c>>> {
+        if self.data.is_empty() {
+            return Ok(None);
+        }
+        DataTree::parse_string(
+            ctx,
+            &self.data,
+            DataFormat::JSON,
+            DataParserFlags::NO_VALIDATION | DataParserFlags::STRICT,
+            DataValidationFlags::empty(),
+        )
+        .map(Some)
+        .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))
+    }
+}
+
+impl fmt::Display for ModuleBackup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let timestamp = self
+            .timestamp
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        writeln!(f, "module: {}", self.module)?;
+        if let Some(revision) = &self.revision {
+            writeln!(f, "revision: {}", revision)?;
+        }
+        writeln!(f, "datastore: {}", datastore_name(&self.datastore))?;
+        writeln!(
+            f,
+            "timestamp: {}.{:09}",
+            timestamp.as_secs(),
+            timestamp.subsec_nanos()
+        )?;
+        writeln!(f)?;
+        write!(f, "{}", self.data)
+    }
+}
+
+impl FromStr for ModuleBackup {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let inval = || Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+        let (header, data) = s.split_once("\n\n").ok_or_else(inval)?;
+
+        let mut module = None;
+        let mut revision = None;
+        let mut datastore = None;
+        let mut timestamp = None;
+        for line in header.lines() {
+            let (key, value) = line.split_once(": ").ok_or_else(inval)?;
+            match key {
+                "module" => module = Some(value.to_owned()),
+                "revision" => revision = Some(value.to_owned()),
+                "datastore" => datastore = Some(parse_datastore(value).ok_or_else(inval)?),
+                "timestamp" => timestamp = Some(parse_timestamp(value).ok_or_else(inval)?),
+                // Unknown metadata, from a newer version.
+                _ => {}
+            }
+        }
+
+        Ok(ModuleBackup {
+            module: module.ok_or_else(inval)?,
+            revision,
+            datastore: datastore.ok_or_else(inval)?,
+            timestamp: timestamp.ok_or_else(inval)?,
+            data: data.to_owned(),
+        })
+    }
+}
+
+impl Session<'_> {
+    /// Back up the data of a module in a datastore.
+    ///
+    /// Only configuration is backed up from the operational datastore.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn backup_module(
+        &self,
+        module: &str,
+        datastore: Datastore,
+        timeout: Duration,
+    ) -> Result<ModuleBackup> {
+        let revision = {
+            let ctx = self
+                .get_context()
+                .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+            let schema = ctx
+                .get_module_implemented(module)
+                .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+            schema.revision().map(str::to_owned)
+        };
+
+        let options = if datastore == Datastore::Operational {
+            GetOptions::NO_STATE
+        } else {
+            GetOptions::default()
+        };
+        let sess = self.connection().start_session(datastore.clone())?;
+        let timestamp = SystemTime::now();
+        let xpath = format!("/{}:*", module);
+        let data = match sess.get_data(&xpath, None, timeout, options) {
+            Ok(data) => data
+                .tree()
+                .print_string(DataFormat::JSON, DataPrinterFlags::WITH_SIBLINGS)
+                .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?,
+            Err(Error {
+                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                ..
+            }) => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(ModuleBackup {
+            module: module.to_owned(),
+            revision,
+            datastore,
+            timestamp,
+            data,
+        })
+    }
+
+    /// Replace the data of a module in a datastore with a backup.
+    ///
+    /// Fails with `SR_ERR_INVAL_ARG` if the installed revision of the module
+    /// differs from the revision of the backup.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn restore_module(
+        &self,
+        backup: &ModuleBackup,
+        datastore: Datastore,
+        timeout: Duration,
+    ) -> Result<()> {
+        let mut sess = self.connection().start_session(datastore)?;
+        let ctx = self
+            .get_context()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+        let schema = ctx
+            .get_module_implemented(&backup.module)
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+        if schema.revision() != backup.revision.as_deref() {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+
+        let config = backup.tree(&ctx)?;
+        sess.replace_config(Some(&backup.module), config, timeout)
+    }
+}
+
+/// The installed modules of a repository with their data.
+#[derive(Clone, Debug)]
+pub struct RepositoryBackup {
+    /// The modules installed in sysrepo.
+    pub modules: Vec<ModuleInfo>,
+    /// File names and YANG sources of all modules of the context, including
+    /// imported modules and submodules.
+    pub schemas: Vec<(String, String)>,
+    /// The data of all modules with configuration.
+    pub data: Vec<ModuleBackup>,
+}
+
+impl RepositoryBackup {
+    /// Save the backup to a directory, which is created if needed.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let mut modules = String::new();
+        for module in &self.modules {
+            modules.push_str(&module.name);
+            if let Some(revision) = &module.revision {
+                modules.push('@');
+                modules.push_str(revision);
+            }
+            for feature in &module.enabled_features {
+                modules.push(' ');
+                modules.push_str(feature);
+            }
+            modules.push('\n');
+        }
+        fs::create_dir_all(dir).map_err(sys_err)?;
+        fs::write(dir.join("modules"), modules).map_err(sys_err)?;
+
+        let schemas = dir.join("schemas");
+        fs::create_dir_all(&schemas).map_err(sys_err)?;
+        for (name, source) in &self.schemas {
+            fs::write(schemas.join(name), source).map_err(sys_err)?;
+        }
+
+        for backup in &self.data {
+            let data = dir.join("data").join(datastore_name(&backup.datastore));
+            fs::create_dir_all(&data).map_err(sys_err)?;
+            fs::write(data.join(&backup.module), backup.to_string()).map_err(sys_err)?;
+        }
+        Ok(())
+    }
+
+    /// Load a backup saved with `save`.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let modules = fs::read_to_string(dir.join("modules"))
+            .map_err(sys_err)?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut words = line.split(' ');
+                let module = words.next().unwrap_or_default();
+                let (name, revision) = match module.split_once('@') {
+                    Some((name, revision)) => (name, Some(revision.to_owned())),
+                    None => (module, None),
+                };
+                ModuleInfo {
+                    name: name.to_owned(),
+                    revision,
+                    enabled_features: words.map(str::to_owned).collect(),
+                    replay_support: false,
+                }
+            })
+            .collect();
+
+        let mut schemas = Vec::new();
+        for entry in fs::read_dir(dir.join("schemas")).map_err(sys_err)? {
+            let entry = entry.map_err(sys_err)?;
+            let Some(name) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            schemas.push((name, fs::read_to_string(entry.path()).map_err(sys_err)?));
+        }
+        schemas.sort();
+
+        let mut data = Vec::new();
+        let data_dir = dir.join("data");
+        if data_dir.exists() {
+            for datastore in fs::read_dir(&data_dir).map_err(sys_err)? {
+                for entry in fs::read_dir(datastore.map_err(sys_err)?.path()).map_err(sys_err)? {
+                    let path = entry.map_err(sys_err)?.path();
+                    data.push(fs::read_to_string(path).map_err(sys_err)?.parse()?);
+                }
+            }
+        }
+
+        Ok(RepositoryBackup {
+            modules,
+            schemas,
+            data,
+        })
+    }
+}
+
+impl Connection {
+    /// Back up the installed modules with their startup configuration, and
+    /// optionally their running configuration.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn backup(&self, include_running: bool, timeout: Duration) -> Result<RepositoryBackup> {
+        let modules = self.modules()?;
+
+        let mut schemas = Vec::new();
+        let mut config_modules = Vec::new();
+        {
+            let ctx = self
+                .get_context()
+                .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+            let ly_err = |_| Error::new(ffi::sr_error_t::SR_ERR_LY);
+            for module in ctx.modules(false) {
+                let source = module
+                    .print_string(SchemaOutputFormat::YANG, SchemaPrinterFlags::empty())
+                    .map_err(ly_err)?;
+                for submodule in included_submodules(&source) {
+                    let Some(submodule) = module.get_submodule_latest(submodule) else {
+                        continue;
+                    };
+                    let source = submodule
+                        .print_string(SchemaOutputFormat::YANG, SchemaPrinterFlags::empty())
+                        .map_err(ly_err)?;
+                    schemas.push((schema_file_name(&source), source));
+                }
+                if module.is_implemented() && module.data().any(|n| n.is_config()) {
+                    config_modules.push(module.name().to_owned());
+                }
+                schemas.push((schema_file_name(&source), source));
+            }
+        }
+        schemas.sort();
+        schemas.dedup();
+
+        let sess = self.start_session(Datastore::Running)?;
+        let mut datastores = vec![Datastore::Startup];
+        if include_running {
+            datastores.push(Datastore::Running);
+        }
+        let mut data = Vec::new();
+        for datastore in datastores {
+            for module in &config_modules {
+                data.push(sess.backup_module(module, datastore.clone(), timeout)?);
+            }
+        }
+
+        Ok(RepositoryBackup {
+            modules,
+            schemas,
+            data,
+        })
+    }
+
+    /// Install the modules of a backup that are not installed and restore
+    /// their data.
+    ///
+    /// Modules installed with another revision are not updated, so restoring
+    /// their data fails.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn restore(&self, backup: &RepositoryBackup, timeout: Duration) -> Result<()> {
+        let installed = self.modules()?;
+        let missing: Vec<&ModuleInfo> = backup
+            .modules
+            .iter()
+            .filter(|module| !installed.iter().any(|i| i.name == module.name))
+            .collect();
+        if !missing.is_empty() {
+            let dir = make_temp_dir()?;
+            let res = self.install_from_backup(backup, &missing, &dir);
+            let _ = fs::remove_dir_all(&dir);
+            res?;
+        }
+
+        let sess = self.start_session(Datastore::Running)?;
+        for module in &backup.data {
+            sess.restore_module(module, module.datastore.clone(), timeout)?;
+        }
+        Ok(())
+    }
+
+    fn install_from_backup(
+        &self,
+        backup: &RepositoryBackup,
+        modules: &[&ModuleInfo],
+        dir: &Path,
+    ) -> Result<()> {
+        for (name, source) in &backup.schemas {
+            fs::write(dir.join(name), source).map_err(sys_err)?;
+        }
+
+        let paths = modules
+            .iter()
+            .map(|module| {
+                let name = match &module.revision {
+                    Some(revision) => format!("{}@{}.yang", module.name, revision),
+                    None => format!("{}.yang", module.name),
+                };
+                str_to_cstring(&dir.join(name).to_string_lossy())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let features = modules
+            .iter()
+            .map(|module| {
+                module
+                    .enabled_features
+                    .iter()
+                    .map(|feature| str_to_cstring(feature))
+                    .collect::<Result<Vec<_>>>()
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let search_dir = str_to_cstring(&dir.to_string_lossy())?;
+
+        // NULL-terminated arrays, as sysrepo expects them.
+        let mut path_ptrs: Vec<*const c_char> = paths.iter().map(|p| p.as_ptr()).collect();
+        path_ptrs.push(ptr::null());
+        let mut feature_ptrs: Vec<Vec<*const c_char>> = features
+            .iter()
+            .map(|features| {
+                let mut ptrs: Vec<*const c_char> = features.iter().map(|f| f.as_ptr()).collect();
+                ptrs.push(ptr::null());
+                ptrs
+            })
+            .collect();
+        let mut feature_ptrs: Vec<*mut *const c_char> =
+            feature_ptrs.iter_mut().map(|f| f.as_mut_ptr()).collect();
+
+        let rc = unsafe {
+            ffi::sr_install_modules(
+                self.conn,
+                path_ptrs.as_mut_ptr(),
+                search_dir.as_ptr(),
+                feature_ptrs.as_mut_ptr(),
+            )
+        };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Names of the submodules included by a printed module.
+fn included_submodules(source: &str) -> impl Iterator<Item = &str> {
+    source.lines().filter_map(|line| {
+        let rest = line.trim_start().strip_prefix("include ")?;
+        rest.split(|c: char| c == ';' || c == '{' || c.is_whitespace())
+            .next()
+    })
+}
+
+/// The file name of a printed module or submodule, `<name>@<revision>.yang`.
+fn schema_file_name(source: &str) -> String {
+    let mut words = source.split_whitespace();
+    let name = words
+        .by_ref()
+        .skip_while(|&word| word != "module" && word != "submodule")
+        .nth(1)
+        .unwrap_or_default();
+    let revision = source.lines().find_map(|line| {
+        let rest = line.trim_start().strip_prefix("revision ")?;
+        rest.split(|c: char| c == ';' || c == '{' || c.is_whitespace())
+            .next()
+    });
+    match revision {
+        Some(revision) => format!("{}@{}.yang", name, revision),
+        None => format!("{}.yang", name),
+    }
+}
+
+/// Create a new directory for temporary files, only accessible to the current
+/// user.
+///
+/// The name of the directory is random and existing paths are never reused,
+/// so other users cannot prepare it beforehand.
+fn make_temp_dir() -> Result<PathBuf> {
+    let template = std::env::temp_dir().join("sysrepo-restore-XXXXXX");
+    let mut template = CString::new(template.into_os_string().into_vec())
+        .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?
+        .into_bytes_with_nul();
+    let dir = unsafe { libc::mkdtemp(template.as_mut_ptr() as *mut c_char) };
+    if dir.is_null() {
+        return Err(sys_err(std::io::Error::last_os_error()));
+    }
+    template.pop();
+    Ok(PathBuf::from(OsString::from_vec(template)))
+}
+
+fn datastore_name(datastore: &Datastore) -> &'static str {
+    match datastore {
+        Datastore::Startup => "startup",
+        Datastore::Running => "running",
+        Datastore::Candidate => "candidate",
+        Datastore::Operational => "operational",
+        Datastore::FactoryDefault => "factory-default",
+    }
+}
+
+fn parse_datastore(name: &str) -> Option<Datastore> {
+    match name {
+        "startup" => Some(Datastore::Startup),
+        "running" => Some(Datastore::Running),
+        "candidate" => Some(Datastore::Candidate),
+        "operational" => Some(Datastore::Operational),
+        "factory-default" => Some(Datastore::FactoryDefault),
+        _ => None,
+    }
+}
+
+fn parse_timestamp(s: &str) -> Option<SystemTime> {
+    let (secs, nanos) = s.split_once('.')?;
+    let nanos: u32 = nanos.parse().ok()?;
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    let duration = Duration::new(secs.parse().ok()?, nanos);
+    SystemTime::UNIX_EPOCH.checked_add(duration)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_parses() {
+        let expected = SystemTime::UNIX_EPOCH + Duration::new(1_700_000_000, 120_000_000);
+        assert_eq!(parse_timestamp("1700000000.120000000"), Some(expected));
+        assert_eq!(parse_timestamp("0.000000000"), Some(SystemTime::UNIX_EPOCH));
+    }
+
+    #[test]
+    fn timestamp_rejects_malformed() {
+        assert_eq!(parse_timestamp("1700000000"), None);
+        assert_eq!(parse_timestamp("1700000000.1000000000"), None);
+        assert_eq!(parse_timestamp("-1.000000000"), None);
+        assert_eq!(parse_timestamp("a.b"), None);
+    }
+
+    #[test]
+    fn datastore_names_roundtrip() {
+        for datastore in [
+            Datastore::Startup,
+            Datastore::Running,
+            Datastore::Candidate,
+            Datastore::Operational,
+            Datastore::FactoryDefault,
+        ] {
+            assert_eq!(parse_datastore(datastore_name(&datastore)), Some(datastore));
+        }
+        assert_eq!(parse_datastore("unknown"), None);
+    }
+}
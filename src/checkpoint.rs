@@ -0,0 +1,198 @@
+//! Checkpoints of configuration, which can be rolled back to.
+//!
+//! A checkpoint holds the configuration of a set of modules, so a datastore
+//! can be restored to it even without a candidate datastore. Checkpoints are
+//! saved as directories with one `<module>.json` file per module, and a
+//! `CheckpointStore` keeps a limited number of them.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::yang::context::Context;
+use crate::yang::data::{
+    Data, DataFormat, DataParserFlags, DataPrinterFlags, DataTree, DataValidationFlags,
+};
+use crate::{ffi, sys_err, Datastore, Error, GetOptions, Result, Session};
+
+/// The configuration of modules at some point in time.
+#[derive(Clone, Debug)]
+pub struct Checkpoint {
+    /// Module names with their configuration in the libyang JSON format.
+    modules: Vec<(String, String)>,
+}
+
+impl Checkpoint {
+    /// Snapshot the configuration of the modules in the datastore of the
+    /// session.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn create(sess: &Session, modules: &[&str], timeout: Duration) -> Result<Self> {
+        let options = if sess.datastore() == Datastore::Operational {
+            GetOptions::NO_STATE
+        } else {
+            GetOptions::default()
+        };
+        let modules = modules
+            .iter()
+            .map(|&module| {
+                let xpath = format!("/{}:*", module);
+                let config = match sess.get_data(&xpath, None, timeout, options.clone()) {
+                    Ok(data) => data
+                        .tree()
+                        .print_string(DataFormat::JSON, DataPrinterFlags::WITH_SIBLINGS)
+                        .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?,
+                    Err(Error {
+                        errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                        ..
+                    }) => String::new(),
+                    Err(e) => return Err(e),
+                };
+                Ok((module.to_owned(), config))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Checkpoint { modules })
+    }
+
+    /// Names of the modules in the checkpoint.
+    pub fn modules(&self) -> impl Iterator<Item = &str> {
+        self.modules.iter().map(|(module, _)| module.as_str())
+    }
+
+    /// The configuration of a module in the checkpoint, or `None` if the
+    /// module has no configuration.
+    pub fn tree<'c>(&self, ctx: &'c Context, module: &str) -> Result<Option<DataTree<'c>>> {
+        let (_, config) = self
+            .modules
+            .iter()
+            .find(|(name, _)| name == module)
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+        if config.is_empty() {
+            return Ok(None);
+        }
+        DataTree::parse_string(
+            ctx,
+            config,
+            DataFormat::JSON,
+            DataParserFlags::NO_VALIDATION | DataParserFlags::STRICT,
+            DataValidationFlags::empty(),
+        )
+        .map(Some)
+        .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))
+    }
+
+    /// Replace the configuration of the modules in the datastore of the
+    /// session with the checkpoint.
+    ///
+    /// Modules are replaced one after another, so a failure leaves the
+    /// modules before the failing one rolled back.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn rollback(&self, sess: &mut Session, timeout: Duration) -> Result<()> {
+        let ctx = sess
+            .get_context()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+        for module in self.modules() {
+            let config = self.tree(&ctx, module)?;
+            sess.replace_config(Some(module), config, timeout)?;
+        }
+        Ok(())
+    }
+
+    /// Save the checkpoint to a directory, which is created if needed.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir).map_err(sys_err)?;
+        for (module, config) in &self.modules {
+            fs::write(dir.join(format!("{}.json", module)), config).map_err(sys_err)?;
+        }
+        Ok(())
+    }
+
+    /// Load a checkpoint saved with `save`.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let mut modules = Vec::new();
+        for entry in fs::read_dir(dir).map_err(sys_err)? {
+            let path = entry.map_err(sys_err)?.path();
+            if path.extension() != Some(OsStr::new("json")) {
+                continue;
+            }
+            let Some(module) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            let config = fs::read_to_string(&path).map_err(sys_err)?;
+            modules.push((module.to_owned(), config));
+        }
+        modules.sort();
+        Ok(Checkpoint { modules })
+    }
+}
+
+/// A directory of checkpoints, keeping only the most recent ones.
+#[derive(Clone, Debug)]
+pub struct CheckpointStore {
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl CheckpointStore {
+    /// Use a directory for checkpoints, keeping at most `keep` of them.
+    pub fn new(dir: impl Into<PathBuf>, keep: usize) -> Self {
+        CheckpointStore {
+            dir: dir.into(),
+            keep,
+        }
+    }
+
+    /// Create and save a checkpoint, then remove the oldest checkpoints
+    /// beyond the limit.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn create(
+        &self,
+        sess: &Session,
+        modules: &[&str],
+        timeout: Duration,
+    ) -> Result<Checkpoint> {
+        let checkpoint = Checkpoint::create(sess, modules, timeout)?;
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        // Zero padded, so that names sort by time.
+        let name = format!("{:020}.{:09}", time.as_secs(), time.subsec_nanos());
+        checkpoint.save(&self.dir.join(name))?;
+
+        let checkpoints = self.list()?;
+        let excess = checkpoints.len().saturating_sub(self.keep);
+        for dir in &checkpoints[..excess] {
+            fs::remove_dir_all(dir).map_err(sys_err)?;
+        }
+        Ok(checkpoint)
+    }
+
+    /// Directories of the saved checkpoints, oldest first.
+    pub fn list(&self) -> Result<Vec<PathBuf>> {
+        let mut checkpoints = Vec::new();
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(checkpoints),
+            Err(e) => return Err(sys_err(e)),
+        };
+        for entry in entries {
+            let entry = entry.map_err(sys_err)?;
+            if entry.file_type().map_err(sys_err)?.is_dir() {
+                checkpoints.push(entry.path());
+            }
+        }
+        checkpoints.sort();
+        Ok(checkpoints)
+    }
+
+    /// Load the most recent checkpoint.
+    pub fn latest(&self) -> Result<Option<Checkpoint>> {
+        self.list()?
+            .last()
+            .map(|dir| Checkpoint::load(dir))
+            .transpose()
+    }
+}
@@ -0,0 +1,126 @@
+//! Textual rendering of configuration differences.
+//!
+//! Differences are rendered one node per line, keyed by the data path of the
+//! node, in the style of a unified diff:
+//!
+//! ```text
+//! - /m:cont/leaf = old
+//! + /m:cont/leaf = new
+//! + /m:cont/list[name='a']
+//! ```
+//!
+//! Moves of user-ordered entries, which only change sets have, are rendered
+//! with `~` and changes unknown to this crate with `?`.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::yang::data::DataTree;
+use crate::{ChangeOperation, Changes, Result};
+
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Render the differences between two data trees.
+///
+/// Lines are sorted by path. With `color`, removed lines are red and added
+/// lines green, using ANSI escape codes.
+pub fn diff_trees(old: &DataTree, new: &DataTree, color: bool) -> String {
+    let old = tree_nodes(old);
+    let new = tree_nodes(new);
+
+    let mut paths = old.keys().chain(new.keys()).collect::<Vec<_>>();
+    paths.sort();
+    paths.dedup();
+
+    let mut out = String::new();
+    for path in paths {
+        let old_value = old.get(path);
+        let new_value = new.get(path);
+        if old_value == new_value {
+            continue;
+        }
+        if let Some(value) = old_value {
+            line(&mut out, '-', path, value.as_deref(), color);
+        }
+        if let Some(value) = new_value {
+            line(&mut out, '+', path, value.as_deref(), color);
+        }
+    }
+    out
+}
+
+/// Render a change set, in the order of its changes.
+///
+/// With `color`, removed lines are red, added lines green and moved lines
+/// yellow, using ANSI escape codes.
+pub fn diff_changes(changes: &Changes, color: bool) -> Result<String> {
+    let mut out = String::new();
+    for change in changes {
+        let (node, oper) = change?;
+        let Some(node) = node.reference() else {
+            continue;
+        };
+        let path = node.path();
+        let value = node.value_canonical();
+        match oper {
+            ChangeOperation::Created
+            | ChangeOperation::CreatedLeafListUserOrdered { .. }
+            | ChangeOperation::CreatedListUserOrdered { .. } => {
+                line(&mut out, '+', &path, value.as_deref(), color);
+            }
+            ChangeOperation::Deleted => {
+                line(&mut out, '-', &path, value.as_deref(), color);
+            }
+            ChangeOperation::Modified { previous_value, .. } => {
+                line(&mut out, '-', &path, Some(previous_value), color);
+                line(&mut out, '+', &path, value.as_deref(), color);
+            }
+            ChangeOperation::MovedLeafListUserOrdered {
+                previous_value: previous,
+            }
+            | ChangeOperation::MovedListUserOrdered {
+                previous_key: previous,
+            } => {
+                let position = if previous.is_empty() {
+                    "first".to_owned()
+                } else {
+                    format!("after {}", previous)
+                };
+                line(&mut out, '~', &path, Some(&position), color);
+            }
+            ChangeOperation::Other(_) => {
+                line(&mut out, '?', &path, value.as_deref(), color);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Paths of all nodes of a tree with their canonical values.
+fn tree_nodes(tree: &DataTree) -> BTreeMap<String, Option<String>> {
+    tree.traverse()
+        .map(|node| (node.path(), node.value_canonical()))
+        .collect()
+}
+
+fn line(out: &mut String, sign: char, path: &str, value: Option<&str>, color: bool) {
+    if color {
+        out.push_str(match sign {
+            '-' => RED,
+            '+' => GREEN,
+            _ => YELLOW,
+        });
+    }
+    let _ = match (sign, value) {
+        ('~', Some(value)) => write!(out, "{} {} {}", sign, path, value),
+        (_, Some(value)) => write!(out, "{} {} = {}", sign, path, value),
+        (_, None) => write!(out, "{} {}", sign, path),
+    };
+    if color {
+        out.push_str(RESET);
+    }
+    out.push('\n');
+}
@@ -0,0 +1,265 @@
+//! Edits prepared once and applied many times.
+//!
+//! A `PreparedEdit` is an edit template in XML or JSON, where `${name}`
+//! placeholders are substituted with the values given when it is applied,
+//! for example to instantiate services with different keys.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::yang::context::Context;
+use crate::yang::data::{
+    Data, DataFormat, DataParserFlags, DataPrinterFlags, DataTree, DataValidationFlags,
+};
+use crate::{ffi, Connection, DefaultOperation, Error, Result, Session};
+
+/// Values of the placeholders of a template, by name.
+pub type Params = HashMap<String, String>;
+
+/// An edit template, checked against the context it is applied with.
+#[derive(Clone, Debug)]
+pub struct PreparedEdit {
+    template: String,
+    format: DataFormat,
+    params: Vec<String>,
+    generation: u32,
+}
+
+impl PreparedEdit {
+    /// Prepare an edit template in XML or JSON.
+    ///
+    /// A template without placeholders is parsed right away, while the others
+    /// are only parsed once their placeholders are substituted.
+    pub fn new(conn: &Connection, template: &str, format: DataFormat) -> Result<Self> {
+        if format == DataFormat::LYB {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+        let edit = PreparedEdit {
+            template: template.to_owned(),
+            format,
+            params: placeholders(template)?,
+            generation: conn.context_generation(),
+        };
+        edit.check(conn)?;
+        Ok(edit)
+    }
+
+    /// Prepare an edit template out of a data tree.
+    ///
+    /// Placeholders can only be used as values of string leaves, other values
+    /// are checked when the tree is created.
+    pub fn from_tree(conn: &Connection, tree: &DataTree) -> Result<Self> {
+        let template = tree
+            .print_string(DataFormat::JSON, DataPrinterFlags::WITH_SIBLINGS)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?;
+        PreparedEdit::new(conn, &template, DataFormat::JSON)
+    }
+
+    /// Names of the placeholders of the template.
+    pub fn params(&self) -> impl Iterator<Item = &str> {
+        self.params.iter().map(String::as_str)
+    }
+
+    /// Prepare the edit in a session with values for all placeholders.
+    ///
+    /// The edit merges its nodes, unless they have an operation attribute.
+    /// If the context changed since the edit was prepared, a template without
+    /// placeholders is checked again first. As it is a batch edit, it must be
+    /// the first change of the session.
+    pub fn apply(&mut self, sess: &mut Session, params: &Params) -> Result<()> {
+        let generation = sess.connection().context_generation();
+        if generation != self.generation {
+            self.check(sess.connection())?;
+            self.generation = generation;
+        }
+
+        let ctx = sess
+            .get_context()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+        let tree = self.instantiate(&ctx, params)?;
+        sess.edit_batch(&tree, DefaultOperation::Merge)
+    }
+
+    /// Substitute values for all placeholders and parse the edit.
+    ///
+    /// The template is scanned once, so placeholders in the values are left
+    /// as they are.
+    pub fn instantiate<'c>(&self, ctx: &'c Context, params: &Params) -> Result<DataTree<'c>> {
+        parse(
+            ctx,
+            &substitute(&self.template, self.format, params)?,
+            self.format,
+        )
+    }
+
+    fn check(&self, conn: &Connection) -> Result<()> {
+        if !self.params.is_empty() {
+            return Ok(());
+        }
+        let ctx = conn
+            .get_context()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+        parse(&ctx, &self.template, self.format).map(|_| ())
+    }
+}
+
+impl Session<'_> {
+    /// Instantiate an edit template and apply it.
+    ///
+    /// The changes are discarded if they cannot be applied. The timeout is
+    /// rounded to the nearest millisecond.
+    pub fn apply_template(
+        &mut self,
+        template: &str,
+        format: DataFormat,
+        params: &Params,
+        timeout: Duration,
+    ) -> Result<()> {
+        let mut edit = PreparedEdit::new(self.connection(), template, format)?;
+        edit.apply(self, params)?;
+        if let Err(e) = self.apply_changes(timeout) {
+            let _ = self.discard_changes();
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+fn parse<'c>(ctx: &'c Context, edit: &str, format: DataFormat) -> Result<DataTree<'c>> {
+    DataTree::parse_string(
+        ctx,
+        edit,
+        format,
+        DataParserFlags::NO_VALIDATION | DataParserFlags::STRICT,
+        DataValidationFlags::empty(),
+    )
+    .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))
+}
+
+/// The names of the `${name}` placeholders of a template, without duplicates.
+fn placeholders(template: &str) -> Result<Vec<String>> {
+    let mut params: Vec<String> = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        let end = rest[start..]
+            .find('}')
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+        let name = &rest[start + 2..start + end];
+        if name.is_empty() {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+        if !params.iter().any(|param| param == name) {
+            params.push(name.to_owned());
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(params)
+}
+
+/// Substitute the escaped values for the placeholders of a template checked
+/// by `placeholders`.
+fn substitute(template: &str, format: DataFormat, params: &Params) -> Result<String> {
+    let mut edit = String::with_capacity(template.len());
+    let mut rest = template;
+    // Every placeholder is closed, and the values are not scanned again.
+    while let Some(start) = rest.find("${") {
+        let end = start + rest[start..].find('}').unwrap();
+        let value = params
+            .get(&rest[start + 2..end])
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+        edit.push_str(&rest[..start]);
+        match format {
+            DataFormat::JSON => edit.push_str(&json_escape(value)),
+            _ => edit.push_str(&xml_escape(value)),
+        }
+        rest = &rest[end + 1..];
+    }
+    edit.push_str(rest);
+    Ok(edit)
+}
+
+/// Escape a value for use in XML text or an attribute.
+fn xml_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape a value for use inside a JSON string.
+fn json_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> Params {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn placeholders_are_listed_once() {
+        let names = placeholders("<a>${x}</a><b>${y}</b><c>${x}</c>").unwrap();
+        assert_eq!(names, ["x", "y"]);
+        assert!(placeholders("<a/>").unwrap().is_empty());
+    }
+
+    #[test]
+    fn placeholders_must_be_closed_and_named() {
+        assert!(placeholders("<a>${x</a>").is_err());
+        assert!(placeholders("<a>${}</a>").is_err());
+    }
+
+    #[test]
+    fn substitute_escapes_values() {
+        let xml = substitute("<a>${x}</a>", DataFormat::XML, &params(&[("x", "<&'\">")]));
+        assert_eq!(xml.unwrap(), "<a>&lt;&amp;&apos;&quot;&gt;</a>");
+
+        let json = substitute(
+            "{\"a\": \"${x}\"}",
+            DataFormat::JSON,
+            &params(&[("x", "\"\\\n\u{1}")]),
+        );
+        assert_eq!(json.unwrap(), "{\"a\": \"\\\"\\\\\\n\\u0001\"}");
+    }
+
+    #[test]
+    fn substitute_leaves_placeholders_in_values() {
+        let edit = substitute(
+            "<a>${x}</a><b>${y}</b>",
+            DataFormat::XML,
+            &params(&[("x", "${y}"), ("y", "2")]),
+        );
+        assert_eq!(edit.unwrap(), "<a>${y}</a><b>2</b>");
+    }
+
+    #[test]
+    fn substitute_fails_on_missing_value() {
+        assert!(substitute("<a>${x}</a>", DataFormat::XML, &Params::new()).is_err());
+    }
+}
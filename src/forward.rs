@@ -0,0 +1,227 @@
+//! Forwarding of notifications as JSON lines.
+//!
+//! Every notification is written as one JSON object per line, holding the
+//! module name, the notification type, the timestamp in seconds since the
+//! epoch and the notification itself in the libyang JSON format:
+//!
+//! ```text
+//! {"module":"m","type":"realtime","time":1700000000.000000000,"notification":{...}}
+//! ```
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+
+use crate::yang::data::{Data, DataFormat, DataPrinterFlags, DataTree};
+use crate::{
+    ffi, Error, NotifContext, NotificationType, ReplayWindow, Result, Session, Subscription,
+    SubscriptionOptions,
+};
+
+/// Where forwarded notifications are written to.
+pub enum Sink {
+    /// Append to a file, which is reopened after write errors.
+    File(PathBuf),
+    /// Write to a UNIX stream socket, which is reconnected after write
+    /// errors.
+    UnixSocket(PathBuf),
+    /// Write to a user-provided writer. Lines that fail to be written are
+    /// dropped.
+    Writer(Box<dyn Write + Send>),
+}
+
+/// Options of a `NotificationForwarder`.
+#[derive(Clone, Debug)]
+pub struct ForwarderOptions {
+    /// Number of lines queued for the sink. Notifications arriving while the
+    /// queue is full are dropped, so slow sinks never block sysrepo.
+    pub capacity: usize,
+    /// Time to wait before reopening a file or socket sink.
+    pub retry_interval: Duration,
+}
+
+impl Default for ForwarderOptions {
+    fn default() -> Self {
+        ForwarderOptions {
+            capacity: 1024,
+            retry_interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Forwards the notifications of modules to a sink, until dropped.
+///
+/// Lines are written from a separate thread. Dropping the forwarder
+/// unsubscribes and waits for the queued lines to be written.
+pub struct NotificationForwarder<'a> {
+    subscription: Option<Subscription<'a>>,
+    dropped: Arc<AtomicU64>,
+    stopping: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl<'a> NotificationForwarder<'a> {
+    pub fn new(
+        sess: &Session<'a>,
+        modules: &[&str],
+        sink: Sink,
+        options: ForwarderOptions,
+    ) -> Result<Self> {
+        let (tx, rx) = mpsc::sync_channel::<String>(options.capacity);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let stopping = Arc::new(AtomicBool::new(false));
+
+        let mut subscription = Subscription::from_raw(sess.conn, std::ptr::null_mut());
+        for &module in modules {
+            let tx = tx.clone();
+            let dropped = dropped.clone();
+            let module_name = module.to_owned();
+            let callback = move |ctx: &NotifContext, notif: &DataTree| {
+                let Some(line) =
+                    notification_line(&module_name, ctx.notif_type(), notif, ctx.timestamp())
+                else {
+                    return;
+                };
+                if tx.try_send(line).is_err() {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            };
+            sess.add_notification_subscription(
+                &mut subscription,
+                module,
+                None,
+                ReplayWindow::LiveOnly,
+                callback,
+                SubscriptionOptions::default(),
+            )?;
+        }
+        drop(tx);
+
+        let mut output = Output { sink, writer: None };
+        let thread_dropped = dropped.clone();
+        let thread_stopping = stopping.clone();
+        let thread = thread::Builder::new()
+            .name("sysrepo-forward".to_owned())
+            .spawn(move || {
+                for line in rx {
+                    while output.write_line(&line).is_err() {
+                        if !output.reconnects() || thread_stopping.load(Ordering::Relaxed) {
+                            thread_dropped.fetch_add(1, Ordering::Relaxed);
+                            break;
+                        }
+                        thread::sleep(options.retry_interval);
+                    }
+                }
+            })
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_SYS))?;
+
+        Ok(NotificationForwarder {
+            subscription: Some(subscription),
+            dropped,
+            stopping,
+            thread: Some(thread),
+        })
+    }
+
+    /// Number of notifications dropped because the queue was full or they
+    /// could not be written.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for NotificationForwarder<'_> {
+    fn drop(&mut self) {
+        // Unsubscribing frees the callbacks, which closes the queue.
+        self.subscription.take();
+        self.stopping.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A sink together with its open writer.
+struct Output {
+    sink: Sink,
+    writer: Option<Box<dyn Write + Send>>,
+}
+
+impl Output {
+    fn reconnects(&self) -> bool {
+        !matches!(self.sink, Sink::Writer(_))
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let writer: &mut dyn Write = match &mut self.sink {
+            Sink::Writer(writer) => writer,
+            Sink::File(path) => {
+                if self.writer.is_none() {
+                    let file = OpenOptions::new().create(true).append(true).open(path)?;
+                    self.writer = Some(Box::new(file));
+                }
+                self.writer.as_mut().unwrap()
+            }
+            Sink::UnixSocket(path) => {
+                if self.writer.is_none() {
+                    self.writer = Some(Box::new(UnixStream::connect(path)?));
+                }
+                self.writer.as_mut().unwrap()
+            }
+        };
+
+        let result = writer
+            .write_all(line.as_bytes())
+            .and_then(|_| writer.flush());
+        if result.is_err() {
+            self.writer = None;
+        }
+        result
+    }
+}
+
+fn notification_line(
+    module: &str,
+    notif_type: NotificationType,
+    notif: &DataTree,
+    timestamp: SystemTime,
+) -> Option<String> {
+    let notif_type = match notif_type {
+        NotificationType::Realtime => "realtime",
+        NotificationType::Replay => "replay",
+        NotificationType::ReplayComplete => "replay-complete",
+        NotificationType::Terminated => "terminated",
+        NotificationType::Modified => "modified",
+        NotificationType::Suspended => "suspended",
+        NotificationType::Resumed => "resumed",
+        NotificationType::StopTime => "stop-time",
+        _ => "unknown",
+    };
+    let notification = if notif.reference().is_some() {
+        notif
+            .print_string(
+                DataFormat::JSON,
+                DataPrinterFlags::WITH_SIBLINGS | DataPrinterFlags::SHRINK,
+            )
+            .ok()?
+    } else {
+        "null".to_owned()
+    };
+    let time = timestamp
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Some(format!(
+        "{{\"module\":\"{}\",\"type\":\"{}\",\"time\":{}.{:09},\"notification\":{}}}\n",
+        module,
+        notif_type,
+        time.as_secs(),
+        time.subsec_nanos(),
+        notification
+    ))
+}
@@ -0,0 +1,110 @@
+//! Datastore contents as `serde_json::Value`.
+//!
+//! libyang prints data as RFC 7951 JSON, which encodes 64-bit integers and
+//! decimal64 values as strings so they survive JavaScript. The printed JSON is
+//! fixed up from the data tree so that these values are numbers as well.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde_json::{Map, Number, Value};
+
+use crate::yang::data::{Data, DataFormat, DataNodeRef, DataPrinterFlags};
+use crate::yang::ffi::LY_DATA_TYPE;
+use crate::yang::schema::{DataValue, SchemaNodeKind};
+use crate::{ffi, term_basetype, Error, GetOptions, Result, Session};
+
+impl Session<'_> {
+    /// Get the data selected by an XPath as a JSON value.
+    ///
+    /// The value is an object of the top-level nodes, named as in RFC 7951,
+    /// and is empty if no data are selected. Unlike RFC 7951, 64-bit integers
+    /// and decimal64 values are numbers, the latter converted to `f64`.
+    pub fn get_data_value(&self, xpath: &str) -> Result<Value> {
+        let data = match self.get_data(xpath, None, Duration::ZERO, GetOptions::default()) {
+            Ok(data) => data,
+            Err(Error {
+                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                ..
+            }) => return Ok(Value::Object(Map::new())),
+            Err(e) => return Err(e),
+        };
+        let tree = data.tree();
+        let json = tree
+            .print_string(DataFormat::JSON, DataPrinterFlags::WITH_SIBLINGS)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?;
+        let mut value: Value = serde_json::from_str(&json)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+
+        if let (Some(first), Value::Object(object)) = (tree.reference(), &mut value) {
+            fix_numbers(first.inclusive_siblings(), object, None);
+        }
+        Ok(value)
+    }
+}
+
+/// Replace the values of 64-bit integer and decimal64 nodes in an object with
+/// numbers.
+///
+/// Members are named after the nodes, prefixed with the module name if it
+/// differs from the one of the parent. Lists and leaf-lists are arrays with
+/// their instances in the order of the data tree.
+fn fix_numbers<'a>(
+    nodes: impl Iterator<Item = DataNodeRef<'a>>,
+    object: &mut Map<String, Value>,
+    parent_module: Option<&str>,
+) {
+    let mut indexes: HashMap<String, usize> = HashMap::new();
+    for node in nodes {
+        let schema = node.schema();
+        let module = schema.module();
+        let name = if parent_module == Some(module.name()) {
+            schema.name().to_owned()
+        } else {
+            format!("{}:{}", module.name(), schema.name())
+        };
+        let kind = schema.kind();
+
+        let Some(member) = object.get_mut(&name) else {
+            continue;
+        };
+        let member = match kind {
+            SchemaNodeKind::List | SchemaNodeKind::LeafList => {
+                let index = indexes.entry(name).or_insert(0);
+                let instance = member.get_mut(*index);
+                *index += 1;
+                match instance {
+                    Some(instance) => instance,
+                    None => continue,
+                }
+            }
+            _ => member,
+        };
+
+        match kind {
+            SchemaNodeKind::Leaf | SchemaNodeKind::LeafList => {
+                if let Some(number) = number(&node) {
+                    *member = Value::Number(number);
+                }
+            }
+            SchemaNodeKind::AnyData => {}
+            _ => {
+                if let Value::Object(object) = member {
+                    fix_numbers(node.children(), object, Some(module.name()));
+                }
+            }
+        }
+    }
+}
+
+/// The value of a node as a number, if RFC 7951 encodes it as a string.
+fn number(node: &DataNodeRef) -> Option<Number> {
+    match node.value()? {
+        DataValue::Int64(v) => Some(v.into()),
+        DataValue::Uint64(v) => Some(v.into()),
+        _ if term_basetype(node) == LY_DATA_TYPE::LY_TYPE_DEC64 => {
+            Number::from_f64(node.value_canonical()?.parse().ok()?)
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,48 @@
+//! Tracking of resources that are never released.
+//!
+//! With the `debug-leaks` feature, boxed subscription callbacks,
+//! `ManagedData` handles and `AcquiredContext` guards are tracked from their
+//! creation, with a backtrace, until they are released. This is meant for
+//! debugging only, capturing backtraces is slow.
+
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+static LIVE: Mutex<BTreeMap<(&'static str, usize), Backtrace>> = Mutex::new(BTreeMap::new());
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// Number of tracked resources not released yet.
+pub fn outstanding() -> usize {
+    LIVE.lock().unwrap().len()
+}
+
+/// Describe every tracked resource not released yet, with the backtrace of
+/// its creation.
+pub fn report() -> String {
+    let live = LIVE.lock().unwrap();
+    let mut out = String::new();
+    for ((kind, key), backtrace) in live.iter() {
+        let _ = writeln!(out, "{} {:#x} created at:\n{}", kind, key, backtrace);
+    }
+    out
+}
+
+pub(crate) fn track(kind: &'static str, key: usize) {
+    LIVE.lock()
+        .unwrap()
+        .insert((kind, key), Backtrace::force_capture());
+}
+
+/// Track a resource without an address of its own, returning its key.
+pub(crate) fn track_new(kind: &'static str) -> usize {
+    let key = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    track(kind, key);
+    key
+}
+
+pub(crate) fn untrack(kind: &'static str, key: usize) {
+    LIVE.lock().unwrap().remove(&(kind, key));
+}
@@ -1,15 +1,21 @@
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
 use std::ffi::CStr;
 use std::ffi::CString;
 use std::fmt;
 use std::marker::PhantomData;
 use std::mem::ManuallyDrop;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::num::NonZero;
-use std::ops::Deref;
+use std::ops::{Deref, DerefMut};
 use std::os::raw::{c_char, c_int, c_void};
 use std::ptr;
-use std::sync::Mutex;
-use std::time::{Duration, SystemTime};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 #[cfg(feature = "yang2")]
 pub use yang2 as yang;
@@ -19,27 +25,116 @@ pub use yang3 as yang;
 use bitflags::bitflags;
 pub use sysrepo_sys as ffi;
 use yang::context::Context;
-use yang::data::DataTree;
+use yang::data::{Data, DataFormat, DataPrinterFlags, DataTree, DataValidationFlags};
+#[cfg(feature = "yang3")]
+use yang::data::{DataDiffFlags, DataDiffOp, DataNodeRef, DataOperation, DataParserFlags};
 use yang::ffi::timespec;
+#[cfg(feature = "yang3")]
+use yang::schema::DataValueType;
+use yang::schema::{DataValue, SchemaNode, SchemaNodeKind};
 use yang::utils::Binding;
 
+#[cfg(feature = "async-std")]
+pub mod async_std;
+pub mod backup;
+pub mod checkpoint;
+pub mod diff;
+pub mod edit;
+#[cfg(feature = "forward")]
+pub mod forward;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "debug-leaks")]
+pub mod leaks;
+#[cfg(feature = "mirror")]
+pub mod mirror;
+pub mod oper;
+pub mod owner;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod reload;
+#[cfg(feature = "async")]
+pub mod runtime;
+#[cfg(feature = "smol")]
+pub mod smol;
+pub mod spec;
+#[cfg(feature = "stats")]
+pub mod stats;
+#[cfg(feature = "systemd")]
+pub mod systemd;
+pub mod time;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+pub mod validate;
+#[cfg(feature = "yang3")]
+pub mod yang_patch;
+
+/// Track a resource if the `debug-leaks` feature is enabled.
+macro_rules! leaks {
+    ($($track:tt)*) => {
+        #[cfg(feature = "debug-leaks")]
+        crate::leaks::$($track)*;
+    };
+}
+
+/// Record a statistic if the `stats` feature is enabled.
+macro_rules! stat {
+    ($($record:tt)*) => {
+        #[cfg(feature = "stats")]
+        crate::stats::$($record)*;
+    };
+}
+
 /// A convenience wrapper around `Result` for `sysrepo_rs::Error`.
 pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Error {
     pub errcode: ffi::sr_error_t::Type,
+    message: Option<String>,
+}
+
+impl Error {
+    /// An error with a sysrepo error code.
+    pub fn new(errcode: ffi::sr_error_t::Type) -> Self {
+        Error {
+            errcode,
+            message: None,
+        }
+    }
+
+    /// An error raised by this crate, with what went wrong.
+    pub fn with_message(errcode: ffi::sr_error_t::Type, message: impl Into<String>) -> Self {
+        Error {
+            errcode,
+            message: Some(message.into()),
+        }
+    }
+
+    /// What went wrong, for errors raised by this crate rather than sysrepo.
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
 }
 
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let msg = unsafe { CStr::from_ptr(ffi::sr_strerror(self.errcode as c_int)) };
-        write!(f, "{}", String::from_utf8_lossy(msg.to_bytes()))
+        write!(f, "{}", String::from_utf8_lossy(msg.to_bytes()))?;
+        if let Some(message) = &self.message {
+            write!(f, ": {}", message)?;
+        }
+        Ok(())
     }
 }
 
 impl std::error::Error for Error {}
 
+/// A system error, keeping what went wrong.
+pub(crate) fn sys_err(e: std::io::Error) -> Error {
+    Error::with_message(ffi::sr_error_t::SR_ERR_SYS, e.to_string())
+}
+
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub enum LogLevel {
     None = ffi::sr_log_level_t::SR_LL_NONE as isize,
@@ -79,7 +174,7 @@ impl Default for ConnectionFlags {
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
 pub enum Datastore {
     Startup = ffi::sr_datastore_t::SR_DS_STARTUP as isize,
     Running = ffi::sr_datastore_t::SR_DS_RUNNING as isize,
@@ -144,9 +239,83 @@ impl Default for EditOptions {
     }
 }
 
+/// What applying changes changed, see `Session::apply_changes_summary`.
+///
+/// Nodes created or deleted with their descendants are counted once.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ChangeSummary {
+    pub created: usize,
+    pub deleted: usize,
+    /// Leaves with a new value and moved instances of user-ordered lists.
+    pub modified: usize,
+    /// Names of the modules with changes, sorted.
+    pub modules: Vec<String>,
+}
+
+impl ChangeSummary {
+    /// Whether nothing changed.
+    pub fn is_empty(&self) -> bool {
+        self.created == 0 && self.deleted == 0 && self.modified == 0
+    }
+}
+
+impl fmt::Display for ChangeSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return f.write_str("no changes");
+        }
+        write!(
+            f,
+            "{} created, {} deleted, {} modified in {}",
+            self.created,
+            self.deleted,
+            self.modified,
+            self.modules.join(", ")
+        )
+    }
+}
+
+/// The operation of the nodes of a batch edit without an operation attribute,
+/// see `Session::edit_batch`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DefaultOperation {
+    #[default]
+    Merge,
+    Replace,
+    /// Nodes without an operation are only the path to the changed ones.
+    None,
+}
+
+impl DefaultOperation {
+    fn as_str(self) -> &'static str {
+        match self {
+            DefaultOperation::Merge => "merge",
+            DefaultOperation::Replace => "replace",
+            DefaultOperation::None => "none",
+        }
+    }
+}
+
+/// Where to move an instance of a user-ordered list or leaf-list, see
+/// `Session::move_item`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MovePosition {
+    First,
+    Last,
+    /// Before another instance, given by the key predicates of a list
+    /// instance, such as `[name='a']`, or the value of a leaf-list instance.
+    Before {
+        key: String,
+    },
+    /// After another instance, given like for `MovePosition::Before`.
+    After {
+        key: String,
+    },
+}
+
 bitflags! {
     #[repr(transparent)]
-    #[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
+    #[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
     pub struct SubscriptionOptions: ffi::sr_subscr_flag_t::Type {
         const NO_THREAD = ffi::sr_subscr_flag_t::SR_SUBSCR_NO_THREAD;
         const PASSIVE = ffi::sr_subscr_flag_t::SR_SUBSCR_PASSIVE;
@@ -159,9 +328,16 @@ bitflags! {
         // Available with sysrepo >= 2.2.12
         const OPER_POLL_DIFF = ffi::sr_subscr_flag_t::SR_SUBSCR_OPER_POLL_DIFF;
         // Available with sysrepo >= 2.2.150
+        // Filters out events from sessions with the same originator name as
+        // the subscribing session, see `Session::set_originator`.
         const FILTER_ORIG = ffi::sr_subscr_flag_t::SR_SUBSCR_FILTER_ORIG;
         // Available with sysrepo >= 3.3.10
         const CHANGE_ALL_MODULES = ffi::sr_subscr_flag_t::SR_SUBSCR_CHANGE_ALL_MODULES;
+        // Handled by this crate and never passed to sysrepo.
+        // Frees the callback of a notification subscription with a stop time
+        // once the stop time is reached, which is then the last event it is
+        // called for.
+        const REMOVE_AT_STOP_TIME = 1 << 31;
     }
 }
 
@@ -171,28 +347,130 @@ impl Default for SubscriptionOptions {
     }
 }
 
-#[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
-pub enum Event {
-    Update = ffi::sr_event_t::SR_EV_UPDATE as isize,
-    Change = ffi::sr_event_t::SR_EV_CHANGE as isize,
-    Done = ffi::sr_event_t::SR_EV_DONE as isize,
-    Abort = ffi::sr_event_t::SR_EV_ABORT as isize,
-    Enabled = ffi::sr_event_t::SR_EV_ENABLED as isize,
-    Rpc = ffi::sr_event_t::SR_EV_RPC as isize,
+impl SubscriptionOptions {
+    /// Check that the options are meaningful for a kind of subscription.
+    ///
+    /// Subscribing validates the options, failing with `SR_ERR_INVAL_ARG`.
+    pub fn validate(&self, kind: SubscriptionKind) -> std::result::Result<(), &'static str> {
+        let common = SubscriptionOptions::NO_THREAD
+            | SubscriptionOptions::PASSIVE
+            | SubscriptionOptions::THREAD_SUSPEND;
+        let allowed = match kind {
+            SubscriptionKind::ModuleChange => {
+                common
+                    | SubscriptionOptions::DONE_ONLY
+                    | SubscriptionOptions::ENABLED
+                    | SubscriptionOptions::UPDATE
+                    | SubscriptionOptions::FILTER_ORIG
+                    | SubscriptionOptions::CHANGE_ALL_MODULES
+            }
+            SubscriptionKind::OperationalGet => common | SubscriptionOptions::OPER_MERGE,
+            SubscriptionKind::OperationalPoll => common | SubscriptionOptions::OPER_POLL_DIFF,
+            SubscriptionKind::Rpc => common,
+            SubscriptionKind::Notification => common | SubscriptionOptions::REMOVE_AT_STOP_TIME,
+        };
+
+        if let Some(invalid) = self.clone().difference(allowed).iter().next() {
+            return Err(match invalid {
+                SubscriptionOptions::DONE_ONLY => {
+                    "DONE_ONLY is only valid for change subscriptions"
+                }
+                SubscriptionOptions::ENABLED => "ENABLED is only valid for change subscriptions",
+                SubscriptionOptions::UPDATE => "UPDATE is only valid for change subscriptions",
+                SubscriptionOptions::FILTER_ORIG => {
+                    "FILTER_ORIG is only valid for change subscriptions"
+                }
+                SubscriptionOptions::CHANGE_ALL_MODULES => {
+                    "CHANGE_ALL_MODULES is only valid for change subscriptions"
+                }
+                SubscriptionOptions::OPER_MERGE => {
+                    "OPER_MERGE is only valid for operational get subscriptions"
+                }
+                SubscriptionOptions::OPER_POLL_DIFF => {
+                    "OPER_POLL_DIFF is only valid for operational poll subscriptions"
+                }
+                SubscriptionOptions::REMOVE_AT_STOP_TIME => {
+                    "REMOVE_AT_STOP_TIME is only valid for notification subscriptions"
+                }
+                _ => "Unknown subscription option",
+            });
+        }
+        if self.contains(SubscriptionOptions::DONE_ONLY | SubscriptionOptions::UPDATE) {
+            return Err("UPDATE events are never delivered with DONE_ONLY");
+        }
+        Ok(())
+    }
 }
 
-impl TryFrom<u32> for Event {
-    type Error = &'static str;
+/// Priority of a module change or RPC subscription.
+///
+/// For the same event, subscriptions with a higher priority are called
+/// before subscriptions with a lower priority. Subscriptions with the same
+/// priority are called in no particular order, or in parallel.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+pub struct Priority(pub u32);
+
+impl From<u32> for Priority {
+    fn from(priority: u32) -> Self {
+        Priority(priority)
+    }
+}
 
-    fn try_from(t: u32) -> std::result::Result<Self, Self::Error> {
+impl From<Priority> for u32 {
+    fn from(priority: Priority) -> Self {
+        priority.0
+    }
+}
+
+/// A boxed module change callback, for `Session::add_module_change_chain`.
+pub type ModuleChangeHandler = Box<dyn FnMut(&ChangeContext) -> Result<()>>;
+
+/// A boxed RPC callback, for `Session::add_rpc_chain`.
+pub type RpcHandler = Box<dyn FnMut(&RpcContext, &mut DataTree) -> Result<()>>;
+
+/// The kinds of subscriptions, each accepting different `SubscriptionOptions`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SubscriptionKind {
+    ModuleChange,
+    OperationalGet,
+    OperationalPoll,
+    Rpc,
+    Notification,
+}
+
+impl SubscriptionKind {
+    /// The kind of the callback running on the current thread, if any.
+    pub fn current() -> Option<Self> {
+        CALLBACK.with(|callback| callback.get().map(|callback| callback.kind))
+    }
+}
+
+/// The event of a change or RPC callback.
+///
+/// Events unknown to this crate, from a newer sysrepo, are passed as
+/// `Event::Other`.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[non_exhaustive]
+pub enum Event {
+    Update,
+    Change,
+    Done,
+    Abort,
+    Enabled,
+    Rpc,
+    Other(ffi::sr_event_t::Type),
+}
+
+impl From<ffi::sr_event_t::Type> for Event {
+    fn from(t: ffi::sr_event_t::Type) -> Self {
         match t {
-            ffi::sr_event_t::SR_EV_UPDATE => Ok(Event::Update),
-            ffi::sr_event_t::SR_EV_CHANGE => Ok(Event::Change),
-            ffi::sr_event_t::SR_EV_DONE => Ok(Event::Done),
-            ffi::sr_event_t::SR_EV_ABORT => Ok(Event::Abort),
-            ffi::sr_event_t::SR_EV_ENABLED => Ok(Event::Enabled),
-            ffi::sr_event_t::SR_EV_RPC => Ok(Event::Rpc),
-            _ => Err("Invalid Event"),
+            ffi::sr_event_t::SR_EV_UPDATE => Event::Update,
+            ffi::sr_event_t::SR_EV_CHANGE => Event::Change,
+            ffi::sr_event_t::SR_EV_DONE => Event::Done,
+            ffi::sr_event_t::SR_EV_ABORT => Event::Abort,
+            ffi::sr_event_t::SR_EV_ENABLED => Event::Enabled,
+            ffi::sr_event_t::SR_EV_RPC => Event::Rpc,
+            _ => Event::Other(t),
         }
     }
 }
@@ -206,40 +484,45 @@ impl fmt::Display for Event {
             Event::Abort => "Abort",
             Event::Enabled => "Enabled",
             Event::Rpc => "RPC",
+            Event::Other(t) => return write!(f, "Unknown({})", t),
         };
         write!(f, "{}", s)
     }
 }
 
+/// The type of a notification callback.
+///
+/// Types unknown to this crate, from a newer sysrepo, are passed as
+/// `NotificationType::Other`.
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
+#[non_exhaustive]
 pub enum NotificationType {
-    Realtime = ffi::sr_ev_notif_type_t::SR_EV_NOTIF_REALTIME as isize,
-    Replay = ffi::sr_ev_notif_type_t::SR_EV_NOTIF_REPLAY as isize,
-    ReplayComplete = ffi::sr_ev_notif_type_t::SR_EV_NOTIF_REPLAY_COMPLETE as isize,
-    Terminated = ffi::sr_ev_notif_type_t::SR_EV_NOTIF_TERMINATED as isize,
-    Modified = ffi::sr_ev_notif_type_t::SR_EV_NOTIF_MODIFIED as isize,
-    Suspended = ffi::sr_ev_notif_type_t::SR_EV_NOTIF_SUSPENDED as isize,
-    Resumed = ffi::sr_ev_notif_type_t::SR_EV_NOTIF_RESUMED as isize,
+    Realtime,
+    Replay,
+    ReplayComplete,
+    Terminated,
+    Modified,
+    Suspended,
+    Resumed,
     // Available with sysrepo >= 2.2.105
-    StopTime = ffi::sr_ev_notif_type_t::SR_EV_NOTIF_STOP_TIME as isize,
+    StopTime,
+    Other(ffi::sr_ev_notif_type_t::Type),
 }
 
-impl TryFrom<ffi::sr_ev_notif_type_t::Type> for NotificationType {
-    type Error = &'static str;
-
-    fn try_from(t: ffi::sr_ev_notif_type_t::Type) -> std::result::Result<Self, Self::Error> {
+impl From<ffi::sr_ev_notif_type_t::Type> for NotificationType {
+    fn from(t: ffi::sr_ev_notif_type_t::Type) -> Self {
         match t {
-            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_REALTIME => Ok(NotificationType::Realtime),
-            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_REPLAY => Ok(NotificationType::Replay),
+            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_REALTIME => NotificationType::Realtime,
+            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_REPLAY => NotificationType::Replay,
             ffi::sr_ev_notif_type_t::SR_EV_NOTIF_REPLAY_COMPLETE => {
-                Ok(NotificationType::ReplayComplete)
+                NotificationType::ReplayComplete
             }
-            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_TERMINATED => Ok(NotificationType::Terminated),
-            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_MODIFIED => Ok(NotificationType::Modified),
-            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_SUSPENDED => Ok(NotificationType::Suspended),
-            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_RESUMED => Ok(NotificationType::Resumed),
-            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_STOP_TIME => Ok(NotificationType::StopTime),
-            _ => Err("Invalid NotificationType"),
+            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_TERMINATED => NotificationType::Terminated,
+            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_MODIFIED => NotificationType::Modified,
+            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_SUSPENDED => NotificationType::Suspended,
+            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_RESUMED => NotificationType::Resumed,
+            ffi::sr_ev_notif_type_t::SR_EV_NOTIF_STOP_TIME => NotificationType::StopTime,
+            _ => NotificationType::Other(t),
         }
     }
 }
@@ -300,6 +583,54 @@ pub fn set_log_callback(callback: Option<fn(LogLevel, &str)>) {
     }
 }
 
+/// Whether the process runs as the sysrepo superuser, which is allowed to
+/// access all modules regardless of their permissions.
+pub fn is_superuser() -> bool {
+    unsafe { libc::geteuid() == ffi::sr_get_su_uid() }
+}
+
+/// The NACM recovery user, whose sessions are not subject to NACM.
+#[cfg(feature = "netconf-acm")]
+pub fn nacm_recovery_user() -> String {
+    let user = unsafe { ffi::sr_nacm_get_recovery_user() };
+    unsafe { CStr::from_ptr(user) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Whether the process runs as the NACM recovery user.
+#[cfg(feature = "netconf-acm")]
+pub fn is_nacm_recovery_user() -> bool {
+    process_user().is_some_and(|user| user == nacm_recovery_user())
+}
+
+/// The name of the effective user of the process.
+#[cfg(feature = "netconf-acm")]
+fn process_user() -> Option<String> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = ptr::null_mut();
+    let mut buf = vec![0 as c_char; 1024];
+    loop {
+        let rc = unsafe {
+            libc::getpwuid_r(
+                libc::geteuid(),
+                &mut pwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match rc {
+            0 if result.is_null() => return None,
+            0 => break,
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            _ => return None,
+        }
+    }
+    let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
 /// Do not use *nix's fork(2) after creating a connection.
 pub struct Connection {
     conn: *mut ffi::sr_conn_ctx_t,
@@ -308,11 +639,12 @@ pub struct Connection {
 impl Connection {
     pub fn new(flags: ConnectionFlags) -> Result<Self> {
         let mut conn = ptr::null_mut();
+        stat!(ffi_call("sr_connect"));
         let rc = unsafe { ffi::sr_connect(flags.bits(), &mut conn) };
 
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
+            Err(Error::new(rc))
         } else {
             debug_assert!(!conn.is_null());
             Ok(Self { conn })
@@ -335,11 +667,12 @@ impl Connection {
 
     pub fn start_session(&self, ds: Datastore) -> Result<Session<'_>> {
         let mut sess = ptr::null_mut();
+        stat!(ffi_call("sr_session_start"));
         let rc = unsafe { ffi::sr_session_start(self.conn, ds as u32, &mut sess) };
 
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
+            Err(Error::new(rc))
         } else {
             debug_assert!(!sess.is_null());
             Ok(unsafe { Session::from_raw(self, sess) })
@@ -348,14 +681,307 @@ impl Connection {
 
     pub fn get_context(&self) -> Option<AcquiredContext<'_>> {
         let ctx = unsafe {
+            stat!(context_acquired());
             let ctx = ffi::sr_acquire_context(self.conn) as *mut _;
             Context::from_raw_opt(&(), ctx)
         };
         ctx.map(|ctx| AcquiredContext {
             conn: self,
             ctx: ManuallyDrop::new(ctx),
+            #[cfg(feature = "debug-leaks")]
+            leak_id: leaks::track_new("AcquiredContext"),
+        })
+    }
+
+    /// Wrap a data tree created elsewhere into `ManagedData`.
+    ///
+    /// The tree must have been created with the context of this connection,
+    /// for example parsed against the context from `get_context`. The
+    /// returned data holds its own context lock, so the acquired context may
+    /// be dropped afterwards.
+    pub fn acquire_data(&self, tree: DataTree<'_>) -> Result<ManagedData<'_>> {
+        let mut data = ptr::null_mut();
+        let tree = tree.into_raw();
+        let rc = unsafe {
+            // Take a context lock for the data to release.
+            stat!(context_acquired());
+            ffi::sr_acquire_context(self.conn);
+            ffi::sr_acquire_data(self.conn, tree, &mut data)
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            // The tree is only owned by the data once it is created.
+            unsafe {
+                yang::ffi::lyd_free_all(tree);
+                ffi::sr_release_context(self.conn);
+            }
+            Err(Error::new(rc))
+        } else {
+            Ok(unsafe { ManagedData::from_raw(self, data) })
+        }
+    }
+
+    /// Get the internal sysrepo module data, `/sysrepo:sysrepo-modules`.
+    pub fn module_info(&self) -> Result<ManagedData<'_>> {
+        let mut data = ptr::null_mut();
+        let rc = unsafe { ffi::sr_get_module_info(self.conn, &mut data) };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(unsafe { ManagedData::from_raw(self, data) })
+        }
+    }
+
+    /// Get the state of all modules installed in sysrepo.
+    ///
+    /// Subscriptions are not part of this, they are reported in the
+    /// `sysrepo-monitoring` operational data.
+    pub fn modules(&self) -> Result<Vec<ModuleInfo>> {
+        let data = self.module_info()?;
+        let tree = data.tree();
+        let ly_err = |_| Error::new(ffi::sr_error_t::SR_ERR_LY);
+
+        tree.find_xpath("/sysrepo:sysrepo-modules/module")
+            .map_err(ly_err)?
+            .map(|module| {
+                let value = |xpath: &str| {
+                    Ok(module
+                        .find_xpath(xpath)
+                        .map_err(ly_err)?
+                        .filter_map(|node| node.value_canonical())
+                        .collect::<Vec<_>>())
+                };
+                Ok(ModuleInfo {
+                    name: value("name")?.pop().unwrap_or_default(),
+                    revision: value("revision")?.pop(),
+                    enabled_features: value("enabled-feature")?,
+                    replay_support: module
+                        .find_xpath("replay-support")
+                        .map_err(ly_err)?
+                        .next()
+                        .is_some(),
+                })
+            })
+            .collect()
+    }
+
+    /// Enable or disable several module features.
+    ///
+    /// Each change is a `(module, feature, enabled)` tuple. Sysrepo has no
+    /// call to change several features at once, so every changed feature
+    /// still updates the context, but features already in the requested state
+    /// are skipped. Stops at the first failed change.
+    pub fn set_module_features(&self, changes: &[(&str, &str, bool)]) -> Result<()> {
+        let modules = self.modules()?;
+        for &(module, feature, enabled) in changes {
+            let current = modules
+                .iter()
+                .find(|info| info.name == module)
+                .map(|info| info.enabled_features.iter().any(|f| f == feature));
+            if current == Some(enabled) {
+                continue;
+            }
+
+            let module = str_to_cstring(module)?;
+            let feature = str_to_cstring(feature)?;
+            let rc = unsafe {
+                if enabled {
+                    ffi::sr_enable_module_feature(self.conn, module.as_ptr(), feature.as_ptr())
+                } else {
+                    ffi::sr_disable_module_feature(self.conn, module.as_ptr(), feature.as_ptr())
+                }
+            };
+            let rc = rc as ffi::sr_error_t::Type;
+            if rc != ffi::sr_error_t::SR_ERR_OK {
+                return Err(Error::new(rc));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether the current user may read and write a module in a
+    /// datastore.
+    pub fn check_module_ds_access(
+        &self,
+        module_name: &str,
+        datastore: Datastore,
+    ) -> Result<ModuleAccess> {
+        let module_name = str_to_cstring(module_name)?;
+        let mut read = 0;
+        let mut write = 0;
+        let rc = unsafe {
+            ffi::sr_check_module_ds_access(
+                self.conn,
+                module_name.as_ptr(),
+                datastore as c_int,
+                &mut read,
+                &mut write,
+            )
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(ModuleAccess {
+                read: read != 0,
+                write: write != 0,
+            })
+        }
+    }
+
+    /// Get the owner, group and permissions of a module datastore.
+    pub fn get_module_ds_access(
+        &self,
+        module_name: &str,
+        datastore: Datastore,
+    ) -> Result<ModuleDsAccess> {
+        let module_name = str_to_cstring(module_name)?;
+        let mut owner = ptr::null_mut();
+        let mut group = ptr::null_mut();
+        let mut perm = 0;
+        let rc = unsafe {
+            ffi::sr_get_module_ds_access(
+                self.conn,
+                module_name.as_ptr(),
+                datastore as c_int,
+                &mut owner,
+                &mut group,
+                &mut perm,
+            )
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            return Err(Error::new(rc));
+        }
+        let take = |s: *mut c_char| {
+            if s.is_null() {
+                return String::new();
+            }
+            let owned = unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned();
+            unsafe { libc::free(s as *mut _) };
+            owned
+        };
+        Ok(ModuleDsAccess {
+            owner: take(owner),
+            group: take(group),
+            permissions: Permissions::from(perm),
+        })
+    }
+
+    /// Change the owner, group or permissions of a module datastore.
+    ///
+    /// Whatever is `None` is left unchanged.
+    pub fn set_module_ds_access(
+        &self,
+        module_name: &str,
+        datastore: Datastore,
+        owner: Option<&str>,
+        group: Option<&str>,
+        permissions: Option<Permissions>,
+    ) -> Result<()> {
+        let module_name = str_to_cstring(module_name)?;
+        let owner = owner.map(str_to_cstring).transpose()?;
+        let group = group.map(str_to_cstring).transpose()?;
+        let rc = unsafe {
+            ffi::sr_set_module_ds_access(
+                self.conn,
+                module_name.as_ptr(),
+                datastore as c_int,
+                owner.as_deref().map_or(ptr::null(), |o| o.as_ptr()),
+                group.as_deref().map_or(ptr::null(), |g| g.as_ptr()),
+                permissions.map_or(0, libc::mode_t::from),
+            )
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Cheaply check that the connection and sysrepo are usable.
+    ///
+    /// Acquires and releases the context and reads the content id, so it is
+    /// suited for readiness probes.
+    pub fn ping(&self) -> Health {
+        if self.get_context().is_none() {
+            return Health::ContextUnavailable;
+        }
+        match unsafe { ffi::sr_get_content_id(self.conn) } {
+            0 => Health::ContentIdUnavailable,
+            content_id => Health::Alive { content_id },
+        }
+    }
+
+    /// Whether `ping` reports the connection as alive.
+    pub fn is_alive(&self) -> bool {
+        matches!(self.ping(), Health::Alive { .. })
+    }
+
+    /// The generation of the context, which changes whenever modules are
+    /// installed, removed or updated.
+    ///
+    /// This is the content id of the YANG library data, 0 if it is not
+    /// available. Caches derived from the context should be refreshed when
+    /// it changes.
+    pub fn context_generation(&self) -> u32 {
+        unsafe { ffi::sr_get_content_id(self.conn) }
+    }
+
+    /// Get whether replay is enabled for a module and the time of its earliest
+    /// stored notification.
+    pub fn replay_support(&self, mod_name: &str) -> Result<ReplaySupport> {
+        let mod_name = str_to_cstring(mod_name)?;
+        let mut earliest = timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+        let mut enabled = 0;
+
+        let rc = unsafe {
+            ffi::sr_get_module_replay_support(
+                self.conn,
+                mod_name.as_ptr(),
+                &mut earliest,
+                &mut enabled,
+            )
+        };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            return Err(Error::new(rc));
+        }
+
+        // A zero timestamp means there are no stored notifications.
+        let earliest_notification =
+            (earliest.tv_sec != 0 || earliest.tv_nsec != 0).then(|| time::from_timespec(&earliest));
+        Ok(ReplaySupport {
+            enabled: enabled != 0,
+            earliest_notification,
         })
     }
+
+    /// Get the earliest time notifications of a module can be replayed from.
+    ///
+    /// Returns `None` if replay is disabled or no notifications are stored.
+    pub fn earliest_replay_time(&self, mod_name: &str) -> Result<Option<SystemTime>> {
+        let support = self.replay_support(mod_name)?;
+        Ok(support.earliest_notification.filter(|_| support.enabled))
+    }
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("cid", &unsafe { ffi::sr_get_cid(self.conn) })
+            .finish()
+    }
 }
 
 impl Drop for Connection {
@@ -375,10 +1001,92 @@ impl Drop for Connection {
 unsafe impl Send for Connection {}
 unsafe impl Sync for Connection {}
 
+/// State of a module installed in sysrepo.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ModuleInfo {
+    pub name: String,
+    pub revision: Option<String>,
+    pub enabled_features: Vec<String>,
+    /// Whether notification replay is enabled for the module.
+    pub replay_support: bool,
+}
+
+/// Access of the current user to a module datastore.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ModuleAccess {
+    pub read: bool,
+    pub write: bool,
+}
+
+/// Permissions of a module datastore, like file permissions without the
+/// execute bits, which sysrepo does not allow.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Permissions {
+    pub owner: ModuleAccess,
+    pub group: ModuleAccess,
+    pub other: ModuleAccess,
+}
+
+impl From<libc::mode_t> for Permissions {
+    fn from(mode: libc::mode_t) -> Self {
+        let access = |shift: u32| ModuleAccess {
+            read: mode & (0o4 << shift) != 0,
+            write: mode & (0o2 << shift) != 0,
+        };
+        Permissions {
+            owner: access(6),
+            group: access(3),
+            other: access(0),
+        }
+    }
+}
+
+impl From<Permissions> for libc::mode_t {
+    fn from(permissions: Permissions) -> Self {
+        let bits = |access: ModuleAccess, shift: u32| {
+            ((access.read as libc::mode_t) << (shift + 2))
+                | ((access.write as libc::mode_t) << (shift + 1))
+        };
+        bits(permissions.owner, 6) | bits(permissions.group, 3) | bits(permissions.other, 0)
+    }
+}
+
+/// Owner, group and permissions of a module datastore.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ModuleDsAccess {
+    pub owner: String,
+    pub group: String,
+    pub permissions: Permissions,
+}
+
+/// Health status of a connection, as reported by `Connection::ping`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Health {
+    /// The connection is usable.
+    Alive {
+        /// Content id of the current YANG library data.
+        content_id: u32,
+    },
+    /// The context could not be acquired.
+    ContextUnavailable,
+    /// The content id could not be read.
+    ContentIdUnavailable,
+}
+
+/// Notification replay state of a module.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ReplaySupport {
+    pub enabled: bool,
+    /// Time of the earliest stored notification, if any.
+    pub earliest_notification: Option<SystemTime>,
+}
+
 /// A wrapper around `Context` to ensure it is released back to sysrepo on drop.
 pub struct AcquiredContext<'a> {
     conn: &'a Connection,
     ctx: ManuallyDrop<Context>,
+    #[cfg(feature = "debug-leaks")]
+    leak_id: usize,
 }
 
 impl Deref for AcquiredContext<'_> {
@@ -391,62 +1099,441 @@ impl Deref for AcquiredContext<'_> {
 
 impl Drop for AcquiredContext<'_> {
     fn drop(&mut self) {
+        leaks!(untrack("AcquiredContext", self.leak_id));
         unsafe {
             ffi::sr_release_context(self.conn.conn);
         }
     }
 }
 
-pub struct Session<'a> {
+/// Calls a hook whenever the context of a connection changes.
+///
+/// sysrepo does not announce context changes, so the generation of the
+/// context is checked every time the watcher is polled.
+pub struct ContextWatcher<'a, F> {
     conn: &'a Connection,
-    sess: *mut ffi::sr_session_ctx_t,
+    generation: u32,
+    hook: F,
 }
 
-impl<'a> Session<'a> {
-    pub unsafe fn from_raw(conn: &'a Connection, sess: *mut ffi::sr_session_ctx_t) -> Self {
-        Self { conn, sess }
+impl<'a, F> ContextWatcher<'a, F>
+where
+    F: FnMut(&'a Connection),
+{
+    /// Watch the context of a connection, starting from its current
+    /// generation.
+    pub fn new(conn: &'a Connection, hook: F) -> Self {
+        ContextWatcher {
+            conn,
+            generation: conn.context_generation(),
+            hook,
+        }
     }
 
-    pub fn into_raw(self) -> *mut ffi::sr_session_ctx_t {
-        self.sess
+    /// The generation of the context as of the last poll.
+    pub fn generation(&self) -> u32 {
+        self.generation
     }
 
-    pub fn datastore(&self) -> Datastore {
-        Datastore::try_from(unsafe { ffi::sr_session_get_ds(self.sess) })
-            .expect("datastore from sr_session_get_ds should match a value from sr_datastore_t")
+    /// Run the hook if the context changed since the last poll, returning
+    /// whether it ran.
+    pub fn poll(&mut self) -> bool {
+        let generation = self.conn.context_generation();
+        if generation == self.generation {
+            return false;
+        }
+        self.generation = generation;
+        (self.hook)(self.conn);
+        true
     }
+}
 
-    pub fn switch_datastore(&mut self, datastore: Datastore) -> Result<()> {
-        let rc =
-            unsafe { ffi::sr_session_switch_ds(self.sess, datastore as ffi::sr_datastore_t::Type) };
-        let rc = rc as ffi::sr_error_t::Type;
-        if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
-        } else {
-            Ok(())
-        }
+/// A schema node that holds the context it belongs to acquired.
+pub struct AcquiredSchemaNode<'a> {
+    ctx: AcquiredContext<'a>,
+    node: *mut yang::ffi::lysc_node,
+}
+
+impl AcquiredSchemaNode<'_> {
+    pub fn context(&self) -> &Context {
+        &self.ctx
     }
 
-    pub fn get_context(&self) -> Option<AcquiredContext<'a>> {
-        self.conn.get_context()
+    pub fn node(&self) -> SchemaNode<'_> {
+        unsafe { SchemaNode::from_raw(&self.ctx, self.node) }
     }
+}
 
-    /// Get a data tree for a given XPath.
-    ///
-    /// The timeout is rounded to the nearest millisecond.
-    pub fn get_data(
-        &self,
-        xpath: &str,
-        max_depth: Option<NonZero<u32>>,
-        timeout: Duration,
+/// The originator of the events caused by a session.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Originator {
+    /// Name of the originator, such as `netopeer2`.
+    pub name: String,
+    /// Originator-specific data chunks, whose meaning is defined by the
+    /// originator.
+    pub data: Vec<Vec<u8>>,
+}
+
+/// The notifications received by a notification subscription.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReplayWindow {
+    /// Only the notifications sent after subscribing.
+    #[default]
+    LiveOnly,
+    /// The stored notifications since the start time, then the live ones.
+    ReplayFrom(SystemTime),
+    /// The stored notifications since the start time, then the live ones
+    /// until the stop time.
+    ReplayBetween { start: SystemTime, stop: SystemTime },
+    /// The live notifications until the stop time.
+    UntilStop(SystemTime),
+}
+
+impl ReplayWindow {
+    /// The start and stop times for sysrepo, failing if the stop time is
+    /// before the start time or a time is not representable.
+    fn times(&self) -> Result<(Option<timespec>, Option<timespec>)> {
+        let inval = || Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+        let convert = |t: SystemTime| time::checked_to_timespec(t).ok_or_else(inval);
+        match *self {
+            ReplayWindow::LiveOnly => Ok((None, None)),
+            ReplayWindow::ReplayFrom(start) => Ok((Some(convert(start)?), None)),
+            ReplayWindow::ReplayBetween { start, stop } => {
+                if stop < start {
+                    return Err(inval());
+                }
+                Ok((Some(convert(start)?), Some(convert(stop)?)))
+            }
+            ReplayWindow::UntilStop(stop) => Ok((None, Some(convert(stop)?))),
+        }
+    }
+}
+
+/// The callback of a notification subscription, as passed to sysrepo.
+struct NotifCallback<F> {
+    callback: F,
+    /// Whether the callback is freed once the stop time is reached, see
+    /// `SubscriptionOptions::REMOVE_AT_STOP_TIME`.
+    remove_at_stop: bool,
+}
+
+/// A handle to wait for the end of a notification subscription.
+pub struct NotificationCompletion {
+    rx: mpsc::Receiver<NotificationType>,
+}
+
+impl NotificationCompletion {
+    /// Wait until the subscription ends.
+    ///
+    /// Returns `NotificationType::StopTime` if the stop time was reached, or
+    /// `NotificationType::Terminated` if the subscription was terminated
+    /// otherwise.
+    pub fn wait(&self) -> NotificationType {
+        self.rx.recv().unwrap_or(NotificationType::Terminated)
+    }
+
+    /// Wait until the subscription ends or the timeout elapses.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<NotificationType> {
+        match self.rx.recv_timeout(timeout) {
+            Ok(notif_type) => Some(notif_type),
+            Err(mpsc::RecvTimeoutError::Timeout) => None,
+            Err(mpsc::RecvTimeoutError::Disconnected) => Some(NotificationType::Terminated),
+        }
+    }
+
+    /// Check whether the subscription has ended, without waiting.
+    pub fn try_wait(&self) -> Option<NotificationType> {
+        match self.rx.try_recv() {
+            Ok(notif_type) => Some(notif_type),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(NotificationType::Terminated),
+        }
+    }
+}
+
+/// A sysrepo session.
+///
+/// Methods that edit the pending changes, apply them or otherwise change the
+/// session state take `&mut self`; reading data only needs `&self`.
+///
+/// Methods given a zero timeout use the default timeouts of the session, see
+/// `Session::set_timeout` and `Session::set_apply_timeout`. Options given to a
+/// method are used as they are, while the `_default_options` variants and the
+/// methods without options use the default options of the session, see
+/// `Session::set_get_options` and `Session::set_edit_options`.
+///
+/// Inside module change and operational get callbacks, the event session is
+/// tied to the event being processed, so applying changes, copying or
+/// replacing configuration and locking through it fail with
+/// `SR_ERR_UNSUPPORTED` and a message naming the module of the event. Other
+/// sessions may be used to write as usual.
+pub struct Session<'a> {
+    conn: &'a Connection,
+    sess: *mut ffi::sr_session_ctx_t,
+    timeout: Duration,
+    apply_timeout: Duration,
+    get_options: GetOptions,
+    edit_options: EditOptions,
+}
+
+impl<'a> Session<'a> {
+    pub unsafe fn from_raw(conn: &'a Connection, sess: *mut ffi::sr_session_ctx_t) -> Self {
+        Self {
+            conn,
+            sess,
+            timeout: Duration::ZERO,
+            apply_timeout: Duration::ZERO,
+            get_options: GetOptions::empty(),
+            edit_options: EditOptions::empty(),
+        }
+    }
+
+    pub fn into_raw(self) -> *mut ffi::sr_session_ctx_t {
+        self.sess
+    }
+
+    pub fn datastore(&self) -> Datastore {
+        Datastore::try_from(unsafe { ffi::sr_session_get_ds(self.sess) })
+            .expect("datastore from sr_session_get_ds should match a value from sr_datastore_t")
+    }
+
+    pub fn switch_datastore(&mut self, datastore: Datastore) -> Result<()> {
+        let rc =
+            unsafe { ffi::sr_session_switch_ds(self.sess, datastore as ffi::sr_datastore_t::Type) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Run `f` with the session switched to another datastore, switching it
+    /// back afterwards. Unlike a new session, the session keeps its NACM user
+    /// and originator.
+    fn on_datastore<T>(
+        &self,
+        datastore: Datastore,
+        f: impl FnOnce(&Self) -> Result<T>,
+    ) -> Result<T> {
+        let current = self.datastore();
+        if datastore == current {
+            return f(self);
+        }
+        let switch = |datastore: Datastore| {
+            let rc = unsafe {
+                ffi::sr_session_switch_ds(self.sess, datastore as ffi::sr_datastore_t::Type)
+            };
+            let rc = rc as ffi::sr_error_t::Type;
+            if rc != ffi::sr_error_t::SR_ERR_OK {
+                Err(Error::new(rc))
+            } else {
+                Ok(())
+            }
+        };
+        switch(datastore)?;
+        let result = f(self);
+        let restored = switch(current);
+        let value = result?;
+        restored?;
+        Ok(value)
+    }
+
+    /// The connection the session was started on.
+    pub fn connection(&self) -> &'a Connection {
+        self.conn
+    }
+
+    /// The default timeout of reading data, locking, copying configuration
+    /// and sending RPCs.
+    pub fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Set the timeout used when these operations are given a zero timeout.
+    /// With a zero default, the sysrepo defaults are used.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// The default timeout of applying changes.
+    pub fn apply_timeout(&self) -> Duration {
+        self.apply_timeout
+    }
+
+    /// Set the timeout used when applying changes with a zero timeout. With a
+    /// zero default, the sysrepo default is used.
+    pub fn set_apply_timeout(&mut self, timeout: Duration) {
+        self.apply_timeout = timeout;
+    }
+
+    /// The default options of reading data.
+    pub fn get_options(&self) -> GetOptions {
+        self.get_options.clone()
+    }
+
+    /// Set the options used when reading data without options.
+    pub fn set_get_options(&mut self, options: GetOptions) {
+        self.get_options = options;
+    }
+
+    /// The default options of editing data.
+    pub fn edit_options(&self) -> EditOptions {
+        self.edit_options.clone()
+    }
+
+    /// Set the options used when editing data without options.
+    pub fn set_edit_options(&mut self, options: EditOptions) {
+        self.edit_options = options;
+    }
+
+    /// The timeout of an operation in milliseconds, falling back to the
+    /// default of the session.
+    fn timeout_ms(timeout: Duration, default: Duration) -> u32 {
+        let timeout = if timeout.is_zero() { default } else { timeout };
+        // TODO: double check that the duration is short enough
+        timeout.as_millis() as u32
+    }
+
+    /// The time left until a deadline, failing with `SR_ERR_TIME_OUT` once
+    /// less than a millisecond is left.
+    fn remaining(deadline: Instant) -> Result<Duration> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining < Duration::from_millis(1) {
+            Err(Error::new(ffi::sr_error_t::SR_ERR_TIME_OUT))
+        } else {
+            Ok(remaining)
+        }
+    }
+
+    pub fn get_context(&self) -> Option<AcquiredContext<'a>> {
+        self.conn.get_context()
+    }
+
+    /// Stamp the session with an originator.
+    ///
+    /// The originator is passed along to the subscribers of all events
+    /// caused by this session. Subscriptions of a session with an originator
+    /// name and `SubscriptionOptions::FILTER_ORIG` ignore events caused by
+    /// sessions with the same originator name.
+    pub fn set_originator(&mut self, originator: &Originator) -> Result<()> {
+        let name = str_to_cstring(&originator.name)?;
+        let rc = unsafe { ffi::sr_session_set_orig_name(self.sess, name.as_ptr()) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            return Err(Error::new(rc));
+        }
+
+        unsafe { ffi::sr_session_del_orig_data(self.sess) };
+        for data in &originator.data {
+            let size = u32::try_from(data.len())
+                .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+            let rc = unsafe {
+                ffi::sr_session_push_orig_data(self.sess, size, data.as_ptr() as *const _)
+            };
+            let rc = rc as ffi::sr_error_t::Type;
+            if rc != ffi::sr_error_t::SR_ERR_OK {
+                return Err(Error::new(rc));
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the originator of the event being handled.
+    ///
+    /// Meant for the implicit sessions passed to callbacks, where it tells
+    /// which originator, such as a NETCONF server, caused the event. Returns
+    /// `None` if no originator was set.
+    pub fn originator(&self) -> Option<Originator> {
+        let name = unsafe { ffi::sr_session_get_orig_name(self.sess) };
+        if name.is_null() {
+            return None;
+        }
+        let name = unsafe { CStr::from_ptr(name) }
+            .to_string_lossy()
+            .into_owned();
+
+        let mut data = Vec::new();
+        for idx in 0.. {
+            let mut size = 0;
+            let mut chunk = ptr::null();
+            let rc =
+                unsafe { ffi::sr_session_get_orig_data(self.sess, idx, &mut size, &mut chunk) };
+            let rc = rc as ffi::sr_error_t::Type;
+            if rc != ffi::sr_error_t::SR_ERR_OK || chunk.is_null() {
+                break;
+            }
+            let chunk = unsafe { std::slice::from_raw_parts(chunk as *const u8, size as usize) };
+            data.push(chunk.to_vec());
+        }
+
+        Some(Originator { name, data })
+    }
+
+    /// Whether NACM checks are bypassed for the session, as it has no NACM
+    /// user or the user is the recovery user.
+    #[cfg(feature = "netconf-acm")]
+    pub fn nacm_bypassed(&self) -> bool {
+        let user = unsafe { ffi::sr_nacm_get_user(self.sess) };
+        user.is_null() || unsafe { CStr::from_ptr(user) }.to_string_lossy() == nacm_recovery_user()
+    }
+
+    /// Get the schema node for a given data path (JSON format).
+    ///
+    /// The context stays acquired for as long as the returned node is held.
+    pub fn schema_node(&self, path: &str) -> Result<AcquiredSchemaNode<'a>> {
+        let ctx = self
+            .get_context()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+        let node = ctx
+            .find_path(path)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?
+            .as_raw();
+        Ok(AcquiredSchemaNode { ctx, node })
+    }
+
+    /// Validate a data tree, such as a payload received over the network.
+    ///
+    /// A validated copy of the tree, with implicit default nodes added, is
+    /// returned. State data is only allowed in the operational datastore. The
+    /// context is held while validating, so it cannot change meanwhile.
+    pub fn validate_tree<'t>(
+        &self,
+        tree: &DataTree<'t>,
+        options: DataValidationFlags,
+    ) -> Result<DataTree<'t>> {
+        let _ctx = self
+            .get_context()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+        let mut options = options;
+        if self.datastore() != Datastore::Operational {
+            options |= DataValidationFlags::NO_STATE;
+        }
+
+        let mut tree = tree
+            .duplicate()
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?;
+        tree.validate(options)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_VALIDATION_FAILED))?;
+        Ok(tree)
+    }
+
+    /// Get a data tree for a given XPath.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn get_data(
+        &self,
+        xpath: impl ToCStr,
+        max_depth: Option<NonZero<u32>>,
+        timeout: Duration,
         options: GetOptions,
     ) -> Result<ManagedData<'a>> {
-        let xpath = str_to_cstring(xpath)?;
+        let xpath = xpath.to_cstr()?;
         let max_depth = max_depth.map(NonZero::get).unwrap_or(0);
-        // TODO: double check this actually fits
-        let timeout_ms = timeout.as_millis() as u32;
+        let timeout_ms = Session::timeout_ms(timeout, self.timeout);
         let mut data: *mut ffi::sr_data_t = ptr::null_mut();
 
+        stat!(ffi_call("sr_get_data"));
         let rc = unsafe {
             ffi::sr_get_data(
                 self.sess,
@@ -459,886 +1546,4805 @@ impl<'a> Session<'a> {
         };
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            return Err(Error { errcode: rc });
+            return Err(Error::new(rc));
         }
         if data.is_null() {
-            return Err(Error {
-                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
-            });
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND));
         }
 
-        unsafe { Ok(ManagedData::from_raw(self.conn, data)) }
+        let data = unsafe { ManagedData::from_raw(self.conn, data) };
+        stat!(fetched_nodes(data.tree().traverse().count() as u64));
+        Ok(data)
     }
 
-    /// Set string item to given Xpath.
-    pub fn set_item_str(
+    /// Get a data tree for a given XPath with the default options of the
+    /// session.
+    pub fn get_data_default_options(
         &self,
-        path: &str,
-        value: &str,
-        origin: Option<&str>,
-        options: EditOptions,
-    ) -> Result<()> {
-        let path = str_to_cstring(path)?;
-        let value = str_to_cstring(value)?;
-        let origin = match origin {
-            Some(orig) => Some(str_to_cstring(orig)?),
-            None => None,
-        };
-        let origin_ptr = origin.as_deref().map_or(ptr::null(), |orig| orig.as_ptr());
+        xpath: impl ToCStr,
+        max_depth: Option<NonZero<u32>>,
+        timeout: Duration,
+    ) -> Result<ManagedData<'a>> {
+        self.get_data(xpath, max_depth, timeout, self.get_options())
+    }
 
-        let rc = unsafe {
-            ffi::sr_set_item_str(
-                self.sess,
-                path.as_ptr(),
-                value.as_ptr(),
-                origin_ptr,
-                options.bits(),
-            )
+    /// Get the data for a given XPath printed in a format.
+    ///
+    /// The string is empty if no data are selected. The timeout is rounded to
+    /// the nearest millisecond.
+    pub fn get_data_string(
+        &self,
+        xpath: &str,
+        format: DataFormat,
+        flags: DataPrinterFlags,
+        max_depth: Option<NonZero<u32>>,
+        timeout: Duration,
+        options: GetOptions,
+    ) -> Result<String> {
+        let data = match self.get_data(xpath, max_depth, timeout, options) {
+            Ok(data) => data,
+            Err(Error {
+                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                ..
+            }) => return Ok(String::new()),
+            Err(e) => return Err(e),
         };
-        let rc = rc as ffi::sr_error_t::Type;
-        if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
-        } else {
-            Ok(())
-        }
+        data.tree()
+            .print_string(format, flags)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))
     }
 
-    /// Delete item at given Xpath.
-    pub fn delete_item(&self, path: &str, options: EditOptions) -> Result<()> {
-        let path = str_to_cstring(path)?;
+    /// Get a data tree for a given XPath, waiting at most until the deadline.
+    pub fn get_data_until(
+        &self,
+        xpath: &str,
+        max_depth: Option<NonZero<u32>>,
+        deadline: Instant,
+        options: GetOptions,
+    ) -> Result<ManagedData<'a>> {
+        self.get_data(xpath, max_depth, Session::remaining(deadline)?, options)
+    }
 
-        let rc = unsafe { ffi::sr_delete_item(self.sess, path.as_ptr(), options.bits()) };
+    /// Get the subtree of the single node at a path.
+    ///
+    /// Unlike `get_data`, the returned data is rooted at the node itself,
+    /// without its parents. Fails with `SR_ERR_NOT_FOUND` if no node matches
+    /// the path and `SR_ERR_INVAL_ARG` if several do. The timeout is rounded
+    /// to the nearest millisecond.
+    pub fn get_subtree(&self, path: impl ToCStr, timeout: Duration) -> Result<ManagedData<'a>> {
+        let path = path.to_cstr()?;
+        let timeout_ms = Session::timeout_ms(timeout, self.timeout);
+        let mut data: *mut ffi::sr_data_t = ptr::null_mut();
+
+        stat!(ffi_call("sr_get_node"));
+        let rc = unsafe { ffi::sr_get_node(self.sess, path.as_ptr(), timeout_ms, &mut data) };
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
-        } else {
-            Ok(())
+            return Err(Error::new(rc));
         }
+        if data.is_null() {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND));
+        }
+
+        let data = unsafe { ManagedData::from_raw(self.conn, data) };
+        stat!(fetched_nodes(data.tree().traverse().count() as u64));
+        Ok(data)
     }
 
-    /// Apply changes for the session.
+    /// Get the single node at a path, or `None` if there is no such node.
     ///
-    /// The timeout is rounded to the nearest millisecond.
-    pub fn apply_changes(&mut self, timeout: Duration) -> Result<()> {
-        // TODO: double check that the duration is short enough
-        let timeout_ms = timeout.as_millis() as u32;
-
-        let rc = unsafe { ffi::sr_apply_changes(self.sess, timeout_ms) };
+    /// The path must select at most one node. The timeout is rounded to the
+    /// nearest millisecond.
+    pub fn get_item(&self, path: impl ToCStr, timeout: Duration) -> Result<Option<Item>> {
+        let path = path.to_cstr()?;
+        let timeout_ms = Session::timeout_ms(timeout, self.timeout);
+        let mut val: *mut ffi::sr_val_t = ptr::null_mut();
+
+        stat!(ffi_call("sr_get_item"));
+        let rc = unsafe { ffi::sr_get_item(self.sess, path.as_ptr(), timeout_ms, &mut val) };
         let rc = rc as ffi::sr_error_t::Type;
-        if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
-        } else {
-            Ok(())
+        match rc {
+            ffi::sr_error_t::SR_ERR_OK => {}
+            ffi::sr_error_t::SR_ERR_NOT_FOUND => return Ok(None),
+            _ => return Err(Error::new(rc)),
         }
+
+        let item = unsafe { Item::from_raw(val) };
+        unsafe { ffi::sr_free_val(val) };
+        item.map(Some)
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_UNSUPPORTED))
     }
 
+    /// Get the value of a leaf converted to a Rust type.
+    ///
+    /// Fails with `SR_ERR_NOT_FOUND` if the leaf does not exist and
+    /// `SR_ERR_INVAL_ARG`, with a message naming both types, if its value
+    /// cannot be converted, see `FromValue`.
     /// The timeout is rounded to the nearest millisecond.
-    pub fn copy_config(
-        &mut self,
-        mod_name: Option<&str>,
-        datastore: Datastore,
+    pub fn get_value<T: FromValue>(&self, path: impl ToCStr, timeout: Duration) -> Result<T> {
+        let item = self
+            .get_item(path, timeout)?
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+        T::from_value(&item.value).ok_or_else(|| {
+            Error::with_message(
+                ffi::sr_error_t::SR_ERR_INVAL_ARG,
+                format!(
+                    "{} of type {} cannot be read as {}",
+                    item.xpath,
+                    item.value.type_name(),
+                    std::any::type_name::<T>()
+                ),
+            )
+        })
+    }
+
+    /// Get the nodes selected by an XPath as a flat list of items.
+    ///
+    /// Nodes of a type the values API does not know are skipped. The timeout
+    /// is rounded to the nearest millisecond.
+    pub fn get_items(
+        &self,
+        xpath: impl ToCStr,
         timeout: Duration,
-    ) -> Result<()> {
-        // TODO: double check that the duration is short enough
-        let timeout_ms = timeout.as_millis() as u32;
-        let mod_name = match mod_name {
-            Some(path) => Some(str_to_cstring(path)?),
-            None => None,
-        };
-        let mod_name = mod_name
-            .as_deref()
-            .map_or(ptr::null(), |mod_name| mod_name.as_ptr());
+        options: GetOptions,
+    ) -> Result<Vec<Item>> {
+        let xpath = xpath.to_cstr()?;
+        let timeout_ms = Session::timeout_ms(timeout, self.timeout);
+        let mut values: *mut ffi::sr_val_t = ptr::null_mut();
+        let mut count: libc::size_t = 0;
 
+        stat!(ffi_call("sr_get_items"));
         let rc = unsafe {
-            ffi::sr_copy_config(
+            ffi::sr_get_items(
                 self.sess,
-                mod_name,
-                datastore as ffi::sr_datastore_t::Type,
+                xpath.as_ptr(),
                 timeout_ms,
+                options.bits(),
+                &mut values,
+                &mut count,
             )
         };
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
-        } else {
-            Ok(())
+            return Err(Error::new(rc));
         }
+
+        let items = (0..count)
+            .filter_map(|i| unsafe { Item::from_raw(values.add(i)) })
+            .collect();
+        unsafe { ffi::sr_free_values(values, count) };
+        Ok(items)
     }
 
-    pub fn new_notification_subscription<F>(
+    /// Get the nodes selected by an XPath as a flat list of items with the
+    /// default options of the session.
+    pub fn get_items_default_options(
         &self,
-        mod_name: &str,
-        xpath: Option<&str>,
-        start_time: Option<SystemTime>,
-        stop_time: Option<SystemTime>,
-        callback: F,
-        options: SubscriptionOptions,
-    ) -> Result<Subscription<'a>>
-    where
-        F: FnMut(&Session, u32, NotificationType, &DataTree, SystemTime) + 'static,
-    {
-        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
-        self.notification_subscribe(
-            &mut subscr,
-            mod_name,
+        xpath: impl ToCStr,
+        timeout: Duration,
+    ) -> Result<Vec<Item>> {
+        self.get_items(xpath, timeout, self.get_options())
+    }
+
+    /// Start building a data request for a given XPath.
+    ///
+    /// The request can take the RESTCONF query parameters `depth`, `fields`
+    /// and `with-defaults` into account.
+    pub fn get<'s>(&'s self, xpath: impl ToCStr) -> GetDataRequest<'s, 'a> {
+        let xpath = xpath.to_cstr().and_then(|xpath| {
+            xpath
+                .to_str()
+                .map(str::to_owned)
+                .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))
+        });
+        GetDataRequest {
+            sess: self,
             xpath,
-            start_time,
-            stop_time,
-            callback,
-            options,
-        )
-        .map(|_| subscr)
+            max_depth: 0,
+            timeout: Duration::default(),
+            options: self.get_options(),
+            fields: None,
+            with_defaults: None,
+            datastore: None,
+        }
     }
 
-    pub fn add_notification_subscription<F>(
+    /// Get a single data tree for several XPaths.
+    ///
+    /// The XPaths are joined into one union expression, so the data is
+    /// retrieved with one request and one context lock.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn get_data_multi(
         &self,
-        subscription: &mut Subscription<'a>,
-        mod_name: &str,
-        xpath: Option<&str>,
-        start_time: Option<SystemTime>,
-        stop_time: Option<SystemTime>,
-        callback: F,
-        options: SubscriptionOptions,
-    ) -> Result<()>
-    where
-        F: FnMut(&Session, u32, NotificationType, &DataTree, SystemTime) + 'static,
-    {
-        self.notification_subscribe(
-            subscription,
-            mod_name,
-            xpath,
-            start_time,
-            stop_time,
-            callback,
-            options,
-        )
+        xpaths: &[&str],
+        max_depth: Option<NonZero<u32>>,
+        timeout: Duration,
+        options: GetOptions,
+    ) -> Result<ManagedData<'a>> {
+        if xpaths.is_empty() {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+        self.get_data(xpaths.join(" | "), max_depth, timeout, options)
     }
 
-    fn notification_subscribe<F>(
+    /// Get the key values of all instances of a list.
+    ///
+    /// Only the list instances and their keys are retrieved. The keys of each
+    /// instance are returned in schema order.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn list_keys(&self, list_xpath: &str, timeout: Duration) -> Result<Vec<Vec<String>>> {
+        let data = match self.get_data_default_options(list_xpath, NonZero::new(1), timeout) {
+            Ok(data) => data,
+            Err(Error {
+                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                ..
+            }) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let tree = data.tree();
+        let instances = tree
+            .find_xpath(list_xpath)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?;
+
+        Ok(instances
+            .map(|instance| {
+                instance
+                    .list_keys()
+                    .map(|key| key.value_canonical().unwrap_or_default())
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Resolve the node referenced by a leafref leaf.
+    ///
+    /// The target is first looked up in the tree containing `node`. If it is
+    /// not present there, the datastore of this session is queried for it.
+    /// Follow-up queries cannot evaluate leafref paths whose predicates use
+    /// `current()`, so such targets are only found locally.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    #[cfg(feature = "yang3")]
+    pub fn resolve_leafref<'n>(
         &self,
-        subscription: &mut Subscription<'a>,
-        mod_name: &str,
-        xpath: Option<&str>,
-        start_time: Option<SystemTime>,
-        stop_time: Option<SystemTime>,
-        callback: F,
-        options: SubscriptionOptions,
-    ) -> Result<()>
-    where
-        // TODO: probably should pass DataNodeRef instead of DataTree
-        F: FnMut(&Session, u32, NotificationType, &DataTree, SystemTime) + 'static,
-    {
-        let mod_name = str_to_cstring(mod_name)?;
-        let xpath = match xpath {
-            Some(path) => Some(str_to_cstring(path)?),
-            None => None,
+        node: &'n DataNodeRef<'_>,
+        timeout: Duration,
+    ) -> Result<LeafrefTarget<'n, 'a>> {
+        let path = leafref_path(node)?;
+        let value = node
+            .value_canonical()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+
+        let local = node
+            .find_xpath(&path)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?
+            .find(|target| target.value_canonical().as_deref() == Some(value.as_str()));
+        if let Some(target) = local {
+            return Ok(LeafrefTarget::Local(target));
+        }
+
+        let xpath = if path.starts_with('/') {
+            format!("{}[.={}]", path, xpath_literal(&value))
+        } else {
+            format!("{}/{}[.={}]", node.path(), path, xpath_literal(&value))
         };
-        let xpath_ptr = xpath.as_deref().map_or(ptr::null(), |xpath| xpath.as_ptr());
-        let into_timespec = |t: SystemTime| {
-            let d = t.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
-            timespec {
-                tv_sec: d.as_secs() as _,
-                tv_nsec: d.subsec_nanos() as _,
-            }
+        self.get_data_default_options(&xpath, None, timeout)
+            .map(LeafrefTarget::Fetched)
+    }
+
+    /// Set string item to given Xpath.
+    pub fn set_item_str(
+        &mut self,
+        path: impl ToCStr,
+        value: &str,
+        origin: Option<&str>,
+        options: EditOptions,
+    ) -> Result<()> {
+        self.set_item(path, value, origin, options)
+    }
+
+    /// Set the item at a path to a value, see `ToValue`.
+    ///
+    /// A value of `None` creates an empty leaf, a presence container or a
+    /// list instance with only its keys.
+    pub fn set_item(
+        &mut self,
+        path: impl ToCStr,
+        value: impl ToValue,
+        origin: Option<&str>,
+        options: EditOptions,
+    ) -> Result<()> {
+        let path = path.to_cstr()?;
+        let value = value
+            .to_value()
+            .as_deref()
+            .map(str_to_cstring)
+            .transpose()?;
+        let value_ptr = value.as_deref().map_or(ptr::null(), |value| value.as_ptr());
+        let origin = match origin {
+            Some(orig) => Some(str_to_cstring(orig)?),
+            None => None,
         };
-        let start_time = start_time.map(into_timespec);
-        let start_time = start_time.as_ref().map_or(ptr::null(), |t| t as *const _);
-        let stop_time = stop_time.map(into_timespec);
-        let stop_time = stop_time.as_ref().map_or(ptr::null(), |t| t as *const _);
+        let origin_ptr = origin.as_deref().map_or(ptr::null(), |orig| orig.as_ptr());
 
-        let data = Box::into_raw(Box::new(callback));
+        stat!(ffi_call("sr_set_item_str"));
         let rc = unsafe {
-            ffi::sr_notif_subscribe_tree(
+            ffi::sr_set_item_str(
                 self.sess,
-                mod_name.as_ptr(),
-                xpath_ptr,
-                start_time,
-                stop_time,
-                Some(Session::call_event_notif::<F>),
-                data as *mut _,
+                path.as_ptr(),
+                value_ptr,
+                origin_ptr,
                 options.bits(),
-                &mut subscription.subscr,
             )
         };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Set the item at a path to a value with the default options of the
+    /// session.
+    pub fn set_item_default_options(
+        &mut self,
+        path: impl ToCStr,
+        value: impl ToValue,
+        origin: Option<&str>,
+    ) -> Result<()> {
+        self.set_item(path, value, origin, self.edit_options())
+    }
+
+    /// Create the container or list instance at the given path if it does not
+    /// exist yet.
+    ///
+    /// List keys are taken from the predicates of the path. Nothing is changed
+    /// if the node is already present in the datastore.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn ensure_path(&mut self, path: &str, timeout: Duration) -> Result<()> {
+        match self.get_data_default_options(path, NonZero::new(1), timeout) {
+            Ok(_) => return Ok(()),
+            Err(Error {
+                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                ..
+            }) => {}
+            Err(e) => return Err(e),
+        }
 
+        let path = str_to_cstring(path)?;
+        stat!(ffi_call("sr_set_item_str"));
+        let rc = unsafe {
+            ffi::sr_set_item_str(
+                self.sess,
+                path.as_ptr(),
+                ptr::null(),
+                ptr::null(),
+                self.edit_options.bits(),
+            )
+        };
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
+            Err(Error::new(rc))
         } else {
             Ok(())
         }
     }
 
-    unsafe extern "C" fn call_event_notif<F>(
-        sess: *mut ffi::sr_session_ctx_t,
-        sub_id: u32,
-        notif_type: ffi::sr_ev_notif_type_t::Type,
-        notif: *const yang::ffi::lyd_node,
-        timestamp: *mut timespec,
-        private_data: *mut c_void,
-    ) where
-        F: FnMut(&Session, u32, NotificationType, &DataTree, SystemTime),
-    {
-        let callback_ptr = private_data as *mut F;
-        let callback = &mut *callback_ptr;
+    /// Delete item at given Xpath.
+    ///
+    /// A list instance is selected with predicates on all its keys and is
+    /// deleted with its whole subtree. The deletion is only prepared, it is
+    /// applied with `Session::apply_changes`. It is not an error if the item
+    /// does not exist, unless `EditOptions::STRICT` is used.
+    pub fn delete_item(&mut self, path: impl ToCStr, options: EditOptions) -> Result<()> {
+        let path = path.to_cstr()?;
+
+        stat!(ffi_call("sr_delete_item"));
+        let rc = unsafe { ffi::sr_delete_item(self.sess, path.as_ptr(), options.bits()) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
+    }
 
-        let conn = ffi::sr_session_get_connection(sess);
-        let ctx = ffi::sr_acquire_context(conn);
-        // ctx will never be NULL as the context is locked for reading before
-        // this callback is called.
-        let ctx = ManuallyDrop::new(Context::from_raw(&(), ctx as *mut _));
-        let conn = ManuallyDrop::new(Connection::from_raw(conn));
-        let sess = ManuallyDrop::new(Session::from_raw(&conn, sess));
-        let notif = ManuallyDrop::new(DataTree::from_raw(&ctx, notif as *mut _));
-        let timestamp = timestamp.as_ref().unwrap();
-        // These casts are good enough for std.
-        let timestamp = SystemTime::UNIX_EPOCH
-            + Duration::new(timestamp.tv_sec as u64, timestamp.tv_nsec as u32);
-        let notif_type = NotificationType::try_from(notif_type).expect("Convert error");
+    /// Delete item at given Xpath with the default options of the session.
+    pub fn delete_item_default_options(&mut self, path: impl ToCStr) -> Result<()> {
+        self.delete_item(path, self.edit_options())
+    }
 
-        callback(&sess, sub_id, notif_type, &notif, timestamp);
+    /// Prepare an edit of NETCONF `edit-config` content, with
+    /// `default_operation` for the nodes without an operation attribute.
+    ///
+    /// As it is a batch edit, it must be the first change of the session.
+    pub fn edit_batch(
+        &mut self,
+        edit: &DataTree,
+        default_operation: DefaultOperation,
+    ) -> Result<()> {
+        let first = edit
+            .reference()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+        let default_operation = str_to_cstring(default_operation.as_str())?;
 
-        ffi::sr_release_context(conn.conn);
+        // The edit is copied by sysrepo.
+        stat!(ffi_call("sr_edit_batch"));
+        let rc =
+            unsafe { ffi::sr_edit_batch(self.sess, first.as_raw(), default_operation.as_ptr()) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
     }
 
-    pub fn new_rpc_subscription<F>(
-        &self,
-        xpath: &str,
-        callback: F,
-        priority: u32,
-        options: SubscriptionOptions,
-    ) -> Result<Subscription<'a>>
-    where
-        F: FnMut(&Session, u32, &str, &DataTree, Event, u32, &mut DataTree) -> Result<()> + 'static,
-    {
-        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
-        self.rpc_subscribe(&mut subscr, xpath, callback, priority, options)
-            .map(|_| subscr)
+    /// Delete all instances of a list or leaf-list, the sysrepo purge
+    /// operation.
+    ///
+    /// The path must not select a single instance with a predicate. It is not
+    /// an error if there are no instances, unless `EditOptions::STRICT` is
+    /// used.
+    pub fn purge_items(&mut self, path: impl ToCStr, options: EditOptions) -> Result<()> {
+        let path = path.to_cstr()?;
+        if path.to_bytes().ends_with(b"]") {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+        self.delete_item(&*path, options)
     }
 
-    pub fn add_rpc_subscription<F>(
-        &self,
-        subscription: &mut Subscription<'a>,
-        xpath: &str,
-        callback: F,
-        priority: u32,
-        options: SubscriptionOptions,
-    ) -> Result<()>
-    where
-        F: FnMut(&Session, u32, &str, &DataTree, Event, u32, &mut DataTree) -> Result<()> + 'static,
-    {
-        self.rpc_subscribe(subscription, xpath, callback, priority, options)
+    /// Delete the items at several paths and apply the deletions at once.
+    ///
+    /// Returns whether the deletion of every path could be prepared, in the
+    /// order of `paths`. The paths that failed are skipped and the others are
+    /// applied, unless all failed. If applying fails, all deletions are
+    /// discarded and the error is returned; with `EditOptions::STRICT`, this is
+    /// also the case if a path has no item.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn delete_items(
+        &mut self,
+        paths: &[&str],
+        options: EditOptions,
+        timeout: Duration,
+    ) -> Result<Vec<Result<()>>> {
+        let results: Vec<Result<()>> = paths
+            .iter()
+            .map(|path| self.delete_item(path, options.clone()))
+            .collect();
+        if results.iter().all(Result::is_err) {
+            return Ok(results);
+        }
+        if let Err(e) = self.apply_changes(timeout) {
+            let _ = self.discard_changes();
+            return Err(e);
+        }
+        Ok(results)
     }
 
-    fn rpc_subscribe<F>(
-        &self,
-        subscription: &mut Subscription<'a>,
-        xpath: &str,
-        callback: F,
-        priority: u32,
-        options: SubscriptionOptions,
-    ) -> Result<()>
-    where
-        F: FnMut(&Session, u32, &str, &DataTree, Event, u32, &mut DataTree) -> Result<()> + 'static,
-    {
-        let data = Box::into_raw(Box::new(callback));
-        let xpath = str_to_cstring(&xpath)?;
+    /// Set the values of a leaf-list to exactly the given ones.
+    ///
+    /// All instances are purged and the values are created in their order,
+    /// in the same edit.
+    pub fn replace_leaf_list(
+        &mut self,
+        path: &str,
+        values: &[&str],
+        options: EditOptions,
+    ) -> Result<()> {
+        self.purge_items(path, options.clone() - EditOptions::STRICT)?;
+        for value in values {
+            self.set_item_str(path, value, None, options.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Move an instance of a user-ordered list or leaf-list.
+    ///
+    /// The instance is created if it does not exist yet. The other instance
+    /// of a relative position is taken as list keys if the path selects a
+    /// list and as a leaf-list value otherwise.
+    pub fn move_item(
+        &mut self,
+        path: &str,
+        position: MovePosition,
+        options: EditOptions,
+    ) -> Result<()> {
+        let list = {
+            let ctx = self
+                .get_context()
+                .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+            let schema = ctx
+                .find_path(path)
+                .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+            schema.kind() == SchemaNodeKind::List
+        };
+        let path = str_to_cstring(path)?;
+        let (position, key) = match &position {
+            MovePosition::First => (ffi::sr_move_position_t::SR_MOVE_FIRST, None),
+            MovePosition::Last => (ffi::sr_move_position_t::SR_MOVE_LAST, None),
+            MovePosition::Before { key } => {
+                (ffi::sr_move_position_t::SR_MOVE_BEFORE, Some(key.as_str()))
+            }
+            MovePosition::After { key } => {
+                (ffi::sr_move_position_t::SR_MOVE_AFTER, Some(key.as_str()))
+            }
+        };
+        let key = key.map(str_to_cstring).transpose()?;
+        let key_ptr = key.as_deref().map_or(ptr::null(), |k| k.as_ptr());
+        let (keys, value) = if list {
+            (key_ptr, ptr::null())
+        } else {
+            (ptr::null(), key_ptr)
+        };
 
+        stat!(ffi_call("sr_move_item"));
         let rc = unsafe {
-            ffi::sr_rpc_subscribe_tree(
+            ffi::sr_move_item(
                 self.sess,
-                xpath.as_ptr(),
-                Some(Session::call_rpc::<F>),
-                data as *mut _,
-                priority,
+                path.as_ptr(),
+                position,
+                keys,
+                value,
+                ptr::null(),
                 options.bits(),
-                &mut subscription.subscr,
             )
         };
-
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
+            Err(Error::new(rc))
         } else {
             Ok(())
         }
     }
 
-    unsafe extern "C" fn call_rpc<F>(
-        sess: *mut ffi::sr_session_ctx_t,
-        sub_id: u32,
-        op_path: *const c_char,
-        input: *const yang::ffi::lyd_node,
-        event: ffi::sr_event_t::Type,
-        request_id: u32,
-        output: *mut yang::ffi::lyd_node,
-        private_data: *mut c_void,
-    ) -> c_int
-    where
-        F: FnMut(&Session, u32, &str, &DataTree, Event, u32, &mut DataTree) -> Result<()>,
-    {
-        let callback_ptr = private_data as *mut F;
-        let callback = &mut *callback_ptr;
+    /// Move an instance of a user-ordered list or leaf-list with the default
+    /// options of the session.
+    pub fn move_item_default_options(&mut self, path: &str, position: MovePosition) -> Result<()> {
+        self.move_item(path, position, self.edit_options())
+    }
 
-        let op_path = CStr::from_ptr(op_path).to_str().unwrap();
-        let conn = ffi::sr_session_get_connection(sess);
-        let ctx = ffi::sr_acquire_context(conn);
-        // ctx will never be NULL as the context is locked for reading before
-        // this callback is called.
-        let ctx = ManuallyDrop::new(Context::from_raw(&(), ctx as *mut _));
-        let conn = ManuallyDrop::new(Connection::from_raw(conn));
-        let sess = ManuallyDrop::new(Session::from_raw(&conn, sess));
-        let input = ManuallyDrop::new(DataTree::from_raw(&ctx, input as *mut _));
-        let mut output = ManuallyDrop::new(DataTree::from_raw(&ctx, output as *mut _));
-        let event = Event::try_from(event).expect("Convert error");
+    /// Replace the subtree at a path with the one in the data, the NETCONF
+    /// replace operation.
+    ///
+    /// The data must contain the node at the path, along with its ancestors
+    /// and list keys. Any node of the subtree missing from the data is
+    /// deleted. As it is a batch edit, it must be the first change of the
+    /// session.
+    #[cfg(feature = "yang3")]
+    pub fn replace_item(&mut self, path: &str, data: &DataTree) -> Result<()> {
+        let ly_err = |_| Error::new(ffi::sr_error_t::SR_ERR_LY);
+        let netconf = data
+            .context()
+            .get_module_implemented("ietf-netconf")
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+        let edit = data.duplicate().map_err(ly_err)?;
+        let node = edit
+            .find_path(path)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+        set_edit_operation(&node, &netconf, "replace")?;
+        self.edit_batch(&edit, DefaultOperation::Merge)
+    }
 
-        let res = callback(
-            &sess,
-            sub_id,
-            op_path,
-            &input,
-            event,
-            request_id,
-            &mut output,
-        );
+    /// Copy the configuration selected by an XPath from another datastore to
+    /// the one of the session.
+    ///
+    /// Unlike `copy_config`, only the selected nodes are copied: they replace
+    /// the ones of the session datastore, and those missing from the source
+    /// are removed. The changes are applied right away and discarded if they
+    /// cannot be, and as it is a batch edit, they must be the first change of
+    /// the session. The source is read through the session, so its NACM user
+    /// applies.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    #[cfg(feature = "yang3")]
+    pub fn copy_config_xpath(
+        &mut self,
+        xpath: &str,
+        datastore: Datastore,
+        timeout: Duration,
+    ) -> Result<()> {
+        let found = |result: Result<ManagedData<'a>>| match result {
+            Ok(data) => Ok(Some(data)),
+            Err(Error {
+                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                ..
+            }) => Ok(None),
+            Err(e) => Err(e),
+        };
+        let inval = |_| Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+        let selected = |data: &Option<ManagedData>| -> Result<Vec<(String, Option<String>)>> {
+            let Some(data) = data else {
+                return Ok(Vec::new());
+            };
+            let tree = data.tree();
+            let nodes = tree.find_xpath(xpath).map_err(inval)?;
+            Ok(nodes.map(|n| (n.path(), n.value_canonical())).collect())
+        };
 
-        ffi::sr_release_context(conn.conn);
+        let source = found(self.on_datastore(datastore, |sess| {
+            sess.get_data_default_options(xpath, None, timeout)
+        }))?;
+        let target = found(self.get_data_default_options(xpath, None, timeout))?;
+        let replaced = selected(&source)?;
+        let removed: Vec<_> = selected(&target)?
+            .into_iter()
+            .filter(|(path, _)| !replaced.iter().any(|(p, _)| p == path))
+            .collect();
+        if replaced.is_empty() && removed.is_empty() {
+            return Ok(());
+        }
 
-        res.err()
-            .map(|e| e.errcode)
-            .unwrap_or(ffi::sr_error_t::SR_ERR_OK) as c_int
-    }
+        {
+            let ctx = self
+                .get_context()
+                .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+            let netconf = ctx
+                .get_module_implemented("ietf-netconf")
+                .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+            let mut edit = match &source {
+                Some(source) => source
+                    .tree()
+                    .duplicate()
+                    .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?,
+                None => DataTree::new(&ctx),
+            };
+            for (path, value) in &removed {
+                edit.new_path(path, value.as_deref(), false)
+                    .map_err(inval)?;
+            }
+            for (paths, operation) in [(&replaced, "replace"), (&removed, "remove")] {
+                for (path, _) in paths {
+                    let node = edit.find_path(path).map_err(inval)?;
+                    set_edit_operation(&node, &netconf, operation)?;
+                }
+            }
+            self.edit_batch(&edit, DefaultOperation::Merge)?;
+        }
+        drop((source, target));
 
-    pub fn new_operational_get_subscription<F>(
-        &self,
-        mod_name: &str,
-        path: &str,
-        callback: F,
-        options: SubscriptionOptions,
-    ) -> Result<Subscription<'a>>
-    where
-        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>
-            + 'static,
-    {
-        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
-        self.oper_get_subscribe(&mut subscr, mod_name, path, callback, options)
-            .map(|_| subscr)
+        if let Err(e) = self.apply_changes(timeout) {
+            let _ = self.discard_changes();
+            return Err(e);
+        }
+        Ok(())
     }
 
-    pub fn add_operational_get_subscription<F>(
-        &self,
-        subscription: &mut Subscription<'a>,
-        mod_name: &str,
-        path: &str,
-        callback: F,
-        options: SubscriptionOptions,
-    ) -> Result<()>
-    where
-        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>
-            + 'static,
-    {
-        self.oper_get_subscribe(subscription, mod_name, path, callback, options)
+    /// Fail with `SR_ERR_UNSUPPORTED` if the session is the event session of
+    /// a running module change or operational get callback.
+    fn check_writable(&self) -> Result<()> {
+        let Some(callback) = CALLBACK.with(Cell::get) else {
+            return Ok(());
+        };
+        match callback.kind {
+            SubscriptionKind::ModuleChange | SubscriptionKind::OperationalGet
+                if callback.sess == self.sess =>
+            {
+                let module = unsafe { CStr::from_ptr(callback.module) };
+                Err(Error::with_message(
+                    ffi::sr_error_t::SR_ERR_UNSUPPORTED,
+                    format!(
+                        "cannot write through the event session of a {:?} callback for module {}",
+                        callback.kind,
+                        module.to_string_lossy()
+                    ),
+                ))
+            }
+            _ => Ok(()),
+        }
     }
 
-    fn oper_get_subscribe<F>(
-        &self,
-        subscription: &mut Subscription<'a>,
-        mod_name: &str,
-        path: &str,
-        callback: F,
-        options: SubscriptionOptions,
-    ) -> Result<()>
-    where
-        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>
-            + 'static,
-    {
-        let data = Box::into_raw(Box::new(callback));
-        let mod_name = str_to_cstring(mod_name)?;
-        let path = str_to_cstring(path)?;
-
-        let rc = unsafe {
-            ffi::sr_oper_get_subscribe(
-                self.sess,
-                mod_name.as_ptr(),
-                path.as_ptr(),
-                Some(Session::call_get_items::<F>),
-                data as *mut _,
-                options.bits(),
-                &mut subscription.subscr,
-            )
-        };
+    /// Apply changes for the session.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn apply_changes(&mut self, timeout: Duration) -> Result<()> {
+        self.check_writable()?;
+        let timeout_ms = Session::timeout_ms(timeout, self.apply_timeout);
 
+        stat!(ffi_call("sr_apply_changes"));
+        let rc = unsafe { ffi::sr_apply_changes(self.sess, timeout_ms) };
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
+            Err(Error::new(rc))
         } else {
             Ok(())
         }
     }
 
-    unsafe extern "C" fn call_get_items<F>(
-        sess: *mut ffi::sr_session_ctx_t,
-        sub_id: u32,
-        mod_name: *const c_char,
-        path: *const c_char,
-        request_xpath: *const c_char,
-        request_id: u32,
-        parent: *mut *mut yang::ffi::lyd_node,
-        private_data: *mut c_void,
-    ) -> c_int
-    where
-        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>,
-    {
-        if private_data.is_null() || parent.is_null() {
-            return ffi::sr_error_t::SR_ERR_INTERNAL as c_int;
-        }
-        let callback_ptr = private_data as *mut F;
-        let callback = &mut *callback_ptr;
+    /// Apply changes for the session, waiting at most until the deadline.
+    pub fn apply_changes_until(&mut self, deadline: Instant) -> Result<()> {
+        self.apply_changes(Session::remaining(deadline)?)
+    }
 
-        let conn = ffi::sr_session_get_connection(sess);
-        let ctx = ffi::sr_acquire_context(conn);
-        // ctx will never be NULL as the context is locked for reading before
-        // this callback is called.
-        let ctx = ManuallyDrop::new(Context::from_raw(&(), ctx as *mut _));
-        let conn = ManuallyDrop::new(Connection::from_raw(conn));
-        let sess = ManuallyDrop::new(Session::from_raw(&conn, sess));
-        let mut tree = DataTree::new(&ctx);
+    /// Apply changes for the session, leaving out the ones that would not
+    /// change anything.
+    ///
+    /// Leaves and leaf-lists merged with the value they already have are
+    /// dropped from the changes, so that pushing the same configuration again
+    /// does not notify subscribers. Returns whether any change was left to
+    /// apply, otherwise the changes are discarded. When changes are dropped,
+    /// the others are prepared again as a batch edit.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    #[cfg(feature = "yang3")]
+    pub fn apply_changes_if_changed(&mut self, timeout: Duration) -> Result<bool> {
+        self.check_writable()?;
+        stat!(ffi_call("sr_get_changes"));
+        let staged = unsafe { ffi::sr_get_changes(self.sess) };
+        if staged.is_null() {
+            return Ok(false);
+        }
 
-        let mod_name = CStr::from_ptr(mod_name).to_str().unwrap();
-        let path = CStr::from_ptr(path).to_str().unwrap();
-        let request_xpath = if request_xpath.is_null() {
-            None
-        } else {
-            Some(CStr::from_ptr(request_xpath).to_str().unwrap())
+        let ctx = self
+            .get_context()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+        let mut edit = unsafe { ManuallyDrop::new(DataTree::from_raw(&ctx, staged as *mut _)) }
+            .duplicate()
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?;
+
+        let mut current = Vec::new();
+        let tops = edit
+            .reference()
+            .into_iter()
+            .flat_map(|n| n.inclusive_siblings());
+        for top in tops {
+            match self.get_data_default_options(top.path(), None, timeout) {
+                Ok(data) => current.push(data),
+                Err(Error {
+                    errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                    ..
+                }) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let find = |path: &str, value: Option<&Option<String>>| {
+            current.iter().any(|data| {
+                data.tree().find_path(path).is_ok_and(|node| {
+                    value.is_none_or(|value| !node.is_default() && node.value_canonical() == *value)
+                })
+            })
         };
 
-        let res = callback(
-            &sess,
-            sub_id,
-            mod_name,
-            path,
-            request_xpath,
-            request_id,
-            &mut tree,
-        );
+        let mut changed = false;
+        let mut noops = Vec::new();
+        for node in edit.traverse() {
+            let schema = node.schema();
+            let path = node.path();
+            match edit_operation(&node) {
+                None => continue,
+                Some(op) if op != "merge" => changed = true,
+                Some(_) if schema.is_list_key() => {}
+                Some(_) => match schema.kind() {
+                    SchemaNodeKind::Leaf | SchemaNodeKind::LeafList => {
+                        if !find(&path, Some(&node.value_canonical())) {
+                            changed = true;
+                        } else {
+                            noops.push(path);
+                        }
+                    }
+                    _ => changed |= !find(&path, None),
+                },
+            }
+        }
+        drop(current);
 
-        ffi::sr_release_context(conn.conn);
+        if !changed {
+            self.discard_changes()?;
+            return Ok(false);
+        }
+        if !noops.is_empty() {
+            for path in &noops {
+                remove_path(&mut edit, path)?;
+            }
+            self.discard_changes()?;
+            self.edit_batch(&edit, DefaultOperation::Merge)?;
+        }
+        drop(edit);
+        drop(ctx);
 
-        *parent = tree.into_raw();
+        if let Err(e) = self.apply_changes(timeout) {
+            let _ = self.discard_changes();
+            return Err(e);
+        }
+        Ok(true)
+    }
 
-        res.err()
-            .map(|e| e.errcode)
-            .unwrap_or(ffi::sr_error_t::SR_ERR_OK) as c_int
+    /// Apply changes for the session and report what they changed.
+    ///
+    /// Only the data under the changed nodes are read, before and after
+    /// applying the changes, so changes made by others meanwhile are reported
+    /// too if they touch the same nodes.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    #[cfg(feature = "yang3")]
+    pub fn apply_changes_summary(&mut self, timeout: Duration) -> Result<ChangeSummary> {
+        self.check_writable()?;
+        stat!(ffi_call("sr_get_changes"));
+        let staged = unsafe { ffi::sr_get_changes(self.sess) };
+        if staged.is_null() {
+            self.apply_changes(timeout)?;
+            return Ok(ChangeSummary::default());
+        }
+
+        let ly_err = |_| Error::new(ffi::sr_error_t::SR_ERR_LY);
+        let no_ctx = || Error::new(ffi::sr_error_t::SR_ERR_INTERNAL);
+
+        // The context is not held while applying, so the data read before
+        // are kept printed in the meantime.
+        let (xpath, before) = {
+            let ctx = self.get_context().ok_or_else(no_ctx)?;
+            let staged = unsafe { ManuallyDrop::new(DataTree::from_raw(&ctx, staged as *mut _)) };
+            let xpath = staged
+                .traverse()
+                .filter(|node| {
+                    edit_operation(node).is_some()
+                        && node
+                            .ancestors()
+                            .next()
+                            .is_none_or(|parent| edit_operation(&parent).is_none())
+                })
+                .map(|node| node.path())
+                .collect::<Vec<_>>()
+                .join(" | ");
+            let before = self.snapshot(&ctx, &xpath, timeout)?;
+            let before = match before.reference() {
+                Some(_) => Some(
+                    before
+                        .print_bytes(DataFormat::LYB, DataPrinterFlags::WITH_SIBLINGS)
+                        .map_err(ly_err)?,
+                ),
+                None => None,
+            };
+            (xpath, before)
+        };
+
+        self.apply_changes(timeout)?;
+
+        let ctx = self.get_context().ok_or_else(no_ctx)?;
+        let before = match before {
+            Some(before) => DataTree::parse_string(
+                &ctx,
+                before,
+                DataFormat::LYB,
+                DataParserFlags::NO_VALIDATION | DataParserFlags::STRICT,
+                DataValidationFlags::empty(),
+            )
+            .map_err(ly_err)?,
+            None => DataTree::new(&ctx),
+        };
+        let after = self.snapshot(&ctx, &xpath, timeout)?;
+        let diff = before
+            .diff(&after, DataDiffFlags::empty())
+            .map_err(ly_err)?;
+
+        let mut summary = ChangeSummary::default();
+        for (op, node) in diff.iter() {
+            match op {
+                DataDiffOp::Create => summary.created += 1,
+                DataDiffOp::Delete => summary.deleted += 1,
+                DataDiffOp::Replace => summary.modified += 1,
+            }
+            let top = node.ancestors().last().unwrap_or(node);
+            let module = top.schema().module().name().to_owned();
+            if !summary.modules.contains(&module) {
+                summary.modules.push(module);
+            }
+        }
+        summary.modules.sort();
+        Ok(summary)
     }
 
-    pub fn new_module_change_subscription<F>(
+    /// A copy of the data selected by an XPath, empty if there are none.
+    #[cfg(feature = "yang3")]
+    fn snapshot<'c>(
         &self,
-        mod_name: &str,
-        xpath: Option<&str>,
-        callback: F,
-        priority: u32,
-        options: SubscriptionOptions,
-    ) -> Result<Subscription<'a>>
+        ctx: &'c Context,
+        xpath: &str,
+        timeout: Duration,
+    ) -> Result<DataTree<'c>> {
+        match self.get_data_default_options(xpath, None, timeout) {
+            Ok(data) => {
+                let copy = data
+                    .tree()
+                    .duplicate()
+                    .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?;
+                // The copy is freed by the returned tree.
+                let copy = ManuallyDrop::new(copy);
+                Ok(unsafe { DataTree::from_raw(ctx, copy.raw()) })
+            }
+            Err(Error {
+                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                ..
+            }) => Ok(DataTree::new(ctx)),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Discard all changes prepared in the session that were not applied.
+    pub fn discard_changes(&mut self) -> Result<()> {
+        stat!(ffi_call("sr_discard_changes"));
+        let rc = unsafe { ffi::sr_discard_changes(self.sess) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Lock the datastore of the session, or only a single module of it.
+    ///
+    /// The lock is held until the returned guard is dropped.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn lock<'s>(
+        &'s mut self,
+        module_name: Option<&str>,
+        timeout: Duration,
+    ) -> Result<LockGuard<'s, 'a>> {
+        self.check_writable()?;
+        let module_name = match module_name {
+            Some(name) => Some(str_to_cstring(name)?),
+            None => None,
+        };
+        let module_name_ptr = module_name.as_deref().map_or(ptr::null(), |n| n.as_ptr());
+        let timeout_ms = Session::timeout_ms(timeout, self.timeout);
+
+        stat!(ffi_call("sr_lock"));
+        let rc = unsafe { ffi::sr_lock(self.sess, module_name_ptr, timeout_ms) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(LockGuard {
+                sess: self,
+                module_name,
+                locked: true,
+            })
+        }
+    }
+
+    /// Run edits under a datastore or module lock and apply them.
+    ///
+    /// The lock is taken before `f` is called and released after the changes
+    /// are applied. If `f` or applying fails, the prepared edits are discarded.
+    /// The lock is released in every case.
+    ///
+    /// The timeout, used for both locking and applying, is rounded to the
+    /// nearest millisecond.
+    pub fn with_lock<F, T>(
+        &mut self,
+        module_name: Option<&str>,
+        timeout: Duration,
+        f: F,
+    ) -> Result<T>
     where
-        F: FnMut(&Session, u32, &str, Option<&str>, Event, u32) -> Result<()> + 'static,
+        F: FnOnce(&mut Session<'a>) -> Result<T>,
     {
-        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
-        self.module_change_subscribe(&mut subscr, mod_name, xpath, callback, priority, options)
-            .map(|_| subscr)
+        let mut guard = self.lock(module_name, timeout)?;
+        let res = f(&mut guard).and_then(|v| guard.apply_changes(timeout).map(|()| v));
+        if res.is_err() {
+            let _ = guard.discard_changes();
+        }
+        let unlocked = guard.unlock();
+        let v = res?;
+        unlocked.map(|()| v)
     }
 
-    pub fn add_module_change_subscription<F>(
-        &self,
-        subscription: &mut Subscription<'a>,
-        mod_name: &str,
-        xpath: Option<&str>,
-        callback: F,
-        priority: u32,
-        options: SubscriptionOptions,
-    ) -> Result<()>
+    /// Read, modify and write back the data at the given XPath.
+    ///
+    /// The current data is passed to `f`, which prepares edits on the session.
+    /// The edits are then applied. If applying fails because the datastore is
+    /// locked or the lock could not be acquired in time, the edits are
+    /// discarded and the whole sequence is retried up to `retries` more times.
+    /// Prepared edits are also discarded on any other failure.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn update<F>(&mut self, xpath: &str, retries: u32, timeout: Duration, f: F) -> Result<()>
     where
-        F: FnMut(&Session, u32, &str, Option<&str>, Event, u32) -> Result<()> + 'static,
+        F: FnMut(&mut Session<'a>, Option<&DataTree>) -> Result<()>,
     {
-        self.module_change_subscribe(subscription, mod_name, xpath, callback, priority, options)
+        self.update_cancellable(xpath, retries, timeout, &CancellationToken::new(), f)
     }
 
-    fn module_change_subscribe<F>(
-        &self,
-        subscription: &mut Subscription<'a>,
-        mod_name: &str,
-        xpath: Option<&str>,
-        callback: F,
-        priority: u32,
-        options: SubscriptionOptions,
+    /// Like `Session::update`, but stops once the token is cancelled, failing
+    /// with `SR_ERR_OPERATION_FAILED` and discarding any prepared edits.
+    ///
+    /// The token is checked before reading, before applying and before every
+    /// retry, but not during the read or apply themselves, which still take up
+    /// to the timeout to return.
+    pub fn update_cancellable<F>(
+        &mut self,
+        xpath: &str,
+        retries: u32,
+        timeout: Duration,
+        cancel: &CancellationToken,
+        mut f: F,
     ) -> Result<()>
     where
-        F: FnMut(&Session, u32, &str, Option<&str>, Event, u32) -> Result<()> + 'static,
+        F: FnMut(&mut Session<'a>, Option<&DataTree>) -> Result<()>,
     {
-        let data = Box::into_raw(Box::new(callback));
-        let mod_name = str_to_cstring(mod_name)?;
-        let xpath = xpath.map(|p| str_to_cstring(&p)).transpose()?;
+        let mut attempt = 0;
+        loop {
+            cancel.check()?;
+            let data = match self.get_data_default_options(xpath, None, timeout) {
+                Ok(data) => Some(data),
+                Err(Error {
+                    errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                    ..
+                }) => None,
+                Err(e) => return Err(e),
+            };
+            let tree = data.as_ref().map(ManagedData::tree);
+
+            if let Err(e) = f(self, tree.as_deref()).and_then(|()| cancel.check()) {
+                let _ = self.discard_changes();
+                return Err(e);
+            }
+            match self.apply_changes(timeout) {
+                Ok(()) => return Ok(()),
+                Err(Error { errcode, .. })
+                    if attempt < retries
+                        && (errcode == ffi::sr_error_t::SR_ERR_LOCKED
+                            || errcode == ffi::sr_error_t::SR_ERR_TIME_OUT) =>
+                {
+                    self.discard_changes()?;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    let _ = self.discard_changes();
+                    return Err(e);
+                }
+            }
+        }
+    }
+
+    /// Replace the configuration of a module, or of all modules, in the
+    /// datastore of the session with the one in the `source` datastore.
+    ///
+    /// This copies candidate to running on commit, running to startup to
+    /// save the configuration or factory-default to running to restore it.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn copy_config(
+        &mut self,
+        mod_name: Option<&str>,
+        source: Datastore,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let timeout_ms = Session::timeout_ms(timeout, self.timeout);
+        let mod_name = match mod_name {
+            Some(path) => Some(str_to_cstring(path)?),
+            None => None,
+        };
+        let mod_name = mod_name
+            .as_deref()
+            .map_or(ptr::null(), |mod_name| mod_name.as_ptr());
 
+        stat!(ffi_call("sr_copy_config"));
         let rc = unsafe {
-            ffi::sr_module_change_subscribe(
+            ffi::sr_copy_config(
                 self.sess,
-                mod_name.as_ptr(),
-                xpath.as_deref().map_or(ptr::null(), |p| p.as_ptr()),
-                Some(Session::call_module_change::<F>),
-                data as *mut _,
-                priority,
-                options.bits(),
-                &mut subscription.subscr,
+                mod_name,
+                source as ffi::sr_datastore_t::Type,
+                timeout_ms,
             )
         };
-
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
+            Err(Error::new(rc))
         } else {
             Ok(())
         }
     }
 
-    unsafe extern "C" fn call_module_change<F>(
-        sess: *mut ffi::sr_session_ctx_t,
-        sub_id: u32,
-        mod_name: *const c_char,
-        path: *const c_char,
-        event: ffi::sr_event_t::Type,
-        request_id: u32,
-        private_data: *mut c_void,
-    ) -> c_int
-    where
-        F: FnMut(&Session, u32, &str, Option<&str>, Event, u32) -> Result<()>,
-    {
-        let callback_ptr = private_data as *mut F;
-        let callback = &mut *callback_ptr;
-
-        let mod_name = CStr::from_ptr(mod_name).to_str().unwrap();
-        let path = if path.is_null() {
-            None
+    /// Replace the configuration of a module, or of all modules, with the
+    /// given data. No data removes the whole configuration.
+    ///
+    /// The configuration is replaced at once, as a single change, such as
+    /// when restoring a backup. Pending changes of the session are not used.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn replace_config(
+        &mut self,
+        mod_name: Option<&str>,
+        config: Option<DataTree<'_>>,
+        timeout: Duration,
+    ) -> Result<()> {
+        self.check_writable()?;
+        let timeout_ms = Session::timeout_ms(timeout, self.timeout);
+        let mod_name = match mod_name {
+            Some(path) => Some(str_to_cstring(path)?),
+            None => None,
+        };
+        let mod_name = mod_name
+            .as_deref()
+            .map_or(ptr::null(), |mod_name| mod_name.as_ptr());
+        // The configuration is always consumed by sysrepo.
+        let config = config.map_or(ptr::null_mut(), |config| config.into_raw());
+
+        stat!(ffi_call("sr_replace_config"));
+        let rc = unsafe { ffi::sr_replace_config(self.sess, mod_name, config, timeout_ms) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
         } else {
-            Some(CStr::from_ptr(path).to_str().unwrap())
+            Ok(())
+        }
+    }
+
+    /// Push operational data, which is retracted when the returned guard is
+    /// dropped.
+    ///
+    /// The data is merged into the data pushed by the session, which must be
+    /// on the operational datastore. The guard retracts the subtrees of the
+    /// top-level nodes of the data, so anything else the session pushed into
+    /// them is retracted too.
+    ///
+    /// The timeout, used for both pushing and retracting, is rounded to the
+    /// nearest millisecond.
+    pub fn push_oper_data<'s>(
+        &'s mut self,
+        data: &DataTree,
+        timeout: Duration,
+    ) -> Result<OperPushGuard<'s, 'a>> {
+        if self.datastore() != Datastore::Operational {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+        let first = data
+            .reference()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+        let paths = first
+            .inclusive_siblings()
+            .map(|node| str_to_cstring(&node.path()))
+            .collect::<Result<Vec<_>>>()?;
+
+        let merge = str_to_cstring("merge")?;
+        stat!(ffi_call("sr_edit_batch"));
+        let rc = unsafe { ffi::sr_edit_batch(self.sess, first.as_raw(), merge.as_ptr()) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            return Err(Error::new(rc));
+        }
+        if let Err(e) = self.apply_changes(timeout) {
+            let _ = self.discard_changes();
+            return Err(e);
+        }
+
+        Ok(OperPushGuard {
+            sess: self,
+            paths,
+            timeout,
+            pushed: true,
+        })
+    }
+
+    /// Publish a snapshot of the operational data of a module, replacing the
+    /// one previously published by the session.
+    ///
+    /// The snapshot is stored by sysrepo and served to readers until it is
+    /// replaced, without any callback. The data must only contain nodes of
+    /// the module, and an empty tree retracts the snapshot. The session must
+    /// be on the operational datastore and have no other changes prepared.
+    ///
+    /// The previous snapshot is retracted before the new one is pushed, so
+    /// readers may briefly see no data of the module.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn publish_oper_snapshot(
+        &mut self,
+        module: &str,
+        data: &DataTree,
+        timeout: Duration,
+    ) -> Result<()> {
+        if self.datastore() != Datastore::Operational {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+        let first = data.reference();
+        if let Some(first) = &first {
+            if first
+                .inclusive_siblings()
+                .any(|node| node.schema().module().name() != module)
+            {
+                return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+            }
+        }
+
+        // sysrepo only accepts a batch edit as the first change of the
+        // session, so the previous snapshot is discarded on its own.
+        let xpath = str_to_cstring(&format!("/{}:*", module))?;
+        stat!(ffi_call("sr_discard_items"));
+        let rc = unsafe { ffi::sr_discard_items(self.sess, xpath.as_ptr()) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            let _ = self.discard_changes();
+            return Err(Error::new(rc));
+        }
+        if let Err(e) = self.apply_changes(timeout) {
+            let _ = self.discard_changes();
+            return Err(e);
+        }
+
+        let Some(first) = first else {
+            return Ok(());
         };
-        let event = Event::try_from(event).expect("Convert error");
-        let conn = ffi::sr_session_get_connection(sess);
-        let conn = ManuallyDrop::new(Connection::from_raw(conn));
-        let sess = ManuallyDrop::new(Session::from_raw(&conn, sess));
+        let merge = str_to_cstring("merge")?;
+        stat!(ffi_call("sr_edit_batch"));
+        let rc = unsafe { ffi::sr_edit_batch(self.sess, first.as_raw(), merge.as_ptr()) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            return Err(Error::new(rc));
+        }
+        if let Err(e) = self.apply_changes(timeout) {
+            let _ = self.discard_changes();
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    pub fn new_notification_subscription<F>(
+        &self,
+        mod_name: impl ToCStr,
+        xpath: Option<&str>,
+        replay: ReplayWindow,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>>
+    where
+        F: FnMut(&NotifContext, &DataTree) + 'static,
+    {
+        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
+        self.notification_subscribe(&mut subscr, mod_name, xpath, replay, callback, options)
+            .map(|_| subscr)
+    }
+
+    pub fn add_notification_subscription<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        xpath: Option<&str>,
+        replay: ReplayWindow,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&NotifContext, &DataTree) + 'static,
+    {
+        self.notification_subscribe(subscription, mod_name, xpath, replay, callback, options)
+    }
 
-        let res = callback(&sess, sub_id, mod_name, path, event, request_id);
+    /// Subscribe for notifications, with a handle to wait for the end of the
+    /// subscription.
+    ///
+    /// A subscription with a stop time is terminated by sysrepo once the stop
+    /// time is reached.
+    pub fn new_notification_subscription_with_completion<F>(
+        &self,
+        mod_name: impl ToCStr,
+        xpath: Option<&str>,
+        replay: ReplayWindow,
+        mut callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<(Subscription<'a>, NotificationCompletion)>
+    where
+        F: FnMut(&NotifContext, &DataTree) + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let mut tx = Some(tx);
+        let callback = move |ctx: &NotifContext, notif: &DataTree| {
+            let notif_type = ctx.notif_type();
+            let end = matches!(
+                notif_type,
+                NotificationType::StopTime | NotificationType::Terminated
+            );
+            if end {
+                if let Some(tx) = tx.take() {
+                    let _ = tx.send(notif_type);
+                }
+            }
+            callback(ctx, notif);
+        };
 
-        res.err()
-            .map(|e| e.errcode)
-            .unwrap_or(ffi::sr_error_t::SR_ERR_OK) as c_int
+        let subscr =
+            self.new_notification_subscription(mod_name, xpath, replay, callback, options)?;
+        Ok((subscr, NotificationCompletion { rx }))
     }
 
-    // TODO: only valid in module_change_subscribe callback
-    pub fn get_changes_iter(&self, xpath: &str) -> Result<Changes> {
-        let xpath = str_to_cstring(xpath)?;
-        let mut it = ptr::null_mut();
-        let rc = unsafe { ffi::sr_get_changes_iter(self.sess, xpath.as_ptr(), &mut it) };
+    fn notification_subscribe<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        xpath: Option<&str>,
+        replay: ReplayWindow,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        // TODO: probably should pass DataNodeRef instead of DataTree
+        F: FnMut(&NotifContext, &DataTree) + 'static,
+    {
+        let mod_name = mod_name.to_cstr()?;
+        let xpath = match xpath {
+            Some(path) => Some(str_to_cstring(path)?),
+            None => None,
+        };
+        let xpath_ptr = xpath.as_deref().map_or(ptr::null(), |xpath| xpath.as_ptr());
+        let (start_time, stop_time) = replay.times()?;
+        let start_time = start_time.as_ref().map_or(ptr::null(), |t| t as *const _);
+        let stop_time = stop_time.as_ref().map_or(ptr::null(), |t| t as *const _);
+
+        options
+            .validate(SubscriptionKind::Notification)
+            .map_err(|message| Error::with_message(ffi::sr_error_t::SR_ERR_INVAL_ARG, message))?;
+        let remove_at_stop = options.contains(SubscriptionOptions::REMOVE_AT_STOP_TIME);
+        let options = options.difference(SubscriptionOptions::REMOVE_AT_STOP_TIME);
+        let data = Box::into_raw(Box::new(NotifCallback {
+            callback,
+            remove_at_stop,
+        }));
+        leaks!(track("callback", data as usize));
+        let rc = unsafe {
+            ffi::sr_notif_subscribe_tree(
+                self.sess,
+                mod_name.as_ptr(),
+                xpath_ptr,
+                start_time,
+                stop_time,
+                Some(Session::call_event_notif::<F>),
+                data as *mut _,
+                options.bits(),
+                &mut subscription.subscr,
+            )
+        };
 
         let rc = rc as ffi::sr_error_t::Type;
         if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
+            Err(Error::new(rc))
         } else {
-            Ok(unsafe { Changes::from_raw(self, it) })
+            subscription.push_last_sub_id();
+            Ok(())
         }
     }
 
-    /// Send event notify tree.
-    pub fn notif_send(&mut self, notif: &DataTree, timeout: Option<Duration>) -> Result<()> {
-        let timeout_ms = timeout.map_or(0, |t| t.as_millis() as u32);
-        let node = notif.reference().ok_or(Error {
-            errcode: ffi::sr_error_t::SR_ERR_INVAL_ARG,
-        })?;
+    unsafe extern "C" fn call_event_notif<F>(
+        sess: *mut ffi::sr_session_ctx_t,
+        sub_id: u32,
+        notif_type: ffi::sr_ev_notif_type_t::Type,
+        notif: *const yang::ffi::lyd_node,
+        timestamp: *mut timespec,
+        private_data: *mut c_void,
+    ) where
+        F: FnMut(&NotifContext, &DataTree),
+    {
+        stat!(callback(sub_id));
+        let _in_flight = InFlight::enter(sub_id, SubscriptionKind::Notification, sess, ptr::null());
+        let callback_ptr = private_data as *mut NotifCallback<F>;
+        let callback = &mut *callback_ptr;
+
+        let conn = ffi::sr_session_get_connection(sess);
+        stat!(context_acquired());
+        let ctx = ffi::sr_acquire_context(conn);
+        // ctx will never be NULL as the context is locked for reading before
+        // this callback is called.
+        let ctx = ManuallyDrop::new(Context::from_raw(&(), ctx as *mut _));
+        let conn = ManuallyDrop::new(Connection::from_raw(conn));
+        let sess = ManuallyDrop::new(Session::from_raw(&conn, sess));
+        let notif = ManuallyDrop::new(DataTree::from_raw(&ctx, notif as *mut _));
+        let timestamp = time::from_timespec(timestamp.as_ref().unwrap());
+        let notif_type = NotificationType::from(notif_type);
+
+        let terminated = notif_type == NotificationType::Terminated
+            || (callback.remove_at_stop && notif_type == NotificationType::StopTime);
+        #[cfg(feature = "stats")]
+        let start = Instant::now();
+        (callback.callback)(
+            &NotifContext {
+                sess: &sess,
+                sub_id,
+                notif_type,
+                timestamp,
+            },
+            &notif,
+        );
+        if !terminated {
+            stat!(event(sub_id, start, false));
+        }
+
+        ffi::sr_release_context(conn.conn);
+
+        // Termination, or reaching the stop time if requested, is the last
+        // event of a subscription, so the callback will not be called again.
+        if terminated {
+            leaks!(untrack("callback", callback_ptr as usize));
+            drop(Box::from_raw(callback_ptr));
+        }
+    }
+
+    /// Subscribe for notifications, delivered as a flat list of items instead
+    /// of a data tree.
+    pub fn new_notification_values_subscription<F>(
+        &self,
+        mod_name: impl ToCStr,
+        xpath: Option<&str>,
+        replay: ReplayWindow,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>>
+    where
+        F: FnMut(&NotifContext, &str, &[Item]) + 'static,
+    {
+        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
+        self.notification_values_subscribe(&mut subscr, mod_name, xpath, replay, callback, options)
+            .map(|_| subscr)
+    }
+
+    pub fn add_notification_values_subscription<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        xpath: Option<&str>,
+        replay: ReplayWindow,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&NotifContext, &str, &[Item]) + 'static,
+    {
+        self.notification_values_subscribe(subscription, mod_name, xpath, replay, callback, options)
+    }
+
+    fn notification_values_subscribe<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        xpath: Option<&str>,
+        replay: ReplayWindow,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&NotifContext, &str, &[Item]) + 'static,
+    {
+        let mod_name = mod_name.to_cstr()?;
+        let xpath = match xpath {
+            Some(path) => Some(str_to_cstring(path)?),
+            None => None,
+        };
+        let xpath_ptr = xpath.as_deref().map_or(ptr::null(), |xpath| xpath.as_ptr());
+        let (start_time, stop_time) = replay.times()?;
+        let start_time = start_time.as_ref().map_or(ptr::null(), |t| t as *const _);
+        let stop_time = stop_time.as_ref().map_or(ptr::null(), |t| t as *const _);
+
+        options
+            .validate(SubscriptionKind::Notification)
+            .map_err(|message| Error::with_message(ffi::sr_error_t::SR_ERR_INVAL_ARG, message))?;
+        let remove_at_stop = options.contains(SubscriptionOptions::REMOVE_AT_STOP_TIME);
+        let options = options.difference(SubscriptionOptions::REMOVE_AT_STOP_TIME);
+        let data = Box::into_raw(Box::new(NotifCallback {
+            callback,
+            remove_at_stop,
+        }));
+        leaks!(track("callback", data as usize));
         let rc = unsafe {
-            ffi::sr_notif_send_tree(
+            ffi::sr_notif_subscribe(
                 self.sess,
-                node.as_raw(),
-                timeout_ms,
-                timeout.is_some() as c_int,
+                mod_name.as_ptr(),
+                xpath_ptr,
+                start_time,
+                stop_time,
+                Some(Session::call_event_notif_values::<F>),
+                data as *mut _,
+                options.bits(),
+                &mut subscription.subscr,
             )
         };
 
-        let rc = rc as ffi::sr_error_t::Type;
-        if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
-        } else {
-            Ok(())
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            subscription.push_last_sub_id();
+            Ok(())
+        }
+    }
+
+    unsafe extern "C" fn call_event_notif_values<F>(
+        sess: *mut ffi::sr_session_ctx_t,
+        sub_id: u32,
+        notif_type: ffi::sr_ev_notif_type_t::Type,
+        xpath: *const c_char,
+        values: *const ffi::sr_val_t,
+        values_cnt: libc::size_t,
+        timestamp: *mut timespec,
+        private_data: *mut c_void,
+    ) where
+        F: FnMut(&NotifContext, &str, &[Item]),
+    {
+        stat!(callback(sub_id));
+        let _in_flight = InFlight::enter(sub_id, SubscriptionKind::Notification, sess, ptr::null());
+        let callback_ptr = private_data as *mut NotifCallback<F>;
+        let callback = &mut *callback_ptr;
+
+        let conn = ffi::sr_session_get_connection(sess);
+        let conn = ManuallyDrop::new(Connection::from_raw(conn));
+        let sess = ManuallyDrop::new(Session::from_raw(&conn, sess));
+        let xpath = if xpath.is_null() {
+            ""
+        } else {
+            CStr::from_ptr(xpath).to_str().unwrap()
+        };
+        let items: Vec<Item> = (0..values_cnt)
+            .filter_map(|i| Item::from_raw(values.add(i)))
+            .collect();
+        let timestamp = time::from_timespec(timestamp.as_ref().unwrap());
+        let notif_type = NotificationType::from(notif_type);
+
+        let terminated = notif_type == NotificationType::Terminated
+            || (callback.remove_at_stop && notif_type == NotificationType::StopTime);
+        #[cfg(feature = "stats")]
+        let start = Instant::now();
+        (callback.callback)(
+            &NotifContext {
+                sess: &sess,
+                sub_id,
+                notif_type,
+                timestamp,
+            },
+            xpath,
+            &items,
+        );
+        if !terminated {
+            stat!(event(sub_id, start, false));
+        }
+
+        // Termination, or reaching the stop time if requested, is the last
+        // event of a subscription, so the callback will not be called again.
+        if terminated {
+            leaks!(untrack("callback", callback_ptr as usize));
+            drop(Box::from_raw(callback_ptr));
+        }
+    }
+
+    /// See `Priority` for how `priority` orders the subscriptions of an event.
+    pub fn new_rpc_subscription<F>(
+        &self,
+        xpath: impl ToCStr,
+        callback: F,
+        priority: impl Into<Priority>,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>>
+    where
+        F: FnMut(&RpcContext, &mut DataTree) -> Result<()> + 'static,
+    {
+        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
+        self.rpc_subscribe(&mut subscr, xpath, callback, priority.into(), options)
+            .map(|_| subscr)
+    }
+
+    pub fn add_rpc_subscription<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        xpath: impl ToCStr,
+        callback: F,
+        priority: impl Into<Priority>,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&RpcContext, &mut DataTree) -> Result<()> + 'static,
+    {
+        self.rpc_subscribe(subscription, xpath, callback, priority.into(), options)
+    }
+
+    /// Subscribe handlers for an RPC or action, called in the given order.
+    ///
+    /// The last handler is subscribed with `priority` and every handler
+    /// before it with a priority one higher than the next one.
+    pub fn add_rpc_chain(
+        &self,
+        subscription: &mut Subscription<'a>,
+        xpath: impl ToCStr,
+        handlers: Vec<RpcHandler>,
+        priority: Priority,
+        options: SubscriptionOptions,
+    ) -> Result<()> {
+        for (priority, handler) in chain_priorities(priority, handlers)? {
+            self.rpc_subscribe(subscription, &xpath, handler, priority, options.clone())?;
+        }
+        Ok(())
+    }
+
+    fn rpc_subscribe<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        xpath: impl ToCStr,
+        callback: F,
+        priority: Priority,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&RpcContext, &mut DataTree) -> Result<()> + 'static,
+    {
+        options
+            .validate(SubscriptionKind::Rpc)
+            .map_err(|message| Error::with_message(ffi::sr_error_t::SR_ERR_INVAL_ARG, message))?;
+        let data = Box::into_raw(Box::new(callback));
+        leaks!(track("callback", data as usize));
+        let xpath = xpath.to_cstr()?;
+
+        let rc = unsafe {
+            ffi::sr_rpc_subscribe_tree(
+                self.sess,
+                xpath.as_ptr(),
+                Some(Session::call_rpc::<F>),
+                data as *mut _,
+                priority.0,
+                options.bits(),
+                &mut subscription.subscr,
+            )
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            subscription.push_last_sub_id();
+            Ok(())
+        }
+    }
+
+    unsafe extern "C" fn call_rpc<F>(
+        sess: *mut ffi::sr_session_ctx_t,
+        sub_id: u32,
+        op_path: *const c_char,
+        input: *const yang::ffi::lyd_node,
+        event: ffi::sr_event_t::Type,
+        request_id: u32,
+        output: *mut yang::ffi::lyd_node,
+        private_data: *mut c_void,
+    ) -> c_int
+    where
+        F: FnMut(&RpcContext, &mut DataTree) -> Result<()>,
+    {
+        stat!(callback(sub_id));
+        let _in_flight = InFlight::enter(sub_id, SubscriptionKind::Rpc, sess, ptr::null());
+        let callback_ptr = private_data as *mut F;
+        let callback = &mut *callback_ptr;
+
+        let op_path = CStr::from_ptr(op_path).to_str().unwrap();
+        let conn = ffi::sr_session_get_connection(sess);
+        stat!(context_acquired());
+        let ctx = ffi::sr_acquire_context(conn);
+        // ctx will never be NULL as the context is locked for reading before
+        // this callback is called.
+        let ctx = ManuallyDrop::new(Context::from_raw(&(), ctx as *mut _));
+        let conn = ManuallyDrop::new(Connection::from_raw(conn));
+        let sess = ManuallyDrop::new(Session::from_raw(&conn, sess));
+        let input = ManuallyDrop::new(DataTree::from_raw(&ctx, input as *mut _));
+        let mut output = ManuallyDrop::new(DataTree::from_raw(&ctx, output as *mut _));
+        let event = Event::from(event);
+
+        #[cfg(feature = "stats")]
+        let start = Instant::now();
+        let res = callback(
+            &RpcContext {
+                sess: &sess,
+                sub_id,
+                op_path,
+                input: &input,
+                event,
+                request_id,
+            },
+            &mut output,
+        );
+        stat!(event(sub_id, start, res.is_err()));
+
+        ffi::sr_release_context(conn.conn);
+
+        res.err()
+            .map(|e| e.errcode)
+            .unwrap_or(ffi::sr_error_t::SR_ERR_OK) as c_int
+    }
+
+    /// Subscribe to poll and cache operational data.
+    ///
+    /// The data are cached for `valid`. With `SubscriptionOptions::OPER_POLL_DIFF`
+    /// the changes between polls are reported to module change subscriptions
+    /// on the operational datastore, where they can be read with
+    /// [`Session::get_change_diff`].
+    ///
+    /// The validity is rounded to the nearest millisecond.
+    pub fn new_oper_poll_subscription(
+        &self,
+        mod_name: &str,
+        path: &str,
+        valid: Duration,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>> {
+        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
+        self.oper_poll_subscribe(&mut subscr, mod_name, path, valid, options)
+            .map(|_| subscr)
+    }
+
+    pub fn add_oper_poll_subscription(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: &str,
+        path: &str,
+        valid: Duration,
+        options: SubscriptionOptions,
+    ) -> Result<()> {
+        self.oper_poll_subscribe(subscription, mod_name, path, valid, options)
+    }
+
+    fn oper_poll_subscribe(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: &str,
+        path: &str,
+        valid: Duration,
+        options: SubscriptionOptions,
+    ) -> Result<()> {
+        options
+            .validate(SubscriptionKind::OperationalPoll)
+            .map_err(|message| Error::with_message(ffi::sr_error_t::SR_ERR_INVAL_ARG, message))?;
+        let mod_name = str_to_cstring(mod_name)?;
+        let path = str_to_cstring(path)?;
+        let valid_ms = valid.as_millis() as u32;
+
+        let rc = unsafe {
+            ffi::sr_oper_poll_subscribe(
+                self.sess,
+                mod_name.as_ptr(),
+                path.as_ptr(),
+                valid_ms,
+                options.bits(),
+                &mut subscription.subscr,
+            )
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            subscription.push_last_sub_id();
+            Ok(())
+        }
+    }
+
+    pub fn new_operational_get_subscription<F>(
+        &self,
+        mod_name: impl ToCStr,
+        path: impl ToCStr,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>>
+    where
+        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>
+            + 'static,
+    {
+        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
+        self.oper_get_subscribe(&mut subscr, mod_name, path, callback, options)
+            .map(|_| subscr)
+    }
+
+    pub fn add_operational_get_subscription<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        path: impl ToCStr,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>
+            + 'static,
+    {
+        self.oper_get_subscribe(subscription, mod_name, path, callback, options)
+    }
+
+    /// Subscribe one operational data provider for several paths of a
+    /// module.
+    ///
+    /// The callback is shared by all paths and tells them apart by the path
+    /// it is called with.
+    pub fn new_operational_get_subscriptions<F>(
+        &self,
+        mod_name: impl ToCStr,
+        paths: &[&str],
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>>
+    where
+        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>
+            + 'static,
+    {
+        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
+        self.add_operational_get_subscriptions(&mut subscr, mod_name, paths, callback, options)
+            .map(|_| subscr)
+    }
+
+    pub fn add_operational_get_subscriptions<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        paths: &[&str],
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>
+            + 'static,
+    {
+        if paths.is_empty() {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+        let callback = Arc::new(Mutex::new(callback));
+        for path in paths {
+            let callback = shared_oper_get_callback(&callback);
+            self.oper_get_subscribe(subscription, &mod_name, path, callback, options.clone())?;
+        }
+        Ok(())
+    }
+
+    fn oper_get_subscribe<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        path: impl ToCStr,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>
+            + 'static,
+    {
+        options
+            .validate(SubscriptionKind::OperationalGet)
+            .map_err(|message| Error::with_message(ffi::sr_error_t::SR_ERR_INVAL_ARG, message))?;
+        let data = Box::into_raw(Box::new(callback));
+        leaks!(track("callback", data as usize));
+        let mod_name = mod_name.to_cstr()?;
+        let path = path.to_cstr()?;
+
+        let rc = unsafe {
+            ffi::sr_oper_get_subscribe(
+                self.sess,
+                mod_name.as_ptr(),
+                path.as_ptr(),
+                Some(Session::call_get_items::<F>),
+                data as *mut _,
+                options.bits(),
+                &mut subscription.subscr,
+            )
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            subscription.push_last_sub_id();
+            Ok(())
+        }
+    }
+
+    unsafe extern "C" fn call_get_items<F>(
+        sess: *mut ffi::sr_session_ctx_t,
+        sub_id: u32,
+        mod_name: *const c_char,
+        path: *const c_char,
+        request_xpath: *const c_char,
+        request_id: u32,
+        parent: *mut *mut yang::ffi::lyd_node,
+        private_data: *mut c_void,
+    ) -> c_int
+    where
+        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>,
+    {
+        stat!(callback(sub_id));
+        let _in_flight = InFlight::enter(sub_id, SubscriptionKind::OperationalGet, sess, mod_name);
+        if private_data.is_null() || parent.is_null() {
+            return ffi::sr_error_t::SR_ERR_INTERNAL as c_int;
+        }
+        let callback_ptr = private_data as *mut F;
+        let callback = &mut *callback_ptr;
+
+        let conn = ffi::sr_session_get_connection(sess);
+        stat!(context_acquired());
+        let ctx = ffi::sr_acquire_context(conn);
+        // ctx will never be NULL as the context is locked for reading before
+        // this callback is called.
+        let ctx = ManuallyDrop::new(Context::from_raw(&(), ctx as *mut _));
+        let conn = ManuallyDrop::new(Connection::from_raw(conn));
+        let sess = ManuallyDrop::new(Session::from_raw(&conn, sess));
+        let mut tree = DataTree::new(&ctx);
+
+        let mod_name = CStr::from_ptr(mod_name).to_str().unwrap();
+        let path = CStr::from_ptr(path).to_str().unwrap();
+        let request_xpath = if request_xpath.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(request_xpath).to_str().unwrap())
+        };
+
+        #[cfg(feature = "stats")]
+        let start = Instant::now();
+        let res = callback(
+            &sess,
+            sub_id,
+            mod_name,
+            path,
+            request_xpath,
+            request_id,
+            &mut tree,
+        );
+        stat!(event(sub_id, start, res.is_err()));
+
+        ffi::sr_release_context(conn.conn);
+
+        *parent = tree.into_raw();
+
+        res.err()
+            .map(|e| e.errcode)
+            .unwrap_or(ffi::sr_error_t::SR_ERR_OK) as c_int
+    }
+
+    /// See `Priority` for how `priority` orders the subscriptions of an event.
+    pub fn new_module_change_subscription<F>(
+        &self,
+        mod_name: impl ToCStr,
+        xpath: Option<impl ToCStr>,
+        callback: F,
+        priority: impl Into<Priority>,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>>
+    where
+        F: FnMut(&ChangeContext) -> Result<()> + 'static,
+    {
+        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
+        self.module_change_subscribe(
+            &mut subscr,
+            mod_name,
+            xpath,
+            callback,
+            priority.into(),
+            options,
+        )
+        .map(|_| subscr)
+    }
+
+    pub fn add_module_change_subscription<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        xpath: Option<impl ToCStr>,
+        callback: F,
+        priority: impl Into<Priority>,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&ChangeContext) -> Result<()> + 'static,
+    {
+        self.module_change_subscribe(
+            subscription,
+            mod_name,
+            xpath,
+            callback,
+            priority.into(),
+            options,
+        )
+    }
+
+    /// Subscribe handlers for changes of a module, called in the given order.
+    ///
+    /// The last handler is subscribed with `priority` and every handler
+    /// before it with a priority one higher than the next one, so a chain
+    /// like `vec![validate, apply]` validates the changes before they are
+    /// applied.
+    pub fn add_module_change_chain(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        xpath: Option<impl ToCStr>,
+        handlers: Vec<ModuleChangeHandler>,
+        priority: Priority,
+        options: SubscriptionOptions,
+    ) -> Result<()> {
+        for (priority, handler) in chain_priorities(priority, handlers)? {
+            self.module_change_subscribe(
+                subscription,
+                &mod_name,
+                xpath.as_ref(),
+                handler,
+                priority,
+                options.clone(),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Subscribe a single callback to changes of several modules.
+    ///
+    /// The callback is shared by the subscriptions of all modules, see
+    /// `ChangeContext::module_name` for the module of an event.
+    pub fn new_modules_change_subscription<F>(
+        &self,
+        mod_names: &[&str],
+        callback: F,
+        priority: impl Into<Priority>,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>>
+    where
+        F: FnMut(&ChangeContext) -> Result<()> + 'static,
+    {
+        let priority = priority.into();
+        let callback = Arc::new(Mutex::new(callback));
+        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
+        for mod_name in mod_names {
+            self.module_change_subscribe(
+                &mut subscr,
+                mod_name,
+                None::<&str>,
+                shared_module_change_callback(&callback),
+                priority,
+                options.clone(),
+            )?;
+        }
+        Ok(subscr)
+    }
+
+    /// Subscribe to changes of all modules.
+    ///
+    /// `SubscriptionOptions::CHANGE_ALL_MODULES` is used if sysrepo supports
+    /// it. Otherwise the callback is subscribed to every implemented module
+    /// with configuration data, so modules installed later are not covered.
+    pub fn new_all_modules_change_subscription<F>(
+        &self,
+        callback: F,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>>
+    where
+        F: FnMut(&ChangeContext) -> Result<()> + 'static,
+    {
+        let callback = Arc::new(Mutex::new(callback));
+        let mut subscr = Subscription::from_raw(self.conn, ptr::null_mut());
+
+        options
+            .validate(SubscriptionKind::ModuleChange)
+            .map_err(|message| Error::with_message(ffi::sr_error_t::SR_ERR_INVAL_ARG, message))?;
+        let all_modules = shared_module_change_callback(&callback);
+        let trampoline = module_change_trampoline(&all_modules);
+        let data = Box::into_raw(Box::new(all_modules));
+        leaks!(track("callback", data as usize));
+        let rc = unsafe {
+            ffi::sr_module_change_subscribe(
+                self.sess,
+                ptr::null(),
+                ptr::null(),
+                trampoline,
+                data as *mut _,
+                0,
+                (options.clone() | SubscriptionOptions::CHANGE_ALL_MODULES).bits(),
+                &mut subscr.subscr,
+            )
+        };
+        let rc = rc as ffi::sr_error_t::Type;
+        match rc {
+            ffi::sr_error_t::SR_ERR_OK => {
+                subscr.push_last_sub_id();
+                return Ok(subscr);
+            }
+            // Older sysrepo requires a module name.
+            ffi::sr_error_t::SR_ERR_INVAL_ARG => {
+                leaks!(untrack("callback", data as usize));
+                drop(unsafe { Box::from_raw(data) });
+            }
+            _ => {
+                leaks!(untrack("callback", data as usize));
+                drop(unsafe { Box::from_raw(data) });
+                return Err(Error::new(rc));
+            }
+        }
+
+        let ctx = self
+            .get_context()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+        let mod_names: Vec<String> = ctx
+            .modules(true)
+            .filter(|module| module.is_implemented() && module.data().any(|n| n.is_config()))
+            .map(|module| module.name().to_owned())
+            .collect();
+        drop(ctx);
+
+        for mod_name in mod_names {
+            self.module_change_subscribe(
+                &mut subscr,
+                &mod_name,
+                None::<&str>,
+                shared_module_change_callback(&callback),
+                Priority::default(),
+                options.clone(),
+            )?;
+        }
+        Ok(subscr)
+    }
+
+    fn module_change_subscribe<F>(
+        &self,
+        subscription: &mut Subscription<'a>,
+        mod_name: impl ToCStr,
+        xpath: Option<impl ToCStr>,
+        callback: F,
+        priority: Priority,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        F: FnMut(&ChangeContext) -> Result<()> + 'static,
+    {
+        options
+            .validate(SubscriptionKind::ModuleChange)
+            .map_err(|message| Error::with_message(ffi::sr_error_t::SR_ERR_INVAL_ARG, message))?;
+        let data = Box::into_raw(Box::new(callback));
+        leaks!(track("callback", data as usize));
+        let mod_name = mod_name.to_cstr()?;
+        let xpath = xpath.as_ref().map(ToCStr::to_cstr).transpose()?;
+
+        let rc = unsafe {
+            ffi::sr_module_change_subscribe(
+                self.sess,
+                mod_name.as_ptr(),
+                xpath.as_deref().map_or(ptr::null(), |p| p.as_ptr()),
+                Some(Session::call_module_change::<F>),
+                data as *mut _,
+                priority.0,
+                options.bits(),
+                &mut subscription.subscr,
+            )
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            subscription.push_last_sub_id();
+            Ok(())
+        }
+    }
+
+    unsafe extern "C" fn call_module_change<F>(
+        sess: *mut ffi::sr_session_ctx_t,
+        sub_id: u32,
+        mod_name: *const c_char,
+        path: *const c_char,
+        event: ffi::sr_event_t::Type,
+        request_id: u32,
+        private_data: *mut c_void,
+    ) -> c_int
+    where
+        F: FnMut(&ChangeContext) -> Result<()>,
+    {
+        stat!(callback(sub_id));
+        let _in_flight = InFlight::enter(sub_id, SubscriptionKind::ModuleChange, sess, mod_name);
+        let callback_ptr = private_data as *mut F;
+        let callback = &mut *callback_ptr;
+
+        let mod_name = CStr::from_ptr(mod_name).to_str().unwrap();
+        let path = if path.is_null() {
+            None
+        } else {
+            Some(CStr::from_ptr(path).to_str().unwrap())
+        };
+        let event = Event::from(event);
+        let conn = ffi::sr_session_get_connection(sess);
+        let conn = ManuallyDrop::new(Connection::from_raw(conn));
+        let sess = ManuallyDrop::new(Session::from_raw(&conn, sess));
+
+        #[cfg(feature = "stats")]
+        let start = Instant::now();
+        let res = callback(&ChangeContext {
+            sess: &sess,
+            sub_id,
+            mod_name,
+            xpath: path,
+            event,
+            request_id,
+        });
+        stat!(event(sub_id, start, res.is_err()));
+
+        res.err()
+            .map(|e| e.errcode)
+            .unwrap_or(ffi::sr_error_t::SR_ERR_OK) as c_int
+    }
+
+    /// Get the whole diff of the current change event.
+    ///
+    /// Only valid in a module change callback.
+    pub fn get_change_diff(&self) -> Result<ChangeDiff<'_>> {
+        let mut diff = ptr::null();
+        stat!(ffi_call("sr_get_change_diff"));
+        let rc = unsafe { ffi::sr_get_change_diff(self.sess, &mut diff) };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(unsafe { ChangeDiff::from_raw(self, diff) })
+        }
+    }
+
+    // TODO: only valid in module_change_subscribe callback
+    pub fn get_changes_iter(&self, xpath: &str) -> Result<Changes> {
+        let xpath = str_to_cstring(xpath)?;
+        let mut it = ptr::null_mut();
+        stat!(ffi_call("sr_get_changes_iter"));
+        let rc = unsafe { ffi::sr_get_changes_iter(self.sess, xpath.as_ptr(), &mut it) };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(unsafe { Changes::from_raw(self, it) })
+        }
+    }
+
+    /// Set the error message reported to the originator when a callback on
+    /// this event session fails.
+    pub fn set_error_message(&self, message: &str) -> Result<()> {
+        let message = str_to_cstring(message)?;
+        let rc = unsafe {
+            ffi::sr_session_set_error_message(self.sess, c"%s".as_ptr(), message.as_ptr())
+        };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Get the errors of the last failed operation of the session.
+    ///
+    /// Useful in callbacks that call other sysrepo operations, to find out
+    /// why they failed before deciding whether to fail the event.
+    pub fn error_info(&self) -> Result<Vec<ErrorInfo>> {
+        Ok(self
+            .raw_errors()?
+            .iter()
+            .map(|err| unsafe { ErrorInfo::from_raw(err) })
+            .collect())
+    }
+
+    fn raw_errors(&self) -> Result<&[ffi::sr_error_info_err_t]> {
+        let mut err_info = ptr::null();
+        let rc = unsafe { ffi::sr_session_get_error(self.sess, &mut err_info) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            return Err(Error::new(rc));
+        }
+        if err_info.is_null() {
+            return Ok(&[]);
+        }
+
+        unsafe {
+            let err_info = &*err_info;
+            if err_info.err.is_null() {
+                Ok(&[])
+            } else {
+                Ok(std::slice::from_raw_parts(
+                    err_info.err,
+                    err_info.err_count as usize,
+                ))
+            }
+        }
+    }
+
+    /// Get the NETCONF errors of the last failed operation of the session.
+    ///
+    /// These are set by subscribers, so are useful after a failed
+    /// `rpc_send` or `apply_changes`. Errors in other formats are skipped.
+    #[cfg(feature = "error-format")]
+    pub fn netconf_errors(&self) -> Result<Vec<NetconfError>> {
+        self.raw_errors()?
+            .iter()
+            .filter(|err| {
+                !err.error_format.is_null()
+                    && unsafe { CStr::from_ptr(err.error_format) }.to_bytes() == b"NETCONF"
+            })
+            .map(|err| unsafe { NetconfError::from_raw(err) })
+            .collect()
+    }
+
+    /// Send event notify tree.
+    pub fn notif_send(&mut self, notif: &DataTree, timeout: Option<Duration>) -> Result<()> {
+        let timeout_ms = timeout.map_or(0, |t| t.as_millis() as u32);
+        let node = notif
+            .reference()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+        stat!(ffi_call("sr_notif_send_tree"));
+        let rc = unsafe {
+            ffi::sr_notif_send_tree(
+                self.sess,
+                node.as_raw(),
+                timeout_ms,
+                timeout.is_some() as c_int,
+            )
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Send RPC.
+    pub fn rpc_send(&mut self, input: DataTree<'_>, timeout: Duration) -> Result<ManagedData<'a>> {
+        self.rpc_send_ref(&input, timeout)
+    }
+
+    /// Send RPC without consuming the input, so that it can be sent again.
+    ///
+    /// Sysrepo may add default nodes to the input while validating it.
+    pub fn rpc_send_ref(
+        &mut self,
+        input: &DataTree<'_>,
+        timeout: Duration,
+    ) -> Result<ManagedData<'a>> {
+        self.rpc_send_detailed(input, timeout)
+            .map_err(|err| err.error)
+    }
+
+    /// Send RPC without consuming the input, returning the errors set by the
+    /// handler and any output produced on failure.
+    pub fn rpc_send_detailed(
+        &mut self,
+        input: &DataTree<'_>,
+        timeout: Duration,
+    ) -> std::result::Result<ManagedData<'a>, RpcError<'a>> {
+        let input = input
+            .reference()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+        let timeout = Session::timeout_ms(timeout, self.timeout);
+
+        let mut output = ptr::null_mut();
+
+        stat!(ffi_call("sr_rpc_send_tree"));
+        let rc = unsafe { ffi::sr_rpc_send_tree(self.sess, input.as_raw(), timeout, &mut output) };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        let output =
+            (!output.is_null()).then(|| unsafe { ManagedData::from_raw(self.conn, output) });
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(RpcError {
+                error: Error::new(rc),
+                errors: self.error_info().unwrap_or_default(),
+                #[cfg(feature = "error-format")]
+                netconf_errors: self.netconf_errors().unwrap_or_default(),
+                output,
+            })
+        } else {
+            output.ok_or(RpcError::from(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL)))
+        }
+    }
+
+    /// Send RPC, waiting at most until the deadline for the reply.
+    pub fn rpc_send_until(
+        &mut self,
+        input: DataTree<'_>,
+        deadline: Instant,
+    ) -> Result<ManagedData<'a>> {
+        self.rpc_send(input, Session::remaining(deadline)?)
+    }
+}
+
+impl fmt::Debug for Session<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("id", &unsafe { ffi::sr_session_get_id(self.sess) })
+            .field("datastore", &self.datastore())
+            .field("connection", &self.conn)
+            .finish()
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        // The sysrepo documentation states that this should be retried until
+        // success.
+        loop {
+            let rc = unsafe { ffi::sr_session_stop(self.sess) };
+            let rc = rc as ffi::sr_error_t::Type;
+            if rc == ffi::sr_error_t::SR_ERR_OK {
+                break;
+            }
+        }
+    }
+}
+
+unsafe impl Send for Session<'_> {}
+
+/// A datastore or module lock held by a session, released on drop.
+pub struct LockGuard<'s, 'a> {
+    sess: &'s mut Session<'a>,
+    module_name: Option<CString>,
+    locked: bool,
+}
+
+impl LockGuard<'_, '_> {
+    /// Release the lock, reporting any error.
+    pub fn unlock(mut self) -> Result<()> {
+        self.release()
+    }
+
+    fn release(&mut self) -> Result<()> {
+        if !self.locked {
+            return Ok(());
+        }
+        self.locked = false;
+
+        let module_name_ptr = self
+            .module_name
+            .as_deref()
+            .map_or(ptr::null(), |n| n.as_ptr());
+        stat!(ffi_call("sr_unlock"));
+        let rc = unsafe { ffi::sr_unlock(self.sess.sess, module_name_ptr) };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            Err(Error::new(rc))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl<'a> Deref for LockGuard<'_, 'a> {
+    type Target = Session<'a>;
+
+    fn deref(&self) -> &Session<'a> {
+        self.sess
+    }
+}
+
+impl<'a> DerefMut for LockGuard<'_, 'a> {
+    fn deref_mut(&mut self) -> &mut Session<'a> {
+        self.sess
+    }
+}
+
+impl Drop for LockGuard<'_, '_> {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
+
+/// Operational data pushed with `Session::push_oper_data`, retracted when
+/// dropped.
+pub struct OperPushGuard<'s, 'a> {
+    sess: &'s mut Session<'a>,
+    paths: Vec<CString>,
+    timeout: Duration,
+    pushed: bool,
+}
+
+impl OperPushGuard<'_, '_> {
+    /// Retract the data, reporting any error.
+    pub fn release(mut self) -> Result<()> {
+        self.retract()
+    }
+
+    fn retract(&mut self) -> Result<()> {
+        if !self.pushed {
+            return Ok(());
+        }
+        self.pushed = false;
+
+        for path in &self.paths {
+            stat!(ffi_call("sr_discard_items"));
+            let rc = unsafe { ffi::sr_discard_items(self.sess.sess, path.as_ptr()) };
+            let rc = rc as ffi::sr_error_t::Type;
+            if rc != ffi::sr_error_t::SR_ERR_OK {
+                let _ = self.sess.discard_changes();
+                return Err(Error::new(rc));
+            }
+        }
+        if let Err(e) = self.sess.apply_changes(self.timeout) {
+            let _ = self.sess.discard_changes();
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Deref for OperPushGuard<'_, 'a> {
+    type Target = Session<'a>;
+
+    fn deref(&self) -> &Session<'a> {
+        self.sess
+    }
+}
+
+impl<'a> DerefMut for OperPushGuard<'_, 'a> {
+    fn deref_mut(&mut self) -> &mut Session<'a> {
+        self.sess
+    }
+}
+
+impl Drop for OperPushGuard<'_, '_> {
+    fn drop(&mut self) {
+        let _ = self.retract();
+    }
+}
+
+/// A token to cancel a retried update, shared by cloning it.
+///
+/// Only `Session::update_cancellable` takes a token, and checks it between
+/// the steps of the update. sysrepo calls cannot be interrupted, so a read,
+/// apply or RPC already waiting in sysrepo still runs until its timeout; keep
+/// the timeouts short where shutdown must be prompt.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the updates using the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            Err(Error::new(ffi::sr_error_t::SR_ERR_OPERATION_FAILED))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// A single error of a failed session operation.
+#[derive(Clone, Debug)]
+pub struct ErrorInfo {
+    pub error: Error,
+    pub message: Option<String>,
+    /// Format of the additional error data, such as `NETCONF`.
+    pub format: Option<String>,
+}
+
+impl ErrorInfo {
+    unsafe fn from_raw(err: &ffi::sr_error_info_err_t) -> Self {
+        let opt_string = |s: *const c_char| {
+            (!s.is_null()).then(|| unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned())
+        };
+        ErrorInfo {
+            error: Error::new(err.err_code),
+            message: opt_string(err.message),
+            format: opt_string(err.error_format),
+        }
+    }
+}
+
+/// A failed RPC, as returned by `Session::rpc_send_detailed`.
+#[derive(Debug)]
+pub struct RpcError<'a> {
+    pub error: Error,
+    /// The errors set by the RPC handler, if any.
+    pub errors: Vec<ErrorInfo>,
+    /// The NETCONF errors set by the RPC handler, if any.
+    #[cfg(feature = "error-format")]
+    pub netconf_errors: Vec<NetconfError>,
+    /// The output produced before the failure, if any.
+    pub output: Option<ManagedData<'a>>,
+}
+
+impl From<Error> for RpcError<'_> {
+    fn from(error: Error) -> Self {
+        RpcError {
+            error,
+            errors: Vec::new(),
+            #[cfg(feature = "error-format")]
+            netconf_errors: Vec::new(),
+            output: None,
+        }
+    }
+}
+
+impl fmt::Display for RpcError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.errors.iter().find_map(|err| err.message.as_deref()) {
+            Some(message) => write!(f, "{}: {}", self.error, message),
+            None => write!(f, "{}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for RpcError<'_> {}
+
+/// A NETCONF error, as set by a subscriber.
+#[cfg(feature = "error-format")]
+#[derive(Clone, Debug)]
+pub struct NetconfError {
+    pub error_type: String,
+    pub tag: String,
+    pub app_tag: Option<String>,
+    pub path: Option<String>,
+    pub message: String,
+    /// The error-info elements and their values.
+    pub info: Vec<(String, String)>,
+}
+
+#[cfg(feature = "error-format")]
+impl NetconfError {
+    unsafe fn from_raw(err: *const ffi::sr_error_info_err_t) -> Result<Self> {
+        let mut error_type = ptr::null();
+        let mut tag = ptr::null();
+        let mut app_tag = ptr::null();
+        let mut path = ptr::null();
+        let mut message = ptr::null();
+        let mut info_elements = ptr::null_mut();
+        let mut info_values = ptr::null_mut();
+        let mut info_count = 0;
+
+        let rc = unsafe {
+            ffi::sr_err_get_netconf_error(
+                err,
+                &mut error_type,
+                &mut tag,
+                &mut app_tag,
+                &mut path,
+                &mut message,
+                &mut info_elements,
+                &mut info_values,
+                &mut info_count,
+            )
+        };
+        let rc = rc as ffi::sr_error_t::Type;
+        if rc != ffi::sr_error_t::SR_ERR_OK {
+            return Err(Error::new(rc));
+        }
+
+        let to_string =
+            |s: *const c_char| unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned();
+        let to_opt_string = |s: *const c_char| (!s.is_null()).then(|| to_string(s));
+        let info = (0..info_count as usize)
+            .map(|i| unsafe {
+                (
+                    to_string(*info_elements.add(i)),
+                    to_string(*info_values.add(i)),
+                )
+            })
+            .collect();
+        // Only the arrays are allocated, the strings belong to the error.
+        unsafe {
+            libc::free(info_elements as *mut _);
+            libc::free(info_values as *mut _);
+        }
+
+        Ok(NetconfError {
+            error_type: to_string(error_type),
+            tag: to_string(tag),
+            app_tag: to_opt_string(app_tag),
+            path: to_opt_string(path),
+            message: to_string(message),
+            info,
+        })
+    }
+}
+
+/// A data request, created with `Session::get`.
+pub struct GetDataRequest<'s, 'a> {
+    sess: &'s Session<'a>,
+    // Checked when the data are fetched.
+    xpath: Result<String>,
+    max_depth: u32,
+    timeout: Duration,
+    options: GetOptions,
+    fields: Option<String>,
+    with_defaults: Option<WithDefaults>,
+    datastore: Option<Datastore>,
+}
+
+impl<'a> GetDataRequest<'_, 'a> {
+    /// Limit the depth of the selected subtrees, as the RESTCONF `depth`
+    /// parameter. 1 returns no descendant nodes, 0 is unlimited.
+    pub fn max_depth(mut self, max_depth: u32) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Use these options instead of the default options of the session.
+    pub fn options(mut self, options: GetOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Only select the descendants given by a RESTCONF `fields` expression,
+    /// such as `name;config(mtu;enabled)`.
+    ///
+    /// The XPath of the request must be a simple path for this.
+    pub fn fields(mut self, fields: &str) -> Self {
+        self.fields = Some(fields.to_owned());
+        self
+    }
+
+    /// Include the origin of the nodes, only available on the operational
+    /// datastore. The other options are kept.
+    pub fn with_origin(mut self) -> Self {
+        self.options |= GetOptions::WITH_ORIGIN;
+        self
+    }
+
+    /// Read another datastore than the one of the session.
+    ///
+    /// The session is switched to it for the read, so its timeouts, options
+    /// and NACM user still apply.
+    pub fn datastore(mut self, datastore: Datastore) -> Self {
+        self.datastore = Some(datastore);
+        self
+    }
+
+    /// The with-defaults mode to print data with. Only affects `print`.
+    pub fn with_defaults(mut self, with_defaults: WithDefaults) -> Self {
+        self.with_defaults = Some(with_defaults);
+        self
+    }
+
+    /// Retrieve the data.
+    pub fn fetch(&self) -> Result<ManagedData<'a>> {
+        let xpath = self.xpath.clone()?;
+        let xpath = match &self.fields {
+            Some(fields) => fields_xpath(&xpath, fields)?,
+            None => xpath,
+        };
+        let max_depth = NonZero::new(self.max_depth);
+        let get = |sess: &Session<'a>| {
+            sess.get_data(&xpath, max_depth, self.timeout, self.options.clone())
+        };
+        match &self.datastore {
+            Some(datastore) => self.sess.on_datastore(datastore.clone(), get),
+            None => get(self.sess),
+        }
+    }
+
+    /// Retrieve the data and print it, following the with-defaults mode.
+    pub fn print(&self, format: DataFormat) -> Result<String> {
+        let data = self.fetch()?;
+        let flags = DataPrinterFlags::WITH_SIBLINGS
+            | self
+                .with_defaults
+                .map_or(DataPrinterFlags::empty(), WithDefaults::printer_flags);
+        data.tree()
+            .print_string(format, flags)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))
+    }
+}
+
+/// The with-defaults modes of RFC 6243.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WithDefaults {
+    ReportAll,
+    ReportAllTagged,
+    Trim,
+    Explicit,
+}
+
+impl WithDefaults {
+    fn printer_flags(self) -> DataPrinterFlags {
+        match self {
+            WithDefaults::ReportAll => DataPrinterFlags::WD_ALL,
+            WithDefaults::ReportAllTagged => {
+                DataPrinterFlags::from_bits_retain(yang::ffi::LYD_PRINT_WD_ALL_TAG)
+            }
+            WithDefaults::Trim => DataPrinterFlags::WD_TRIM,
+            WithDefaults::Explicit => DataPrinterFlags::WD_EXPLICIT,
+        }
+    }
+}
+
+/// Convert a RESTCONF `fields` expression to an XPath union below `xpath`.
+fn fields_xpath(xpath: &str, fields: &str) -> Result<String> {
+    let base = xpath.trim_end_matches('/');
+    Ok(fields_paths(fields)?
+        .iter()
+        .map(|path| format!("{}/{}", base, path))
+        .collect::<Vec<_>>()
+        .join(" | "))
+}
+
+fn fields_paths(fields: &str) -> Result<Vec<String>> {
+    let inval = || Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+
+    // Split on the top-level `;`.
+    let mut items = Vec::new();
+    let mut depth = 0usize;
+    let mut start = 0;
+    for (i, c) in fields.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.checked_sub(1).ok_or_else(inval)?,
+            ';' if depth == 0 => {
+                items.push(&fields[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(inval());
+    }
+    items.push(&fields[start..]);
+
+    let mut paths = Vec::new();
+    for item in items {
+        match item.split_once('(') {
+            Some((path, rest)) => {
+                let inner = rest.strip_suffix(')').ok_or_else(inval)?;
+                if path.is_empty() {
+                    return Err(inval());
+                }
+                for sub in fields_paths(inner)? {
+                    paths.push(format!("{}/{}", path, sub));
+                }
+            }
+            None if item.is_empty() => return Err(inval()),
+            None => paths.push(item.to_owned()),
+        }
+    }
+    Ok(paths)
+}
+
+pub struct ManagedData<'a> {
+    conn: &'a Connection,
+    ctx: ManuallyDrop<Context>,
+    data: *mut ffi::sr_data_t,
+}
+
+impl<'a> ManagedData<'a> {
+    pub unsafe fn from_raw(conn: &'a Connection, data: *mut ffi::sr_data_t) -> Self {
+        debug_assert!(!data.is_null());
+        // Aquire the context and then drop it right away.
+        // SAFETY: This pointer will be valid as the context read lock continues
+        // to be held by the data tree.
+        let ctx = unsafe {
+            stat!(context_acquired());
+            let ctx = ffi::sr_acquire_context(conn.conn) as *mut _;
+            ffi::sr_release_context(conn.conn);
+            ManuallyDrop::new(Context::from_raw(&(), ctx))
+        };
+        leaks!(track("ManagedData", data as usize));
+        Self { conn, ctx, data }
+    }
+
+    pub fn into_raw(self) -> *mut ffi::sr_data_t {
+        leaks!(untrack("ManagedData", self.data as usize));
+        self.data
+    }
+
+    /// The connection the data was retrieved on.
+    pub fn connection(&self) -> &'a Connection {
+        self.conn
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    pub fn tree(&self) -> ManagedDataTree<'_> {
+        let tree = unsafe { ManuallyDrop::new(DataTree::from_raw(&self.ctx, (*self.data).tree)) };
+        ManagedDataTree { tree }
+    }
+
+    /// The data tree, to be modified in place.
+    ///
+    /// If the first node of the tree changes, the data are updated once the
+    /// returned tree is dropped.
+    pub fn tree_mut(&mut self) -> ManagedDataTreeMut<'_> {
+        let root = unsafe { &mut (*self.data).tree };
+        let tree = unsafe { ManuallyDrop::new(DataTree::from_raw(&self.ctx, *root)) };
+        ManagedDataTreeMut {
+            conn: self.conn,
+            tree,
+            root,
+        }
+    }
+}
+
+impl fmt::Debug for ManagedData<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cid = unsafe { ffi::sr_get_cid(self.conn.conn) };
+        f.debug_struct("ManagedData").field("cid", &cid).finish()
+    }
+}
+
+impl Drop for ManagedData<'_> {
+    fn drop(&mut self) {
+        leaks!(untrack("ManagedData", self.data as usize));
+        unsafe {
+            ffi::sr_release_data(self.data);
+        }
+    }
+}
+
+pub struct ManagedDataTree<'a> {
+    tree: ManuallyDrop<DataTree<'a>>,
+}
+
+impl<'a> Deref for ManagedDataTree<'a> {
+    type Target = DataTree<'a>;
+
+    fn deref(&self) -> &DataTree<'a> {
+        &self.tree
+    }
+}
+
+impl<'a> Data<'a> for ManagedDataTree<'a> {
+    fn tree(&self) -> &DataTree<'a> {
+        &self.tree
+    }
+
+    fn raw(&self) -> *mut yang::ffi::lyd_node {
+        self.tree.raw()
+    }
+}
+
+/// A data tree of `ManagedData`, borrowed mutably.
+///
+/// The tree is only modified through its own methods so that it can never be
+/// replaced by a tree of another context.
+pub struct ManagedDataTreeMut<'a> {
+    conn: &'a Connection,
+    tree: ManuallyDrop<DataTree<'a>>,
+    root: &'a mut *mut yang::ffi::lyd_node,
+}
+
+impl<'a> ManagedDataTreeMut<'a> {
+    /// Create a node or modify an existing one, along with any missing
+    /// parents, see [`DataTree::new_path`].
+    pub fn new_path(&mut self, path: &str, value: Option<&str>) -> Result<()> {
+        self.tree
+            .new_path(path, value, false)
+            .map(|_| ())
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))
+    }
+
+    /// Remove the node at a path along with its descendants.
+    pub fn remove(&mut self, path: &str) -> Result<()> {
+        remove_path(&mut self.tree, path)
+    }
+
+    /// Merge a copy of a tree of the same context into the tree.
+    ///
+    /// Fails with `SR_ERR_INVAL_ARG` if the source tree belongs to another
+    /// context.
+    pub fn merge(&mut self, source: &DataTree<'a>) -> Result<()> {
+        let node = source.raw();
+        if node.is_null() {
+            return Ok(());
+        }
+        let ctx: *const yang::ffi::ly_ctx = unsafe {
+            stat!(context_acquired());
+            let ctx = ffi::sr_acquire_context(self.conn.conn).cast();
+            ffi::sr_release_context(self.conn.conn);
+            ctx
+        };
+        // Opaque nodes have no schema but record their context themselves.
+        let source_ctx = unsafe {
+            match (*node).schema.as_ref() {
+                Some(schema) => (*schema.module).ctx as *const _,
+                None => (*(node as *const yang::ffi::lyd_node_opaq)).ctx,
+            }
+        };
+        if source_ctx != ctx {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+        self.tree
+            .merge(source)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))
+    }
+}
+
+impl<'a> Deref for ManagedDataTreeMut<'a> {
+    type Target = DataTree<'a>;
+
+    fn deref(&self) -> &DataTree<'a> {
+        &self.tree
+    }
+}
+
+impl<'a> Data<'a> for ManagedDataTreeMut<'a> {
+    fn tree(&self) -> &DataTree<'a> {
+        &self.tree
+    }
+
+    fn raw(&self) -> *mut yang::ffi::lyd_node {
+        self.tree.raw()
+    }
+}
+
+impl Drop for ManagedDataTreeMut<'_> {
+    fn drop(&mut self) {
+        *self.root = self.tree.raw();
+    }
+}
+
+/// Remove the node at a path along with its descendants, failing with
+/// `SR_ERR_NOT_FOUND` if there is none.
+///
+/// Unlike `DataTree::remove`, the tree stays valid if the node is its first
+/// top-level node.
+fn remove_path(tree: &mut DataTree, path: &str) -> Result<()> {
+    let node = tree
+        .find_path(path)
+        .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?
+        .as_raw();
+    let ctx = tree.context();
+    let mut first = std::mem::replace(tree, DataTree::new(ctx)).into_raw();
+    unsafe {
+        if node == first {
+            first = (*node).next;
+        }
+        yang::ffi::lyd_free_tree(node);
+        *tree = DataTree::from_raw(ctx, first);
+    }
+    Ok(())
+}
+
+/// The node referenced by a leafref, as found by [`Session::resolve_leafref`].
+#[cfg(feature = "yang3")]
+pub enum LeafrefTarget<'n, 'a> {
+    /// The target is part of the same tree as the leafref node.
+    Local(DataNodeRef<'n>),
+    /// The target was fetched from the datastore.
+    Fetched(ManagedData<'a>),
+}
+
+pub struct Subscription<'a> {
+    subscr: *mut ffi::sr_subscription_ctx_t,
+    _conn: &'a Connection,
+    sub_ids: Vec<u32>,
+}
+
+impl<'a> Subscription<'a> {
+    pub fn from_raw(conn: &'a Connection, subscr: *mut ffi::sr_subscription_ctx_t) -> Self {
+        Self {
+            _conn: conn,
+            subscr,
+            sub_ids: Vec::new(),
+        }
+    }
+
+    /// IDs of the subscriptions added through this wrapper.
+    pub fn sub_ids(&self) -> &[u32] {
+        &self.sub_ids
+    }
+
+    /// Statistics of the callbacks of the subscriptions added through this
+    /// wrapper, by subscription ID.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> BTreeMap<u32, stats::SubscriptionStats> {
+        stats::subscription_stats(&self.sub_ids)
+    }
+
+    /// Stop delivering events to the subscriptions added through this
+    /// wrapper, wait for the callbacks already running to return and
+    /// unsubscribe.
+    ///
+    /// Fails with `SR_ERR_TIME_OUT` if callbacks are still running after
+    /// `timeout`, in which case the subscriptions are removed regardless. A
+    /// timeout too large to be represented waits for as long as needed. It
+    /// must not be called from one of the callbacks, which would wait for
+    /// itself.
+    ///
+    /// Only callbacks that have started are waited for. An event sysrepo was
+    /// dispatching when the subscriptions were suspended may still reach its
+    /// callback afterwards, which unsubscribing then waits for.
+    pub fn drain(self, timeout: Duration) -> Result<()> {
+        for id in &self.sub_ids {
+            let rc = unsafe { ffi::sr_subscription_suspend(self.subscr, *id) };
+            let rc = rc as ffi::sr_error_t::Type;
+            // Subscriptions that are already suspended fail with
+            // SR_ERR_UNSUPPORTED and stay so.
+            if rc != ffi::sr_error_t::SR_ERR_OK && rc != ffi::sr_error_t::SR_ERR_UNSUPPORTED {
+                return Err(Error::new(rc));
+            }
+        }
+
+        let deadline = Instant::now().checked_add(timeout);
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        while self.sub_ids.iter().any(|id| in_flight.contains_key(id)) {
+            let Some(deadline) = deadline else {
+                in_flight = IN_FLIGHT_DONE.wait(in_flight).unwrap();
+                continue;
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(Error::new(ffi::sr_error_t::SR_ERR_TIME_OUT));
+            }
+            in_flight = IN_FLIGHT_DONE.wait_timeout(in_flight, remaining).unwrap().0;
+        }
+        Ok(())
+    }
+
+    fn push_last_sub_id(&mut self) {
+        self.sub_ids
+            .push(unsafe { ffi::sr_subscription_get_last_sub_id(self.subscr) });
+    }
+}
+
+impl fmt::Debug for Subscription<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("sub_ids", &self.sub_ids)
+            .finish()
+    }
+}
+
+impl Drop for Subscription<'_> {
+    fn drop(&mut self) {
+        // The sysrepo documentation states that this should be retried until
+        // success.
+        loop {
+            let rc = unsafe { ffi::sr_unsubscribe(self.subscr) };
+            let rc = rc as ffi::sr_error_t::Type;
+            if rc == ffi::sr_error_t::SR_ERR_OK {
+                break;
+            }
+        }
+
+        stat!(unsubscribed(&self.sub_ids));
+    }
+}
+
+unsafe impl Send for Subscription<'_> {}
+unsafe impl Sync for Subscription<'_> {}
+
+/// Callbacks running, by subscription ID.
+static IN_FLIGHT: Mutex<BTreeMap<u32, usize>> = Mutex::new(BTreeMap::new());
+static IN_FLIGHT_DONE: Condvar = Condvar::new();
+
+/// A callback running on a thread, with its event session and the module of
+/// the event, if any.
+#[derive(Clone, Copy)]
+struct Callback {
+    kind: SubscriptionKind,
+    sess: *mut ffi::sr_session_ctx_t,
+    module: *const c_char,
+}
+
+thread_local! {
+    /// The callback running on this thread.
+    static CALLBACK: Cell<Option<Callback>> = const { Cell::new(None) };
+}
+
+/// Marks a callback as running until dropped.
+struct InFlight {
+    sub_id: u32,
+    outer: Option<Callback>,
+}
+
+impl InFlight {
+    fn enter(
+        sub_id: u32,
+        kind: SubscriptionKind,
+        sess: *mut ffi::sr_session_ctx_t,
+        module: *const c_char,
+    ) -> Self {
+        *IN_FLIGHT.lock().unwrap().entry(sub_id).or_default() += 1;
+        let callback = Callback { kind, sess, module };
+        let outer = CALLBACK.with(|outer| outer.replace(Some(callback)));
+        InFlight { sub_id, outer }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        CALLBACK.with(|callback| callback.set(self.outer));
+        let mut in_flight = IN_FLIGHT.lock().unwrap();
+        if let Some(count) = in_flight.get_mut(&self.sub_id) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&self.sub_id);
+            }
+        }
+        IN_FLIGHT_DONE.notify_all();
+    }
+}
+
+/// The diff of a change event, owned by the event session.
+pub struct ChangeDiff<'a> {
+    ctx: ManuallyDrop<Context>,
+    diff: *const yang::ffi::lyd_node,
+    _sess: PhantomData<&'a Session<'a>>,
+}
+
+impl<'a> ChangeDiff<'a> {
+    unsafe fn from_raw(sess: &'a Session<'a>, diff: *const yang::ffi::lyd_node) -> Self {
+        // Aquire the context and then drop it right away.
+        // SAFETY: This pointer will be valid as the context read lock continues
+        // to be held for the change event.
+        let ctx = unsafe {
+            stat!(context_acquired());
+            let ctx = ffi::sr_acquire_context(sess.conn.conn);
+            ffi::sr_release_context(sess.conn.conn);
+            ManuallyDrop::new(Context::from_raw(&(), ctx as *mut _))
+        };
+        Self {
+            ctx,
+            diff,
+            _sess: PhantomData,
+        }
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.ctx
+    }
+
+    /// The diff as a libyang diff tree, with the operations in metadata.
+    pub fn tree(&self) -> ManagedDataTree<'_> {
+        let tree = unsafe { ManuallyDrop::new(DataTree::from_raw(&self.ctx, self.diff as *mut _)) };
+        ManagedDataTree { tree }
+    }
+
+    /// Render the diff as a NETCONF `<config>` payload for `edit-config`.
+    ///
+    /// Every created, deleted or replaced node gets an `ietf-netconf`
+    /// operation attribute. Moves of user-ordered entries are rendered as
+    /// plain replaces, without their position.
+    #[cfg(feature = "yang3")]
+    pub fn to_edit_config(&self) -> Result<String> {
+        let ly_err = |_| Error::new(ffi::sr_error_t::SR_ERR_LY);
+        let netconf = self
+            .ctx
+            .get_module_implemented("ietf-netconf")
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+        let tree = self.tree().duplicate().map_err(ly_err)?;
+
+        let nodes = tree
+            .traverse()
+            .map(|node| {
+                let operation = node
+                    .meta()
+                    .find(|meta| meta.name() == "operation")
+                    .map(|meta| meta.value().to_owned());
+                (node.as_raw(), operation)
+            })
+            .collect::<Vec<_>>();
+        let name = str_to_cstring("operation")?;
+        for (node, operation) in nodes {
+            unsafe {
+                // Drop the diff metadata, such as the original values.
+                yang::ffi::lyd_free_meta_siblings((*node).meta);
+            }
+            let Some(operation) = operation.filter(|op| op != "none") else {
+                continue;
+            };
+            let operation = str_to_cstring(&operation)?;
+            let ret = unsafe {
+                yang::ffi::lyd_new_meta(
+                    ptr::null(),
+                    node,
+                    netconf.as_raw(),
+                    name.as_ptr(),
+                    operation.as_ptr(),
+                    0,
+                    ptr::null_mut(),
+                )
+            };
+            if ret != yang::ffi::LY_ERR::LY_SUCCESS {
+                return Err(Error::new(ffi::sr_error_t::SR_ERR_LY));
+            }
+        }
+
+        let config = tree
+            .print_string(DataFormat::XML, DataPrinterFlags::WITH_SIBLINGS)
+            .map_err(ly_err)?;
+        Ok(format!(
+            "<config xmlns=\"urn:ietf:params:xml:ns:netconf:base:1.0\">{}</config>",
+            config
+        ))
+    }
+}
+
+/// The event a module change callback is called for.
+pub struct ChangeContext<'s> {
+    sess: &'s Session<'s>,
+    sub_id: u32,
+    mod_name: &'s str,
+    xpath: Option<&'s str>,
+    event: Event,
+    request_id: u32,
+}
+
+impl<'s> ChangeContext<'s> {
+    /// The event session, whose changes are being made.
+    pub fn session(&self) -> &'s Session<'s> {
+        self.sess
+    }
+
+    pub fn sub_id(&self) -> u32 {
+        self.sub_id
+    }
+
+    pub fn module_name(&self) -> &'s str {
+        self.mod_name
+    }
+
+    /// The XPath of the subscription, if it has one.
+    pub fn xpath(&self) -> Option<&'s str> {
+        self.xpath
+    }
+
+    pub fn event(&self) -> Event {
+        self.event.clone()
+    }
+
+    /// The ID of the change request, shared by all its events.
+    pub fn request_id(&self) -> u32 {
+        self.request_id
+    }
+
+    /// The changes of the event selected by the subscription, that is all
+    /// changes of its XPath or of the whole module.
+    pub fn changes(&self) -> Result<Changes<'s>> {
+        let xpath = match self.xpath {
+            Some(xpath) => format!("{}//.", xpath),
+            None => format!("/{}:*//.", self.mod_name),
+        };
+        self.sess.get_changes_iter(&xpath)
+    }
+}
+
+/// The event an RPC callback is called for.
+pub struct RpcContext<'s> {
+    sess: &'s Session<'s>,
+    sub_id: u32,
+    op_path: &'s str,
+    input: &'s DataTree<'s>,
+    event: Event,
+    request_id: u32,
+}
+
+impl<'s> RpcContext<'s> {
+    /// The event session, of the originator of the RPC.
+    pub fn session(&self) -> &'s Session<'s> {
+        self.sess
+    }
+
+    pub fn sub_id(&self) -> u32 {
+        self.sub_id
+    }
+
+    /// The simple path of the RPC or action.
+    pub fn op_path(&self) -> &'s str {
+        self.op_path
+    }
+
+    pub fn input(&self) -> &'s DataTree<'s> {
+        self.input
+    }
+
+    pub fn event(&self) -> Event {
+        self.event.clone()
+    }
+
+    pub fn request_id(&self) -> u32 {
+        self.request_id
+    }
+}
+
+/// The event a notification callback is called for.
+pub struct NotifContext<'s> {
+    sess: &'s Session<'s>,
+    sub_id: u32,
+    notif_type: NotificationType,
+    timestamp: SystemTime,
+}
+
+impl<'s> NotifContext<'s> {
+    /// The event session, of the originator of the notification.
+    pub fn session(&self) -> &'s Session<'s> {
+        self.sess
+    }
+
+    pub fn sub_id(&self) -> u32 {
+        self.sub_id
+    }
+
+    pub fn notif_type(&self) -> NotificationType {
+        self.notif_type.clone()
+    }
+
+    /// The time the notification was sent.
+    pub fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+}
+
+pub struct Changes<'a> {
+    sess: &'a Session<'a>,
+    ctx: ManuallyDrop<Context>,
+    iter: *mut ffi::sr_change_iter_t,
+}
+
+impl<'a> Changes<'a> {
+    pub unsafe fn from_raw(sess: &'a Session<'a>, iter: *mut ffi::sr_change_iter_t) -> Self {
+        // Aquire the context and then drop it right away.
+        // SAFETY: This pointer will be valid as the context read lock continues
+        // to be held by the iterator.
+        let ctx = unsafe {
+            stat!(context_acquired());
+            let ctx = ffi::sr_acquire_context(sess.conn.conn);
+            ffi::sr_release_context(sess.conn.conn);
+            ManuallyDrop::new(Context::from_raw(&(), ctx as *mut _))
+        };
+        Self { sess, ctx, iter }
+    }
+
+    pub fn iter<'b>(&'b self) -> ChangesIter<'b> {
+        ChangesIter {
+            sess: self.sess.sess,
+            ctx: &self.ctx,
+            iter: self.iter,
+        }
+    }
+
+    /// The whole diff of the change event as a libyang diff tree, with the
+    /// operations in metadata.
+    ///
+    /// Unlike the iterator, the diff is not limited to the XPath the changes
+    /// were selected with. It can be printed in any format or processed with
+    /// the libyang diff functions.
+    pub fn as_diff_tree(&self) -> Result<ChangeDiff<'a>> {
+        self.sess.get_change_diff()
+    }
+}
+
+impl Drop for Changes<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::sr_free_change_iter(self.iter);
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Changes<'_> {
+    type Item = Result<(ManagedDataTree<'a>, ChangeOperation<'a>)>;
+    type IntoIter = ChangesIter<'a>;
+
+    fn into_iter(self) -> ChangesIter<'a> {
+        self.iter()
+    }
+}
+
+pub struct ChangesIter<'a> {
+    sess: *mut ffi::sr_session_ctx_t,
+    ctx: &'a Context,
+    iter: *mut ffi::sr_change_iter_t,
+}
+
+impl<'a> Iterator for ChangesIter<'a> {
+    // TODO: maybe should be a wrapper around a DataNodeRef instead
+    type Item = Result<(ManagedDataTree<'a>, ChangeOperation<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut oper = 0;
+        let mut node = ptr::null();
+        let mut prev_value = ptr::null();
+        let mut prev_list_keys = ptr::null();
+        let mut prev_default_flag = 0;
+
+        stat!(ffi_call("sr_get_change_tree_next"));
+        let rc = unsafe {
+            ffi::sr_get_change_tree_next(
+                self.sess,
+                self.iter,
+                &mut oper,
+                &mut node,
+                &mut prev_value,
+                &mut prev_list_keys,
+                &mut prev_default_flag,
+            )
+        };
+
+        let rc = rc as ffi::sr_error_t::Type;
+        match rc {
+            ffi::sr_error_t::SR_ERR_OK => {
+                let node = unsafe { DataTree::from_raw(&self.ctx, node as *mut _) };
+                let node = ManagedDataTree {
+                    tree: ManuallyDrop::new(node),
+                };
+                let oper = unsafe {
+                    change_operation(oper, prev_value, prev_list_keys, prev_default_flag)
+                };
+                Some(oper.map(|oper| (node, oper)))
+            }
+            ffi::sr_error_t::SR_ERR_NOT_FOUND => None,
+            _ => Some(Err(Error::new(rc))),
+        }
+    }
+}
+
+/// Convert the operation of a change returned by `sr_get_change_tree_next`.
+///
+/// Unknown operations, from a newer sysrepo, are returned as
+/// `ChangeOperation::Other`.
+unsafe fn change_operation<'a>(
+    oper: ffi::sr_change_oper_t::Type,
+    prev_value: *const c_char,
+    prev_list_keys: *const c_char,
+    prev_default_flag: c_int,
+) -> Result<ChangeOperation<'a>> {
+    let to_str = |s: *const c_char| -> Result<&'a str> {
+        if s.is_null() {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL));
+        }
+        unsafe { CStr::from_ptr(s) }
+            .to_str()
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))
+    };
+
+    Ok(match oper {
+        ffi::sr_change_oper_t::SR_OP_CREATED if !prev_value.is_null() => {
+            ChangeOperation::CreatedLeafListUserOrdered {
+                previous_value: to_str(prev_value)?,
+            }
+        }
+        ffi::sr_change_oper_t::SR_OP_CREATED if !prev_list_keys.is_null() => {
+            ChangeOperation::CreatedListUserOrdered {
+                previous_key: to_str(prev_list_keys)?,
+            }
+        }
+        ffi::sr_change_oper_t::SR_OP_CREATED => ChangeOperation::Created,
+        ffi::sr_change_oper_t::SR_OP_MODIFIED => ChangeOperation::Modified {
+            previous_value: to_str(prev_value)?,
+            previous_default: prev_default_flag != 0,
+        },
+        ffi::sr_change_oper_t::SR_OP_DELETED => ChangeOperation::Deleted,
+        ffi::sr_change_oper_t::SR_OP_MOVED if !prev_value.is_null() => {
+            ChangeOperation::MovedLeafListUserOrdered {
+                previous_value: to_str(prev_value)?,
+            }
+        }
+        ffi::sr_change_oper_t::SR_OP_MOVED if !prev_list_keys.is_null() => {
+            ChangeOperation::MovedListUserOrdered {
+                previous_key: to_str(prev_list_keys)?,
+            }
+        }
+        _ => ChangeOperation::Other(oper),
+    })
+}
+
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum ChangeOperation<'a> {
+    Created,
+    CreatedLeafListUserOrdered {
+        previous_value: &'a str,
+    },
+    CreatedListUserOrdered {
+        previous_key: &'a str,
+    },
+    Modified {
+        previous_value: &'a str,
+        previous_default: bool,
+    },
+    Deleted,
+    MovedLeafListUserOrdered {
+        previous_value: &'a str,
+    },
+    MovedListUserOrdered {
+        previous_key: &'a str,
+    },
+    Other(ffi::sr_change_oper_t::Type),
+}
+
+/// The value of a single data node, as used by the values API.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    List,
+    Container,
+    ContainerPresence,
+    LeafEmpty,
+    Notification,
+    Binary(String),
+    Bits(String),
+    Bool(bool),
+    Decimal64(f64),
+    Enum(String),
+    IdentityRef(String),
+    InstanceId(String),
+    Int8(i8),
+    Int16(i16),
+    Int32(i32),
+    Int64(i64),
+    String(String),
+    Uint8(u8),
+    Uint16(u16),
+    Uint32(u32),
+    Uint64(u64),
+    AnyXml(String),
+    AnyData(String),
+}
+
+impl Value {
+    /// The string representation of the value, if the node has one.
+    pub fn as_string(&self) -> Option<String> {
+        match self {
+            Value::List
+            | Value::Container
+            | Value::ContainerPresence
+            | Value::LeafEmpty
+            | Value::Notification => None,
+            Value::Binary(s)
+            | Value::Bits(s)
+            | Value::Enum(s)
+            | Value::IdentityRef(s)
+            | Value::InstanceId(s)
+            | Value::String(s)
+            | Value::AnyXml(s)
+            | Value::AnyData(s) => Some(s.clone()),
+            Value::Bool(v) => Some(v.to_string()),
+            Value::Decimal64(v) => Some(v.to_string()),
+            Value::Int8(v) => Some(v.to_string()),
+            Value::Int16(v) => Some(v.to_string()),
+            Value::Int32(v) => Some(v.to_string()),
+            Value::Int64(v) => Some(v.to_string()),
+            Value::Uint8(v) => Some(v.to_string()),
+            Value::Uint16(v) => Some(v.to_string()),
+            Value::Uint32(v) => Some(v.to_string()),
+            Value::Uint64(v) => Some(v.to_string()),
+        }
+    }
+
+    /// The name of the YANG type or node kind of the value.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::List => "list",
+            Value::Container => "container",
+            Value::ContainerPresence => "presence container",
+            Value::LeafEmpty => "empty",
+            Value::Notification => "notification",
+            Value::Binary(_) => "binary",
+            Value::Bits(_) => "bits",
+            Value::Bool(_) => "boolean",
+            Value::Decimal64(_) => "decimal64",
+            Value::Enum(_) => "enumeration",
+            Value::IdentityRef(_) => "identityref",
+            Value::InstanceId(_) => "instance-identifier",
+            Value::Int8(_) => "int8",
+            Value::Int16(_) => "int16",
+            Value::Int32(_) => "int32",
+            Value::Int64(_) => "int64",
+            Value::String(_) => "string",
+            Value::Uint8(_) => "uint8",
+            Value::Uint16(_) => "uint16",
+            Value::Uint32(_) => "uint32",
+            Value::Uint64(_) => "uint64",
+            Value::AnyXml(_) => "anyxml",
+            Value::AnyData(_) => "anydata",
+        }
+    }
+}
+
+/// Types the value of a leaf can be converted to, see `Session::get_value`.
+///
+/// Integers are converted from any integer type if they are in range, strings
+/// from any leaf value and IP addresses from strings. Addresses with a zone,
+/// such as `fe80::1%eth0`, are only converted to an address and its zone, as
+/// `(Ipv6Addr, Option<String>)`.
+pub trait FromValue: Sized {
+    /// Convert a value, or `None` if it is of another type.
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Bool(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Decimal64(v) => Some(*v),
+            _ => None,
+        }
+    }
+}
+
+macro_rules! impl_from_value_int {
+    ($($t:ty),*) => {
+        $(
+            impl FromValue for $t {
+                fn from_value(value: &Value) -> Option<Self> {
+                    match *value {
+                        Value::Int8(v) => <$t>::try_from(v).ok(),
+                        Value::Int16(v) => <$t>::try_from(v).ok(),
+                        Value::Int32(v) => <$t>::try_from(v).ok(),
+                        Value::Int64(v) => <$t>::try_from(v).ok(),
+                        Value::Uint8(v) => <$t>::try_from(v).ok(),
+                        Value::Uint16(v) => <$t>::try_from(v).ok(),
+                        Value::Uint32(v) => <$t>::try_from(v).ok(),
+                        Value::Uint64(v) => <$t>::try_from(v).ok(),
+                        _ => None,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_value_int!(i8, i16, i32, i64, u8, u16, u32, u64);
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_string()
+    }
+}
+
+// Addresses of ietf-inet-types may have a zone, which the std types cannot
+// hold, so it is returned next to them.
+macro_rules! impl_from_value_ip {
+    ($($t:ty),*) => {
+        $(
+            impl FromValue for $t {
+                fn from_value(value: &Value) -> Option<Self> {
+                    match value {
+                        Value::String(s) => s.parse().ok(),
+                        _ => None,
+                    }
+                }
+            }
+
+            impl FromValue for ($t, Option<String>) {
+                fn from_value(value: &Value) -> Option<Self> {
+                    let Value::String(s) = value else {
+                        return None;
+                    };
+                    match s.split_once('%') {
+                        Some((addr, zone)) => Some((addr.parse().ok()?, Some(zone.to_owned()))),
+                        None => Some((s.parse().ok()?, None)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_from_value_ip!(IpAddr, Ipv4Addr, Ipv6Addr);
+
+/// Types the value of a leaf can be set from, see `Session::set_item`.
+///
+/// It is not implemented for `f64`, which cannot be printed as a decimal64
+/// with the fraction digits of its leaf; such values are set as strings.
+pub trait ToValue {
+    /// The value as a string, or `None` for nodes without a value.
+    fn to_value(&self) -> Option<String>;
+}
+
+impl<T: ToValue + ?Sized> ToValue for &T {
+    fn to_value(&self) -> Option<String> {
+        (**self).to_value()
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self) -> Option<String> {
+        self.as_ref().and_then(ToValue::to_value)
+    }
+}
+
+impl ToValue for str {
+    fn to_value(&self) -> Option<String> {
+        Some(self.to_owned())
+    }
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Option<String> {
+        self.as_string()
+    }
+}
+
+impl ToValue for DataValue {
+    fn to_value(&self) -> Option<String> {
+        match self {
+            DataValue::Uint8(v) => Some(v.to_string()),
+            DataValue::Uint16(v) => Some(v.to_string()),
+            DataValue::Uint32(v) => Some(v.to_string()),
+            DataValue::Uint64(v) => Some(v.to_string()),
+            DataValue::Bool(v) => Some(v.to_string()),
+            DataValue::Empty => None,
+            DataValue::Int8(v) => Some(v.to_string()),
+            DataValue::Int16(v) => Some(v.to_string()),
+            DataValue::Int32(v) => Some(v.to_string()),
+            DataValue::Int64(v) => Some(v.to_string()),
+            DataValue::Other(v) => Some(v.clone()),
+        }
+    }
+}
+
+macro_rules! impl_to_value_display {
+    ($($t:ty),*) => {
+        $(
+            impl ToValue for $t {
+                fn to_value(&self) -> Option<String> {
+                    Some(self.to_string())
+                }
+            }
+        )*
+    };
+}
+
+impl_to_value_display!(
+    bool, i8, i16, i32, i64, u8, u16, u32, u64, String, IpAddr, Ipv4Addr, Ipv6Addr
+);
+
+/// A data node of the values API, identified by its XPath.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Item {
+    pub xpath: String,
+    pub value: Value,
+    /// Whether the value is the default one.
+    pub default: bool,
+    pub origin: Option<String>,
+}
+
+impl Item {
+    /// Copy an item out of a sysrepo value.
+    ///
+    /// Returns `None` for values of unknown type.
+    ///
+    /// # Safety
+    ///
+    /// `val` must point to a valid sysrepo value.
+    pub unsafe fn from_raw(val: *const ffi::sr_val_t) -> Option<Self> {
+        let val = unsafe { &*val };
+        let to_string = |s: *const c_char| {
+            if s.is_null() {
+                String::new()
+            } else {
+                unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned()
+            }
+        };
+        let data = &val.data;
+        let value = unsafe {
+            match val.type_ {
+                ffi::sr_val_type_t::SR_LIST_T => Value::List,
+                ffi::sr_val_type_t::SR_CONTAINER_T => Value::Container,
+                ffi::sr_val_type_t::SR_CONTAINER_PRESENCE_T => Value::ContainerPresence,
+                ffi::sr_val_type_t::SR_LEAF_EMPTY_T => Value::LeafEmpty,
+                ffi::sr_val_type_t::SR_NOTIFICATION_T => Value::Notification,
+                ffi::sr_val_type_t::SR_BINARY_T => Value::Binary(to_string(data.binary_val)),
+                ffi::sr_val_type_t::SR_BITS_T => Value::Bits(to_string(data.bits_val)),
+                ffi::sr_val_type_t::SR_BOOL_T => Value::Bool(data.bool_val),
+                ffi::sr_val_type_t::SR_DECIMAL64_T => Value::Decimal64(data.decimal64_val),
+                ffi::sr_val_type_t::SR_ENUM_T => Value::Enum(to_string(data.enum_val)),
+                ffi::sr_val_type_t::SR_IDENTITYREF_T => {
+                    Value::IdentityRef(to_string(data.identityref_val))
+                }
+                ffi::sr_val_type_t::SR_INSTANCEID_T => {
+                    Value::InstanceId(to_string(data.instanceid_val))
+                }
+                ffi::sr_val_type_t::SR_INT8_T => Value::Int8(data.int8_val),
+                ffi::sr_val_type_t::SR_INT16_T => Value::Int16(data.int16_val),
+                ffi::sr_val_type_t::SR_INT32_T => Value::Int32(data.int32_val),
+                ffi::sr_val_type_t::SR_INT64_T => Value::Int64(data.int64_val),
+                ffi::sr_val_type_t::SR_STRING_T => Value::String(to_string(data.string_val)),
+                ffi::sr_val_type_t::SR_UINT8_T => Value::Uint8(data.uint8_val),
+                ffi::sr_val_type_t::SR_UINT16_T => Value::Uint16(data.uint16_val),
+                ffi::sr_val_type_t::SR_UINT32_T => Value::Uint32(data.uint32_val),
+                ffi::sr_val_type_t::SR_UINT64_T => Value::Uint64(data.uint64_val),
+                ffi::sr_val_type_t::SR_ANYXML_T => Value::AnyXml(to_string(data.anyxml_val)),
+                ffi::sr_val_type_t::SR_ANYDATA_T => Value::AnyData(to_string(data.anydata_val)),
+                _ => return None,
+            }
+        };
+
+        Some(Item {
+            xpath: to_string(val.xpath),
+            value,
+            default: val.dflt != 0,
+            origin: (!val.origin.is_null()).then(|| to_string(val.origin)),
+        })
+    }
+}
+
+/// Convert all nodes of a data tree into items, in depth-first order.
+///
+/// Nodes without a values API representation, such as RPCs, are skipped.
+pub fn tree_to_items(tree: &DataTree) -> Vec<Item> {
+    tree.traverse()
+        .filter_map(|node| {
+            let schema = node.schema();
+            let value = match schema.kind() {
+                SchemaNodeKind::Container if schema.is_np_container() => Value::Container,
+                SchemaNodeKind::Container => Value::ContainerPresence,
+                SchemaNodeKind::List => Value::List,
+                SchemaNodeKind::Notification => Value::Notification,
+                SchemaNodeKind::AnyData => Value::AnyData(node.value_canonical()?),
+                SchemaNodeKind::Leaf | SchemaNodeKind::LeafList => {
+                    term_value(term_basetype(&node), node.value_canonical()?, node.value()?)
+                }
+                _ => return None,
+            };
+            Some(Item {
+                xpath: node.path(),
+                value,
+                default: node.is_default(),
+                origin: None,
+            })
+        })
+        .collect()
+}
+
+/// Build a data tree out of items.
+///
+/// Lists are created from the keys in their XPath, so list items themselves
+/// may be omitted.
+pub fn items_to_tree<'a>(ctx: &'a Context, items: &[Item]) -> Result<DataTree<'a>> {
+    let mut tree = DataTree::new(ctx);
+    for item in items {
+        tree.new_path(&item.xpath, item.value.as_string().as_deref(), false)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?;
+    }
+    Ok(tree)
+}
+
+/// The base type of the value of a leaf or leaf-list node.
+///
+/// This is the resolved type, so unions and leafrefs get the type of their
+/// actual value.
+fn term_basetype(node: &yang::data::DataNodeRef) -> yang::ffi::LY_DATA_TYPE::Type {
+    unsafe {
+        let term = node.as_raw() as *const yang::ffi::lyd_node_term;
+        (*(*term).value.realtype).basetype
+    }
+}
+
+fn term_value(
+    basetype: yang::ffi::LY_DATA_TYPE::Type,
+    canonical: String,
+    typed: DataValue,
+) -> Value {
+    use yang::ffi::LY_DATA_TYPE;
+
+    match (basetype, typed) {
+        (_, DataValue::Bool(v)) => Value::Bool(v),
+        (_, DataValue::Empty) => Value::LeafEmpty,
+        (_, DataValue::Int8(v)) => Value::Int8(v),
+        (_, DataValue::Int16(v)) => Value::Int16(v),
+        (_, DataValue::Int32(v)) => Value::Int32(v),
+        (_, DataValue::Int64(v)) => Value::Int64(v),
+        (_, DataValue::Uint8(v)) => Value::Uint8(v),
+        (_, DataValue::Uint16(v)) => Value::Uint16(v),
+        (_, DataValue::Uint32(v)) => Value::Uint32(v),
+        (_, DataValue::Uint64(v)) => Value::Uint64(v),
+        (LY_DATA_TYPE::LY_TYPE_BINARY, _) => Value::Binary(canonical),
+        (LY_DATA_TYPE::LY_TYPE_BITS, _) => Value::Bits(canonical),
+        (LY_DATA_TYPE::LY_TYPE_DEC64, _) => match canonical.parse() {
+            Ok(v) => Value::Decimal64(v),
+            Err(_) => Value::String(canonical),
+        },
+        (LY_DATA_TYPE::LY_TYPE_ENUM, _) => Value::Enum(canonical),
+        (LY_DATA_TYPE::LY_TYPE_IDENT, _) => Value::IdentityRef(canonical),
+        (LY_DATA_TYPE::LY_TYPE_INST, _) => Value::InstanceId(canonical),
+        _ => Value::String(canonical),
+    }
+}
+
+/// A tokenizer over the nodes and list keys of an XPath.
+///
+/// Methods that look up a node by name or index start from the beginning of
+/// the XPath, while the others continue from the current node.
+#[cfg(feature = "xpath")]
+pub struct XPathTokenizer {
+    // Modified in place by sysrepo, so it must not be reallocated.
+    xpath: Box<[u8]>,
+    state: ffi::sr_xpath_ctx_t,
+    started: bool,
+}
+
+#[cfg(feature = "xpath")]
+impl XPathTokenizer {
+    pub fn new(xpath: &str) -> Result<Self> {
+        let xpath = str_to_cstring(xpath)?;
+        Ok(Self {
+            xpath: xpath.into_bytes_with_nul().into_boxed_slice(),
+            state: Default::default(),
+            started: false,
+        })
+    }
+
+    /// Get the name of the next node.
+    pub fn next_node(&mut self) -> Option<String> {
+        let xpath = self.current();
+        Self::to_string(unsafe { ffi::sr_xpath_next_node(xpath, &mut self.state) })
+    }
+
+    /// Get the name of the next node, prefixed with its module name if present.
+    pub fn next_node_with_ns(&mut self) -> Option<String> {
+        let xpath = self.current();
+        Self::to_string(unsafe { ffi::sr_xpath_next_node_with_ns(xpath, &mut self.state) })
+    }
+
+    /// Get the name of the next key of the current node.
+    pub fn next_key_name(&mut self) -> Option<String> {
+        let xpath = self.current();
+        Self::to_string(unsafe { ffi::sr_xpath_next_key_name(xpath, &mut self.state) })
+    }
+
+    /// Get the value of the next key of the current node.
+    pub fn next_key_value(&mut self) -> Option<String> {
+        let xpath = self.current();
+        Self::to_string(unsafe { ffi::sr_xpath_next_key_value(xpath, &mut self.state) })
+    }
+
+    /// Get the name of the last node.
+    pub fn last_node(&mut self) -> Option<String> {
+        let xpath = self.restart();
+        Self::to_string(unsafe { ffi::sr_xpath_last_node(xpath, &mut self.state) })
+    }
+
+    /// Move to the node with the given name.
+    pub fn node(&mut self, node_name: &str) -> Option<String> {
+        let node_name = str_to_cstring(node_name).ok()?;
+        let xpath = self.restart();
+        Self::to_string(unsafe { ffi::sr_xpath_node(xpath, node_name.as_ptr(), &mut self.state) })
+    }
+
+    /// Move to the node with the given name, searching after the current node.
+    pub fn node_rel(&mut self, node_name: &str) -> Option<String> {
+        let node_name = str_to_cstring(node_name).ok()?;
+        let xpath = self.current();
+        Self::to_string(unsafe {
+            ffi::sr_xpath_node_rel(xpath, node_name.as_ptr(), &mut self.state)
+        })
+    }
+
+    /// Move to the node at the given index.
+    pub fn node_idx(&mut self, index: usize) -> Option<String> {
+        let xpath = self.restart();
+        Self::to_string(unsafe { ffi::sr_xpath_node_idx(xpath, index, &mut self.state) })
+    }
+
+    /// Move to the node at the given index, counting from the current node.
+    pub fn node_idx_rel(&mut self, index: usize) -> Option<String> {
+        let xpath = self.current();
+        Self::to_string(unsafe { ffi::sr_xpath_node_idx_rel(xpath, index, &mut self.state) })
+    }
+
+    /// Get the value of the given key of the current node.
+    pub fn node_key_value(&mut self, key: &str) -> Option<String> {
+        let key = str_to_cstring(key).ok()?;
+        let xpath = self.current();
+        Self::to_string(unsafe {
+            ffi::sr_xpath_node_key_value(xpath, key.as_ptr(), &mut self.state)
+        })
+    }
+
+    /// Get the value of the key at the given index of the current node.
+    pub fn node_key_value_idx(&mut self, index: usize) -> Option<String> {
+        let xpath = self.current();
+        Self::to_string(unsafe { ffi::sr_xpath_node_key_value_idx(xpath, index, &mut self.state) })
+    }
+
+    /// Get the value of a key of the node with the given name.
+    pub fn key_value(&mut self, node_name: &str, key_name: &str) -> Option<String> {
+        let node_name = str_to_cstring(node_name).ok()?;
+        let key_name = str_to_cstring(key_name).ok()?;
+        let xpath = self.restart();
+        Self::to_string(unsafe {
+            ffi::sr_xpath_key_value(
+                xpath,
+                node_name.as_ptr(),
+                key_name.as_ptr(),
+                &mut self.state,
+            )
+        })
+    }
+
+    /// Get the value of a key of a node, both given by their index.
+    pub fn key_value_idx(&mut self, node_index: usize, key_index: usize) -> Option<String> {
+        let xpath = self.restart();
+        Self::to_string(unsafe {
+            ffi::sr_xpath_key_value_idx(xpath, node_index, key_index, &mut self.state)
+        })
+    }
+
+    /// Get the XPath to pass to continue from the current node.
+    fn current(&mut self) -> *mut c_char {
+        if self.started {
+            ptr::null_mut()
+        } else {
+            self.restart()
+        }
+    }
+
+    /// Restore the XPath and get it to pass to start from its beginning.
+    fn restart(&mut self) -> *mut c_char {
+        if self.started {
+            unsafe { ffi::sr_xpath_recover(&mut self.state) };
+        }
+        self.started = true;
+        self.xpath.as_mut_ptr() as *mut c_char
+    }
+
+    fn to_string(s: *const c_char) -> Option<String> {
+        (!s.is_null()).then(|| unsafe { CStr::from_ptr(s) }.to_string_lossy().into_owned())
+    }
+}
+
+/// Get the name of the last node of an XPath.
+#[cfg(feature = "xpath")]
+pub fn xpath_node_name(xpath: &str) -> Option<String> {
+    let xpath = str_to_cstring(xpath).ok()?;
+    // The returned name points into the passed XPath.
+    XPathTokenizer::to_string(unsafe { ffi::sr_xpath_node_name(xpath.as_ptr()) })
+}
+
+/// Check whether the last node of an XPath has the given name.
+#[cfg(feature = "xpath")]
+pub fn xpath_node_name_eq(xpath: &str, node_name: &str) -> bool {
+    match (str_to_cstring(xpath), str_to_cstring(node_name)) {
+        (Ok(xpath), Ok(node_name)) => unsafe {
+            ffi::sr_xpath_node_name_eq(xpath.as_ptr(), node_name.as_ptr()) != 0
+        },
+        _ => false,
+    }
+}
+
+/// Get the module change trampoline for a callback whose type cannot be named.
+fn module_change_trampoline<F>(_: &F) -> ffi::sr_module_change_cb
+where
+    F: FnMut(&ChangeContext) -> Result<()>,
+{
+    Some(Session::call_module_change::<F>)
+}
+
+/// Wrap a module change callback so it can be shared between subscriptions.
+fn shared_module_change_callback<F>(
+    callback: &Arc<Mutex<F>>,
+) -> impl FnMut(&ChangeContext) -> Result<()> + 'static
+where
+    F: FnMut(&ChangeContext) -> Result<()> + 'static,
+{
+    let callback = callback.clone();
+    move |ctx: &ChangeContext| {
+        let mut callback = callback.lock().unwrap();
+        (*callback)(ctx)
+    }
+}
+
+fn shared_oper_get_callback<F>(
+    callback: &Arc<Mutex<F>>,
+) -> impl FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()> + 'static
+where
+    F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()> + 'static,
+{
+    let callback = callback.clone();
+    move |sess: &Session,
+          sub_id: u32,
+          mod_name: &str,
+          path: &str,
+          request_xpath: Option<&str>,
+          request_id: u32,
+          parent: &mut DataTree| {
+        let mut callback = callback.lock().unwrap();
+        (*callback)(
+            sess,
+            sub_id,
+            mod_name,
+            path,
+            request_xpath,
+            request_id,
+            parent,
+        )
+    }
+}
+
+/// Wrap a module change callback to keep state across the events of a
+/// request.
+///
+/// The callback is passed the state of the request, which is `None` on the
+/// first event. State stored during the change event can then be used in
+/// the done event or released in the abort event. The state is dropped after
+/// the done or abort event, or when the callback fails.
+pub fn with_request_state<T, F>(
+    mut callback: F,
+) -> impl FnMut(&ChangeContext) -> Result<()> + 'static
+where
+    T: 'static,
+    F: FnMut(&ChangeContext, &mut Option<T>) -> Result<()> + 'static,
+{
+    let mut states: HashMap<u32, T> = HashMap::new();
+    move |ctx: &ChangeContext| {
+        let keep = matches!(ctx.event, Event::Update | Event::Change | Event::Enabled);
+        let mut state = states.remove(&ctx.request_id);
+        let res = callback(ctx, &mut state);
+        if let (true, Ok(()), Some(state)) = (keep, &res, state) {
+            states.insert(ctx.request_id, state);
+        }
+        res
+    }
+}
+
+/// Cleanup actions of a change, run if the change is aborted.
+#[derive(Default)]
+pub struct AbortCleanup {
+    actions: Vec<Box<dyn FnOnce()>>,
+}
+
+impl AbortCleanup {
+    /// Register an action undoing part of the change, for example releasing
+    /// a reserved resource.
+    ///
+    /// Actions run in the reverse order of their registration.
+    pub fn on_abort<F>(&mut self, action: F)
+    where
+        F: FnOnce() + 'static,
+    {
+        self.actions.push(Box::new(action));
+    }
+
+    fn run(&mut self) {
+        while let Some(action) = self.actions.pop() {
+            action();
         }
     }
+}
 
-    /// Send RPC.
-    pub fn rpc_send(&mut self, input: DataTree<'_>, timeout: Duration) -> Result<ManagedData<'a>> {
-        let input = input.into_raw();
-        // TODO: check this fits
-        let timeout = timeout.as_millis() as u32;
+/// Wrap a module change callback to run cleanup actions of aborted changes.
+///
+/// Actions registered on the `AbortCleanup` of a request run on its abort
+/// event, or when the callback fails before the done event, as sysrepo sends
+/// no abort event to the failing subscriber. They are dropped without running
+/// after the done event.
+pub fn with_abort_cleanup<F>(mut callback: F) -> impl FnMut(&ChangeContext) -> Result<()> + 'static
+where
+    F: FnMut(&ChangeContext, &mut AbortCleanup) -> Result<()> + 'static,
+{
+    with_request_state(
+        move |ctx: &ChangeContext, state: &mut Option<AbortCleanup>| {
+            let abort = matches!(ctx.event, Event::Abort);
+            let done = matches!(ctx.event, Event::Done);
+            let cleanup = state.get_or_insert_with(AbortCleanup::default);
+            let res = callback(ctx, cleanup);
+            if abort || (res.is_err() && !done) {
+                cleanup.run();
+            }
+            res
+        },
+    )
+}
 
-        let mut output = ptr::null_mut();
+/// Convert a NETCONF subtree filter to an XPath for `get_data`.
+///
+/// `filter` is the XML content of the `<filter>` element. Returns `None` for
+/// an empty filter, which selects no data.
+#[cfg(feature = "yang3")]
+pub fn subtree_filter_to_xpath(ctx: &Context, filter: &str) -> Result<Option<String>> {
+    let inval = || Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+    let get = format!(
+        "<get xmlns=\"urn:ietf:params:xml:ns:netconf:base:1.0\"><filter>{}</filter></get>",
+        filter
+    );
+    let tree = DataTree::parse_op_string(ctx, get, DataFormat::XML, DataOperation::RpcYang)
+        .map_err(|_| inval())?;
+    let Some(filter) = tree
+        .reference()
+        .and_then(|get| get.children().find(|n| n.schema().name() == "filter"))
+    else {
+        return Ok(None);
+    };
 
-        let rc = unsafe { ffi::sr_rpc_send_tree(self.sess, input, timeout, &mut output) };
+    let any = filter.as_raw() as *mut yang::ffi::lyd_node_any;
+    let content = unsafe {
+        if (*any).value_type != yang::ffi::LYD_ANYDATA_VALUETYPE::LYD_ANYDATA_DATATREE {
+            return Err(inval());
+        }
+        // The content is owned by the filter node.
+        ManuallyDrop::new(DataTree::from_raw(ctx, (*any).value.tree))
+    };
 
-        let rc = rc as ffi::sr_error_t::Type;
-        if rc != ffi::sr_error_t::SR_ERR_OK {
-            Err(Error { errcode: rc })
-        } else {
-            unsafe { Ok(ManagedData::from_raw(self.conn, output)) }
+    let mut xpaths = Vec::new();
+    if let Some(first) = content.reference() {
+        for node in first.inclusive_siblings() {
+            subtree_filter_node(ctx, "", &node, &mut xpaths)?;
         }
     }
+    Ok((!xpaths.is_empty()).then(|| xpaths.join(" | ")))
 }
 
-impl Drop for Session<'_> {
-    fn drop(&mut self) {
-        // The sysrepo documentation states that this should be retried until
-        // success.
-        loop {
-            let rc = unsafe { ffi::sr_session_stop(self.sess) };
-            let rc = rc as ffi::sr_error_t::Type;
-            if rc == ffi::sr_error_t::SR_ERR_OK {
-                break;
+/// Add the XPaths selected by a containment node of a subtree filter.
+#[cfg(feature = "yang3")]
+fn subtree_filter_node(
+    ctx: &Context,
+    parent: &str,
+    node: &DataNodeRef<'_>,
+    xpaths: &mut Vec<String>,
+) -> Result<()> {
+    let (module, name, _) = subtree_filter_node_info(ctx, node)?;
+    let mut path = format!("{}/{}:{}", parent, module, name);
+
+    let mut content_matches = Vec::new();
+    let mut selections = Vec::new();
+    let mut containments = Vec::new();
+    for child in node.children() {
+        let (module, name, value) = subtree_filter_node_info(ctx, &child)?;
+        let name = format!("{}:{}", module, name);
+        match value {
+            _ if child.children().next().is_some() => containments.push(child),
+            Some(value) => {
+                path.push_str(&format!("[{}={}]", name, xpath_literal(&value)));
+                content_matches.push(name);
             }
+            None => selections.push(name),
         }
     }
+
+    // Without selection or containment nodes, the whole subtree is selected.
+    if selections.is_empty() && containments.is_empty() {
+        xpaths.push(path);
+        return Ok(());
+    }
+    for name in content_matches.iter().chain(&selections) {
+        xpaths.push(format!("{}/{}", path, name));
+    }
+    for child in containments {
+        subtree_filter_node(ctx, &path, &child, xpaths)?;
+    }
+    Ok(())
 }
 
-unsafe impl Send for Session<'_> {}
+/// Get the module name, name and value of a subtree filter node, which is
+/// usually opaque.
+#[cfg(feature = "yang3")]
+fn subtree_filter_node_info(
+    ctx: &Context,
+    node: &DataNodeRef<'_>,
+) -> Result<(String, String, Option<String>)> {
+    let raw = node.as_raw();
+    if unsafe { !(*raw).schema.is_null() } {
+        let schema = node.schema();
+        return Ok((
+            schema.module().name().to_owned(),
+            schema.name().to_owned(),
+            node.value_canonical(),
+        ));
+    }
 
-pub struct ManagedData<'a> {
-    ctx: ManuallyDrop<Context>,
-    data: *mut ffi::sr_data_t,
-    _ghost: PhantomData<&'a ()>,
+    let inval = || Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+    unsafe {
+        let opaq = raw as *mut yang::ffi::lyd_node_opaq;
+        if (*opaq).format != yang::ffi::LY_VALUE_FORMAT::LY_VALUE_XML {
+            return Err(inval());
+        }
+        let name = CStr::from_ptr((*opaq).name.name)
+            .to_string_lossy()
+            .into_owned();
+        let ns = (*opaq).name.__bindgen_anon_1.module_ns;
+        if ns.is_null() {
+            return Err(inval());
+        }
+        let ns = CStr::from_ptr(ns).to_str().map_err(|_| inval())?;
+        let module = ctx
+            .get_module_implemented_ns(ns)
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+        let value = (!(*opaq).value.is_null())
+            .then(|| {
+                CStr::from_ptr((*opaq).value)
+                    .to_string_lossy()
+                    .trim()
+                    .to_owned()
+            })
+            .filter(|value| !value.is_empty());
+        Ok((module.name().to_owned(), name, value))
+    }
 }
 
-impl<'a> ManagedData<'a> {
-    pub unsafe fn from_raw(conn: &'a Connection, data: *mut ffi::sr_data_t) -> Self {
-        debug_assert!(!data.is_null());
-        // Aquire the context and then drop it right away.
-        // SAFETY: This pointer will be valid as the context read lock continues
-        // to be held by the data tree.
-        let ctx = unsafe {
-            let ctx = ffi::sr_acquire_context(conn.conn) as *mut _;
-            ffi::sr_release_context(conn.conn);
-            ManuallyDrop::new(Context::from_raw(&(), ctx))
-        };
-        Self {
-            ctx,
-            data,
-            _ghost: PhantomData,
-        }
+/// Pair handlers with descending priorities, ending with `last`.
+fn chain_priorities<H>(last: Priority, handlers: Vec<H>) -> Result<Vec<(Priority, H)>> {
+    let count = handlers.len().saturating_sub(1) as u32;
+    let first = last
+        .0
+        .checked_add(count)
+        .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+    Ok((0..).map(|i| Priority(first - i)).zip(handlers).collect())
+}
+
+/// Strings passed to sysrepo, such as module names and paths.
+///
+/// `ModuleName` and `SchemaPath` are checked and converted once, while plain
+/// strings are converted every time they are passed. An optional path left
+/// out needs its type, such as `None::<&str>`.
+pub trait ToCStr {
+    /// The string as a C string, failing with `SR_ERR_INVAL_ARG` if it
+    /// contains a nul byte.
+    fn to_cstr(&self) -> Result<Cow<'_, CStr>>;
+}
+
+impl ToCStr for str {
+    fn to_cstr(&self) -> Result<Cow<'_, CStr>> {
+        str_to_cstring(self).map(Cow::Owned)
     }
+}
 
-    pub fn into_raw(self) -> *mut ffi::sr_data_t {
-        self.data
+impl ToCStr for CStr {
+    fn to_cstr(&self) -> Result<Cow<'_, CStr>> {
+        Ok(Cow::Borrowed(self))
     }
+}
 
-    pub fn context(&self) -> &Context {
-        &self.ctx
+impl ToCStr for String {
+    fn to_cstr(&self) -> Result<Cow<'_, CStr>> {
+        self.as_str().to_cstr()
     }
+}
 
-    pub fn tree(&self) -> ManagedDataTree<'_> {
-        let tree = unsafe { ManuallyDrop::new(DataTree::from_raw(&self.ctx, (*self.data).tree)) };
-        ManagedDataTree { tree }
+impl<T: ToCStr + ?Sized> ToCStr for &T {
+    fn to_cstr(&self) -> Result<Cow<'_, CStr>> {
+        (**self).to_cstr()
     }
 }
 
-impl Drop for ManagedData<'_> {
-    fn drop(&mut self) {
-        unsafe {
-            ffi::sr_release_data(self.data);
+/// The name of a YANG module, checked to be a YANG identifier.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Ord, PartialOrd)]
+pub struct ModuleName(CString);
+
+impl ModuleName {
+    /// Check a module name, failing with `SR_ERR_INVAL_ARG` if it is not an
+    /// identifier.
+    pub fn new(name: &str) -> Result<Self> {
+        let mut chars = name.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        if !valid {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
         }
+        str_to_cstring(name).map(ModuleName)
     }
-}
 
-pub struct ManagedDataTree<'a> {
-    tree: ManuallyDrop<DataTree<'a>>,
+    pub fn as_str(&self) -> &str {
+        // Checked to be ASCII on construction.
+        self.0.to_str().unwrap()
+    }
 }
 
-impl<'a> Deref for ManagedDataTree<'a> {
-    type Target = DataTree<'a>;
-
-    fn deref(&self) -> &DataTree<'a> {
-        &self.tree
+impl ToCStr for ModuleName {
+    fn to_cstr(&self) -> Result<Cow<'_, CStr>> {
+        Ok(Cow::Borrowed(&self.0))
     }
 }
 
-pub struct Subscription<'a> {
-    subscr: *mut ffi::sr_subscription_ctx_t,
-    _conn: &'a Connection,
+impl FromStr for ModuleName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        ModuleName::new(s)
+    }
 }
 
-impl<'a> Subscription<'a> {
-    pub fn from_raw(conn: &'a Connection, subscr: *mut ffi::sr_subscription_ctx_t) -> Self {
-        Self {
-            _conn: conn,
-            subscr,
-        }
+impl fmt::Display for ModuleName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
-impl Drop for Subscription<'_> {
-    fn drop(&mut self) {
-        // The sysrepo documentation states that this should be retried until
-        // success.
-        loop {
-            let rc = unsafe { ffi::sr_unsubscribe(self.subscr) };
-            let rc = rc as ffi::sr_error_t::Type;
-            if rc == ffi::sr_error_t::SR_ERR_OK {
-                break;
+/// An absolute schema or data path, checked to be well formed.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Ord, PartialOrd)]
+pub struct SchemaPath(CString);
+
+impl SchemaPath {
+    /// Check a path, failing with `SR_ERR_INVAL_ARG` unless it starts with a
+    /// slash, does not end with one and its predicates and quotes are closed.
+    ///
+    /// Whether the nodes of the path exist is only known once it is used.
+    pub fn new(path: &str) -> Result<Self> {
+        let inval = Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+        if !path.starts_with('/') || (path.len() > 1 && path.ends_with('/')) {
+            return Err(inval);
+        }
+        let mut depth = 0usize;
+        let mut quote = None;
+        for c in path.chars() {
+            match (quote, c) {
+                (Some(q), c) if c == q => quote = None,
+                (Some(_), _) => {}
+                (None, '\'' | '"') => quote = Some(c),
+                (None, '[') => depth += 1,
+                (None, ']') => depth = depth.checked_sub(1).ok_or(inval.clone())?,
+                _ => {}
             }
         }
+        if depth != 0 || quote.is_some() {
+            return Err(inval);
+        }
+        str_to_cstring(path).map(SchemaPath)
     }
-}
 
-unsafe impl Send for Subscription<'_> {}
-unsafe impl Sync for Subscription<'_> {}
-
-pub struct Changes<'a> {
-    sess: &'a Session<'a>,
-    ctx: ManuallyDrop<Context>,
-    iter: *mut ffi::sr_change_iter_t,
+    pub fn as_str(&self) -> &str {
+        // Made from a `&str` on construction.
+        self.0.to_str().unwrap()
+    }
 }
 
-impl<'a> Changes<'a> {
-    pub unsafe fn from_raw(sess: &'a Session<'a>, iter: *mut ffi::sr_change_iter_t) -> Self {
-        // Aquire the context and then drop it right away.
-        // SAFETY: This pointer will be valid as the context read lock continues
-        // to be held by the iterator.
-        let ctx = unsafe {
-            let ctx = ffi::sr_acquire_context(sess.conn.conn);
-            ffi::sr_release_context(sess.conn.conn);
-            ManuallyDrop::new(Context::from_raw(&(), ctx as *mut _))
-        };
-        Self { sess, ctx, iter }
+impl ToCStr for SchemaPath {
+    fn to_cstr(&self) -> Result<Cow<'_, CStr>> {
+        Ok(Cow::Borrowed(&self.0))
     }
+}
 
-    pub fn iter<'b>(&'b self) -> ChangesIter<'b> {
-        ChangesIter {
-            sess: self.sess.sess,
-            ctx: &self.ctx,
-            iter: self.iter,
-        }
+impl FromStr for SchemaPath {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        SchemaPath::new(s)
     }
 }
 
-impl Drop for Changes<'_> {
-    fn drop(&mut self) {
-        unsafe {
-            ffi::sr_free_change_iter(self.iter);
-        }
+impl fmt::Display for SchemaPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
     }
 }
 
-impl<'a> IntoIterator for &'a Changes<'_> {
-    type Item = Result<(ManagedDataTree<'a>, ChangeOperation<'a>)>;
-    type IntoIter = ChangesIter<'a>;
+fn str_to_cstring(s: &str) -> Result<CString> {
+    CString::new(s).map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))
+}
 
-    fn into_iter(self) -> ChangesIter<'a> {
-        self.iter()
+/// The operation of a node of the changes of a session, inherited from its
+/// ancestors if it has none, or `None` if the node is only part of the path
+/// to other changes.
+#[cfg(feature = "yang3")]
+fn edit_operation(node: &DataNodeRef) -> Option<String> {
+    let operation = |node: &DataNodeRef| {
+        node.meta()
+            .find(|meta| meta.name() == "operation")
+            .map(|meta| meta.value().to_owned())
+    };
+    match operation(node) {
+        Some(op) if op == "none" => None,
+        Some(op) => Some(op),
+        None => Some(
+            node.ancestors()
+                .find_map(|node| operation(&node).filter(|op| op != "none"))
+                .unwrap_or_else(|| "merge".to_owned()),
+        ),
     }
 }
 
-pub struct ChangesIter<'a> {
-    sess: *mut ffi::sr_session_ctx_t,
-    ctx: &'a Context,
-    iter: *mut ffi::sr_change_iter_t,
+/// Set the NETCONF operation of a node of an edit.
+#[cfg(feature = "yang3")]
+fn set_edit_operation(
+    node: &DataNodeRef,
+    netconf: &yang::schema::SchemaModule,
+    operation: &str,
+) -> Result<()> {
+    let name = str_to_cstring("operation")?;
+    let operation = str_to_cstring(operation)?;
+    let ret = unsafe {
+        yang::ffi::lyd_new_meta(
+            ptr::null(),
+            node.as_raw(),
+            netconf.as_raw(),
+            name.as_ptr(),
+            operation.as_ptr(),
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if ret != yang::ffi::LY_ERR::LY_SUCCESS {
+        return Err(Error::new(ffi::sr_error_t::SR_ERR_LY));
+    }
+    Ok(())
 }
 
-impl<'a> Iterator for ChangesIter<'a> {
-    // TODO: maybe should be a wrapper around a DataNodeRef instead
-    type Item = Result<(ManagedDataTree<'a>, ChangeOperation<'a>)>;
+/// Quote a string as an XPath literal.
+#[cfg(feature = "yang3")]
+fn xpath_literal(s: &str) -> String {
+    if !s.contains('\'') {
+        format!("'{}'", s)
+    } else if !s.contains('"') {
+        format!("\"{}\"", s)
+    } else {
+        let parts: Vec<String> = s.split('\'').map(|part| format!("'{}'", part)).collect();
+        format!("concat({})", parts.join(", \"'\", "))
+    }
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        let mut oper = 0;
-        let mut node = ptr::null();
-        let mut prev_value = ptr::null();
-        let mut prev_list_keys = ptr::null();
-        let mut prev_default_flag = 0;
+/// The items of a libyang sized array, which stores its length as a
+/// `LY_ARRAY_COUNT_TYPE` just before the first item.
+#[cfg(feature = "yang3")]
+unsafe fn ly_array<'a, T>(array: *const T) -> &'a [T] {
+    /// `LY_ARRAY_COUNT_TYPE`, a macro of libyang.
+    type LyArrayCount = u64;
 
-        let rc = unsafe {
-            ffi::sr_get_change_tree_next(
-                self.sess,
-                self.iter,
-                &mut oper,
-                &mut node,
-                &mut prev_value,
-                &mut prev_list_keys,
-                &mut prev_default_flag,
-            )
-        };
+    if array.is_null() {
+        return &[];
+    }
+    let count = unsafe { *(array as *const LyArrayCount).sub(1) } as usize;
+    unsafe { std::slice::from_raw_parts(array, count) }
+}
 
-        let rc = rc as ffi::sr_error_t::Type;
-        match rc {
-            ffi::sr_error_t::SR_ERR_OK => {
-                let node = unsafe { DataTree::from_raw(&self.ctx, node as *mut _) };
-                let node = ManagedDataTree {
-                    tree: ManuallyDrop::new(node),
-                };
-                let oper = match oper {
-                    ffi::sr_change_oper_t::SR_OP_CREATED if !prev_value.is_null() => {
-                        ChangeOperation::CreatedLeafListUserOrdered {
-                            previous_value: unsafe { CStr::from_ptr(prev_value).to_str().unwrap() },
-                        }
-                    }
-                    ffi::sr_change_oper_t::SR_OP_CREATED if !prev_list_keys.is_null() => {
-                        ChangeOperation::CreatedListUserOrdered {
-                            previous_key: unsafe {
-                                CStr::from_ptr(prev_list_keys).to_str().unwrap()
-                            },
-                        }
-                    }
-                    ffi::sr_change_oper_t::SR_OP_CREATED => ChangeOperation::Created,
-                    ffi::sr_change_oper_t::SR_OP_MODIFIED => ChangeOperation::Modified {
-                        previous_value: unsafe { CStr::from_ptr(prev_value).to_str().unwrap() },
-                        previous_default: prev_default_flag != 0,
-                    },
-                    ffi::sr_change_oper_t::SR_OP_DELETED => ChangeOperation::Deleted,
-                    ffi::sr_change_oper_t::SR_OP_MOVED if !prev_value.is_null() => {
-                        ChangeOperation::MovedLeafListUserOrdered {
-                            previous_value: unsafe { CStr::from_ptr(prev_value).to_str().unwrap() },
-                        }
-                    }
-                    ffi::sr_change_oper_t::SR_OP_MOVED if !prev_list_keys.is_null() => {
-                        ChangeOperation::MovedListUserOrdered {
-                            previous_key: unsafe {
-                                CStr::from_ptr(prev_list_keys).to_str().unwrap()
-                            },
-                        }
-                    }
-                    _ => unreachable!(),
-                };
-                Some(Ok((node, oper)))
+/// Get the path of a leafref node with module names as prefixes, so it can
+/// be evaluated as a JSON-formatted XPath.
+#[cfg(feature = "yang3")]
+fn leafref_path(node: &DataNodeRef<'_>) -> Result<String> {
+    let schema = node.schema();
+    let leaf_type = schema
+        .leaf_type()
+        .filter(|t| t.base_type() == DataValueType::LeafRef)
+        .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+
+    let lref = leaf_type.as_raw() as *const yang::ffi::lysc_type_leafref;
+    let (expr, prefixes) = unsafe {
+        let expr = yang::ffi::lyxp_get_expr((*lref).path);
+        (CStr::from_ptr(expr).to_string_lossy(), (*lref).prefixes)
+    };
+    let prefixes = unsafe { ly_array(prefixes) };
+    let module_name = |prefix: Option<&str>| {
+        prefixes
+            .iter()
+            .find(|p| match prefix {
+                Some(prefix) => {
+                    !p.prefix.is_null()
+                        && unsafe { CStr::from_ptr(p.prefix) }.to_bytes() == prefix.as_bytes()
+                }
+                None => p.prefix.is_null(),
+            })
+            .map(|p| unsafe { CStr::from_ptr((*p.mod_).name) }.to_string_lossy())
+    };
+    let local_module =
+        module_name(None).unwrap_or_else(|| schema.module().name().to_owned().into());
+
+    // Rewrite every name step as `module:name`, leaving literals, function
+    // calls, axes and operator names untouched. As in the XPath grammar, a name
+    // is an operator if it follows a complete operand.
+    let mut path = String::with_capacity(expr.len());
+    let mut chars = expr.char_indices().peekable();
+    let mut qualified = false;
+    let mut after_operand = false;
+    while let Some((start, c)) = chars.next() {
+        if c == '\'' || c == '"' {
+            path.push(c);
+            for (_, q) in chars.by_ref() {
+                path.push(q);
+                if q == c {
+                    break;
+                }
+            }
+            after_operand = true;
+        } else if c.is_alphabetic() || c == '_' {
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, n)) = chars.peek() {
+                if n.is_alphanumeric() || n == '_' || n == '-' || n == '.' {
+                    end = i + n.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let ident = &expr[start..end];
+            let rest = &expr[end..];
+            if qualified {
+                qualified = false;
+                path.push_str(ident);
+                after_operand = true;
+            } else if after_operand && matches!(ident, "and" | "or" | "div" | "mod") {
+                path.push_str(ident);
+                after_operand = false;
+            } else if rest.starts_with("::") {
+                chars.next();
+                chars.next();
+                path.push_str(ident);
+                path.push_str("::");
+                after_operand = false;
+            } else if rest.starts_with(':') {
+                chars.next();
+                let module =
+                    module_name(Some(ident)).ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+                path.push_str(&module);
+                path.push(':');
+                qualified = true;
+            } else if rest.trim_start().starts_with('(') {
+                path.push_str(ident);
+                after_operand = false;
+            } else {
+                path.push_str(&local_module);
+                path.push(':');
+                path.push_str(ident);
+                after_operand = true;
+            }
+        } else {
+            path.push(c);
+            if c == '*' {
+                after_operand = !after_operand;
+            } else if c.is_ascii_digit() || matches!(c, ')' | ']' | '.') {
+                after_operand = true;
+            } else if !c.is_whitespace() {
+                after_operand = false;
             }
-            ffi::sr_error_t::SR_ERR_NOT_FOUND => None,
-            _ => Some(Err(Error { errcode: rc })),
         }
     }
-}
 
-#[derive(Clone, Debug)]
-pub enum ChangeOperation<'a> {
-    Created,
-    CreatedLeafListUserOrdered {
-        previous_value: &'a str,
-    },
-    CreatedListUserOrdered {
-        previous_key: &'a str,
-    },
-    Modified {
-        previous_value: &'a str,
-        previous_default: bool,
-    },
-    Deleted,
-    MovedLeafListUserOrdered {
-        previous_value: &'a str,
-    },
-    MovedListUserOrdered {
-        previous_key: &'a str,
-    },
+    Ok(path)
 }
 
-fn str_to_cstring(s: &str) -> Result<CString> {
-    CString::new(s).map_err(|_| Error {
-        errcode: ffi::sr_error_t::SR_ERR_INVAL_ARG,
-    })
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fields_expand_to_paths() {
+        assert_eq!(
+            fields_xpath("/if:interfaces/", "interface(name;mtu);other").unwrap(),
+            "/if:interfaces/interface/name | /if:interfaces/interface/mtu | /if:interfaces/other"
+        );
+        assert_eq!(
+            fields_paths("a(b(c;d);e)").unwrap(),
+            ["a/b/c", "a/b/d", "a/e"]
+        );
+    }
+
+    #[test]
+    fn fields_reject_malformed() {
+        for fields in ["", "a;", "a(b", "a)b(", "(b)", "a()", "a(b)c"] {
+            assert!(fields_paths(fields).is_err(), "{}", fields);
+        }
+    }
+
+    #[test]
+    fn permissions_from_mode() {
+        let permissions = Permissions::from(0o640 as libc::mode_t);
+        assert_eq!(
+            permissions.owner,
+            ModuleAccess {
+                read: true,
+                write: true
+            }
+        );
+        assert_eq!(
+            permissions.group,
+            ModuleAccess {
+                read: true,
+                write: false
+            }
+        );
+        assert_eq!(permissions.other, ModuleAccess::default());
+    }
+
+    #[test]
+    fn permissions_roundtrip() {
+        for mode in [0o000, 0o600, 0o644, 0o660, 0o666, 0o400, 0o222] {
+            let mode = mode as libc::mode_t;
+            assert_eq!(libc::mode_t::from(Permissions::from(mode)), mode);
+        }
+        // The execute bits are dropped.
+        assert_eq!(libc::mode_t::from(Permissions::from(0o755)), 0o644);
+    }
 }
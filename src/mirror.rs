@@ -0,0 +1,134 @@
+//! Mirroring of configuration to files.
+//!
+//! Every mirrored module is written to its own file, `<module>.json` or
+//! `<module>.xml` in the mirror directory, whenever its configuration
+//! changes. Files are replaced atomically by writing a temporary file and
+//! renaming it over the previous one.
+
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::yang::data::{Data, DataFormat, DataPrinterFlags};
+use crate::{
+    ffi, ChangeContext, Error, GetOptions, Result, Session, Subscription, SubscriptionOptions,
+};
+
+/// Options of a `ConfigMirror`.
+#[derive(Clone, Debug)]
+pub struct MirrorOptions {
+    /// Format of the written files, either JSON or XML.
+    pub format: DataFormat,
+    /// Number of previous versions kept as `<file>.1`, `<file>.2`, ... with
+    /// `<file>.1` being the most recent. No versions are kept with 0.
+    pub rotate: usize,
+    /// Timeout of reading the configuration.
+    pub timeout: Duration,
+}
+
+impl Default for MirrorOptions {
+    fn default() -> Self {
+        MirrorOptions {
+            format: DataFormat::JSON,
+            rotate: 0,
+            timeout: Duration::ZERO,
+        }
+    }
+}
+
+/// Keeps files up to date with the configuration of modules, until dropped.
+pub struct ConfigMirror<'a> {
+    _subscription: Subscription<'a>,
+}
+
+impl<'a> ConfigMirror<'a> {
+    /// Write the current configuration of the modules and subscribe to their
+    /// changes in the datastore of the session.
+    ///
+    /// The directory must exist.
+    pub fn new(
+        sess: &Session<'a>,
+        modules: &[&str],
+        dir: impl Into<PathBuf>,
+        options: MirrorOptions,
+    ) -> Result<Self> {
+        let dir = dir.into();
+        let mut subscription = Subscription::from_raw(sess.conn, std::ptr::null_mut());
+        for &module in modules {
+            let path = dir.join(match options.format {
+                DataFormat::XML => format!("{}.xml", module),
+                _ => format!("{}.json", module),
+            });
+            write_module(sess, module, &path, &options)?;
+
+            let options = options.clone();
+            let callback = move |ctx: &ChangeContext| {
+                write_module(ctx.session(), ctx.module_name(), &path, &options)
+            };
+            sess.add_module_change_subscription(
+                &mut subscription,
+                module,
+                None::<&str>,
+                callback,
+                0,
+                SubscriptionOptions::DONE_ONLY,
+            )?;
+        }
+
+        Ok(ConfigMirror {
+            _subscription: subscription,
+        })
+    }
+}
+
+fn write_module(sess: &Session, module: &str, path: &Path, options: &MirrorOptions) -> Result<()> {
+    let xpath = format!("/{}:*", module);
+    let data = match sess.get_data(&xpath, None, options.timeout, GetOptions::default()) {
+        Ok(data) => Some(data),
+        Err(Error {
+            errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+            ..
+        }) => None,
+        Err(e) => return Err(e),
+    };
+    let content = match &data {
+        Some(data) => data
+            .tree()
+            .print_string(options.format, DataPrinterFlags::WITH_SIBLINGS)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?,
+        None => String::new(),
+    };
+
+    replace_file(path, content.as_bytes(), options.rotate)
+        .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_SYS))
+}
+
+/// Atomically replace the file, keeping up to `rotate` previous versions.
+fn replace_file(path: &Path, content: &[u8], rotate: usize) -> io::Result<()> {
+    let tmp = numbered(path, "tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(content)?;
+    file.sync_all()?;
+    drop(file);
+
+    if rotate > 0 && path.exists() {
+        for i in (1..rotate).rev() {
+            let older = numbered(path, &i.to_string());
+            if older.exists() {
+                fs::rename(&older, numbered(path, &(i + 1).to_string()))?;
+            }
+        }
+        let previous = numbered(path, "1");
+        let _ = fs::remove_file(&previous);
+        fs::hard_link(path, &previous)?;
+    }
+    fs::rename(&tmp, path)
+}
+
+fn numbered(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
@@ -0,0 +1,177 @@
+//! Routing of operational data requests to per-subtree handlers.
+//!
+//! Big state models are usually provided by many functions, each for one
+//! subtree. An `OperRouter` subscribes once for every top-level node of the
+//! routes and calls only the handlers whose subtree overlaps the requested
+//! data.
+
+use crate::yang::data::DataTree;
+use crate::{ffi, Error, Result, Session, Subscription, SubscriptionOptions};
+
+/// A handler providing the operational data of a subtree.
+///
+/// It is called with the route path and the request XPath, and adds the data
+/// to the tree with absolute paths.
+pub type OperHandler = Box<dyn FnMut(&Session, &str, Option<&str>, &mut DataTree) -> Result<()>>;
+
+/// Routes operational data requests of a module to handlers.
+#[derive(Default)]
+pub struct OperRouter {
+    routes: Vec<(String, OperHandler)>,
+}
+
+impl OperRouter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a handler for the subtree at the given data path, without
+    /// predicates.
+    ///
+    /// The ancestors of the subtree must be containers. They are created
+    /// before the handler is called.
+    pub fn route<F>(mut self, path: &str, handler: F) -> Self
+    where
+        F: FnMut(&Session, &str, Option<&str>, &mut DataTree) -> Result<()> + 'static,
+    {
+        self.routes.push((path.to_owned(), Box::new(handler)));
+        self
+    }
+
+    /// Subscribe the routes of a module.
+    pub fn subscribe<'a>(
+        mut self,
+        sess: &Session<'a>,
+        mod_name: &str,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>> {
+        let mut roots: Vec<String> = Vec::new();
+        for (path, _) in &self.routes {
+            let root = path_segments(path)
+                .next()
+                .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+            let root = format!("/{}", root);
+            if !roots.contains(&root) {
+                roots.push(root);
+            }
+        }
+        let roots: Vec<&str> = roots.iter().map(String::as_str).collect();
+
+        let callback = move |sess: &Session,
+                             _sub_id: u32,
+                             _mod_name: &str,
+                             path: &str,
+                             request_xpath: Option<&str>,
+                             _request_id: u32,
+                             tree: &mut DataTree| {
+            for (route, handler) in &mut self.routes {
+                if !overlaps(route, path) {
+                    continue;
+                }
+                if let Some(request_xpath) = request_xpath {
+                    if !overlaps(route, request_xpath) {
+                        continue;
+                    }
+                }
+                if let Some((parent, _)) = route.rsplit_once('/').filter(|(p, _)| !p.is_empty()) {
+                    tree.new_path(parent, None, false)
+                        .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?;
+                }
+                handler(sess, route, request_xpath, tree)?;
+            }
+            Ok(())
+        };
+        sess.new_operational_get_subscriptions(mod_name, &roots, callback, options)
+    }
+}
+
+/// Whether the data selected by two paths may overlap, that is one path is
+/// a prefix of the other when ignoring predicates.
+///
+/// XPaths more complex than a location path always overlap.
+fn overlaps(route: &str, xpath: &str) -> bool {
+    if xpath.contains("//") || xpath.contains('|') || xpath.contains('(') {
+        return true;
+    }
+    path_segments(route)
+        .zip(path_segments(xpath))
+        .all(|(a, b)| a == "*" || b == "*" || local_name(a) == local_name(b))
+}
+
+fn local_name(segment: &str) -> &str {
+    segment.rsplit_once(':').map_or(segment, |(_, name)| name)
+}
+
+/// The node names of a path, without predicates.
+fn path_segments(path: &str) -> impl Iterator<Item = &str> {
+    let mut segments = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+    let mut quote = None;
+    for (i, c) in path.char_indices() {
+        match (c, quote) {
+            (_, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {}
+            ('\'' | '"', None) if depth > 0 => quote = Some(c),
+            ('[', None) => {
+                if depth == 0 {
+                    if let Some(s) = start.take() {
+                        segments.push(&path[s..i]);
+                    }
+                }
+                depth += 1;
+            }
+            (']', None) => depth -= 1,
+            ('/', None) if depth == 0 => {
+                if let Some(s) = start.take() {
+                    segments.push(&path[s..i]);
+                }
+            }
+            (_, None) if depth == 0 && start.is_none() => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        segments.push(&path[s..]);
+    }
+    segments.into_iter()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_segments_skip_predicates() {
+        let segments: Vec<&str> =
+            path_segments("/if:interfaces/interface[name='a/b[c]']/mtu").collect();
+        assert_eq!(segments, ["if:interfaces", "interface", "mtu"]);
+    }
+
+    #[test]
+    fn overlapping_paths() {
+        assert!(overlaps(
+            "/if:interfaces",
+            "/if:interfaces/interface[name='eth0']/mtu"
+        ));
+        assert!(overlaps("/if:interfaces/interface/mtu", "/if:interfaces"));
+        assert!(overlaps(
+            "/if:interfaces/*/mtu",
+            "/interfaces/interface/mtu"
+        ));
+        assert!(overlaps("/if:interfaces", "/if:interfaces//mtu"));
+        assert!(overlaps(
+            "/if:interfaces/interface",
+            "/a:b | /if:interfaces"
+        ));
+    }
+
+    #[test]
+    fn disjoint_paths() {
+        assert!(!overlaps("/if:interfaces", "/sys:system"));
+        assert!(!overlaps(
+            "/if:interfaces/interface/mtu",
+            "/if:interfaces/interface/name"
+        ));
+    }
+}
@@ -0,0 +1,88 @@
+//! Operational data owned by components.
+//!
+//! Where several daemons push operational data into shared models, each can
+//! tag the nodes it pushes with its own origin, an identity derived from
+//! `ietf-origin:origin` in one of its modules such as `my-agent:routing`, and
+//! later find or remove the nodes it owns. sysrepo only stores origins in the
+//! operational datastore, so configuration cannot be tagged this way.
+
+use std::time::Duration;
+
+use crate::yang::data::DataNodeRef;
+use crate::{ffi, Datastore, EditOptions, Error, GetOptions, Result, Session};
+
+impl Session<'_> {
+    /// Set an item owned by `owner`, an origin identity.
+    ///
+    /// Fails with `SR_ERR_INVAL_ARG` unless the session is on the operational
+    /// datastore.
+    pub fn set_owned_item(
+        &mut self,
+        path: &str,
+        value: &str,
+        owner: &str,
+        options: EditOptions,
+    ) -> Result<()> {
+        self.check_operational()?;
+        self.set_item_str(path, value, Some(owner), options)
+    }
+
+    /// Paths of the nodes selected by an XPath that are owned by `owner`.
+    ///
+    /// A node is owned if its origin, or else the one of its closest ancestor
+    /// with an origin, is `owner`. Only the outermost owned nodes are
+    /// returned. The timeout is rounded to the nearest millisecond.
+    pub fn owned_paths(&self, xpath: &str, owner: &str, timeout: Duration) -> Result<Vec<String>> {
+        self.check_operational()?;
+        let data = match self.get_data(xpath, None, timeout, GetOptions::WITH_ORIGIN) {
+            Ok(data) => data,
+            Err(Error {
+                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                ..
+            }) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let tree = data.tree();
+        let paths = tree
+            .traverse()
+            .filter(|node| {
+                origin(node).as_deref() == Some(owner)
+                    && node
+                        .ancestors()
+                        .next()
+                        .is_none_or(|parent| origin(&parent).as_deref() != Some(owner))
+            })
+            .map(|node| node.path())
+            .collect();
+        Ok(paths)
+    }
+
+    /// Delete the nodes selected by an XPath that are owned by `owner` and
+    /// apply the deletion, returning how many nodes were deleted.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn delete_owned(&mut self, xpath: &str, owner: &str, timeout: Duration) -> Result<usize> {
+        let paths = self.owned_paths(xpath, owner, timeout)?;
+        let paths: Vec<&str> = paths.iter().map(String::as_str).collect();
+        let results = self.delete_items(&paths, EditOptions::default(), timeout)?;
+        Ok(results.iter().filter(|result| result.is_ok()).count())
+    }
+
+    fn check_operational(&self) -> Result<()> {
+        if self.datastore() != Datastore::Operational {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+        }
+        Ok(())
+    }
+}
+
+/// The origin of a node, inherited from its ancestors if it has none.
+fn origin(node: &DataNodeRef) -> Option<String> {
+    std::iter::once(node.clone())
+        .chain(node.ancestors())
+        .find_map(|node| {
+            node.meta()
+                .find(|meta| meta.name() == "origin")
+                .map(|meta| meta.value().to_owned())
+        })
+}
@@ -0,0 +1,255 @@
+//! Export of sysrepo monitoring data in the Prometheus text format.
+//!
+//! The exporter does not serve HTTP itself. Call `Exporter::render` from the
+//! handler of any HTTP server and reply with `CONTENT_TYPE`.
+//!
+//! With the `stats` feature, the counters of the `stats` module and the
+//! statistics of the subscriptions of this process are rendered as well.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+use crate::yang::data::Data;
+use crate::{ffi, Connection, Datastore, Error, GetOptions, Result, Session};
+
+/// Content type of the rendered metrics.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+const STATE: &str = "/sysrepo-monitoring:sysrepo-state";
+
+/// Reads `sysrepo-monitoring` data and renders it as Prometheus metrics.
+pub struct Exporter<'a> {
+    sess: Session<'a>,
+    timeout: Duration,
+}
+
+impl<'a> Exporter<'a> {
+    /// Create an exporter with its own operational session.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn new(conn: &'a Connection, timeout: Duration) -> Result<Self> {
+        let sess = conn.start_session(Datastore::Operational)?;
+        Ok(Exporter { sess, timeout })
+    }
+
+    /// Read the current monitoring data and render it.
+    ///
+    /// Meant to be called on every scrape.
+    pub fn render(&self) -> Result<String> {
+        let data = match self
+            .sess
+            .get_data(STATE, None, self.timeout, GetOptions::default())
+        {
+            Ok(data) => Some(data),
+            Err(Error {
+                errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                ..
+            }) => None,
+            Err(e) => return Err(e),
+        };
+        let ly_err = |_| Error::new(ffi::sr_error_t::SR_ERR_LY);
+
+        let mut connections = 0;
+        let mut subscriptions = BTreeMap::new();
+        let mut rpc_subscriptions = BTreeMap::new();
+        let mut locks = BTreeMap::new();
+        if let Some(data) = &data {
+            let tree = data.tree();
+            connections = tree
+                .find_xpath(&format!("{}/connection", STATE))
+                .map_err(ly_err)?
+                .count();
+
+            for module in tree
+                .find_xpath(&format!("{}/module", STATE))
+                .map_err(ly_err)?
+            {
+                let name = leaf_value(&module, "name")?;
+                for sub in module.find_xpath("subscriptions/*").map_err(ly_err)? {
+                    let kind = sub.schema().name().trim_end_matches("-sub").to_owned();
+                    *subscriptions.entry((name.clone(), kind)).or_insert(0) += 1;
+                }
+                for lock in module
+                    .children()
+                    .filter(|n| n.schema().name().ends_with("lock"))
+                {
+                    let kind = lock.schema().name().to_owned();
+                    *locks.entry((name.clone(), kind)).or_insert(0) += 1;
+                }
+            }
+
+            for rpc in tree.find_xpath(&format!("{}/rpc", STATE)).map_err(ly_err)? {
+                let path = leaf_value(&rpc, "path")?;
+                let count = rpc.find_xpath("rpc-sub").map_err(ly_err)?.count();
+                rpc_subscriptions.insert(path, count);
+            }
+        }
+
+        let mut out = String::new();
+        header(
+            &mut out,
+            "gauge",
+            "sysrepo_connections",
+            "Number of connections to sysrepo.",
+        );
+        let _ = writeln!(out, "sysrepo_connections {}", connections);
+
+        header(
+            &mut out,
+            "gauge",
+            "sysrepo_subscriptions",
+            "Number of subscriptions, by module and kind.",
+        );
+        for ((module, kind), count) in &subscriptions {
+            let _ = writeln!(
+                out,
+                "sysrepo_subscriptions{{module=\"{}\",kind=\"{}\"}} {}",
+                label(module),
+                label(kind),
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "gauge",
+            "sysrepo_rpc_subscriptions",
+            "Number of subscriptions, by RPC or action path.",
+        );
+        for (path, count) in &rpc_subscriptions {
+            let _ = writeln!(
+                out,
+                "sysrepo_rpc_subscriptions{{path=\"{}\"}} {}",
+                label(path),
+                count
+            );
+        }
+
+        header(
+            &mut out,
+            "gauge",
+            "sysrepo_locks",
+            "Number of held locks, by module and kind.",
+        );
+        for ((module, kind), count) in &locks {
+            let _ = writeln!(
+                out,
+                "sysrepo_locks{{module=\"{}\",kind=\"{}\"}} {}",
+                label(module),
+                label(kind),
+                count
+            );
+        }
+
+        #[cfg(feature = "stats")]
+        render_stats(&mut out);
+
+        Ok(out)
+    }
+}
+
+#[cfg(feature = "stats")]
+fn render_stats(out: &mut String) {
+    let stats = crate::stats::snapshot();
+    header(
+        out,
+        "counter",
+        "sysrepo_ffi_calls_total",
+        "Calls of sysrepo functions, by function.",
+    );
+    for (function, count) in &stats.ffi_calls {
+        let _ = writeln!(
+            out,
+            "sysrepo_ffi_calls_total{{function=\"{}\"}} {}",
+            label(function),
+            count
+        );
+    }
+
+    header(
+        out,
+        "counter",
+        "sysrepo_context_acquisitions_total",
+        "Acquisitions of the libyang context.",
+    );
+    let _ = writeln!(
+        out,
+        "sysrepo_context_acquisitions_total {}",
+        stats.context_acquisitions
+    );
+
+    header(
+        out,
+        "counter",
+        "sysrepo_fetched_nodes_total",
+        "Data nodes returned by get_data.",
+    );
+    let _ = writeln!(out, "sysrepo_fetched_nodes_total {}", stats.fetched_nodes);
+
+    let subscriptions = crate::stats::all_subscription_stats();
+    header(
+        out,
+        "counter",
+        "sysrepo_subscription_events_total",
+        "Events handled by the callbacks of this process, by subscription ID.",
+    );
+    for (sub_id, stats) in &subscriptions {
+        let _ = writeln!(
+            out,
+            "sysrepo_subscription_events_total{{sub_id=\"{}\"}} {}",
+            sub_id, stats.events
+        );
+    }
+
+    header(
+        out,
+        "counter",
+        "sysrepo_subscription_errors_total",
+        "Events a callback of this process failed, by subscription ID.",
+    );
+    for (sub_id, stats) in &subscriptions {
+        let _ = writeln!(
+            out,
+            "sysrepo_subscription_errors_total{{sub_id=\"{}\"}} {}",
+            sub_id, stats.errors
+        );
+    }
+
+    header(
+        out,
+        "counter",
+        "sysrepo_subscription_duration_seconds_total",
+        "Time spent in the callbacks of this process, by subscription ID.",
+    );
+    for (sub_id, stats) in &subscriptions {
+        let _ = writeln!(
+            out,
+            "sysrepo_subscription_duration_seconds_total{{sub_id=\"{}\"}} {}",
+            sub_id,
+            stats.total_duration.as_secs_f64()
+        );
+    }
+}
+
+fn leaf_value<'a, T: Data<'a>>(node: &'a T, name: &str) -> Result<String> {
+    Ok(node
+        .find_xpath(name)
+        .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?
+        .next()
+        .and_then(|leaf| leaf.value_canonical())
+        .unwrap_or_default())
+}
+
+fn header(out: &mut String, kind: &str, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Escape a label value.
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
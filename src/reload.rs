@@ -0,0 +1,87 @@
+//! Reloading of daemons on SIGHUP.
+//!
+//! The signal handler only records that a reload was requested. The reload
+//! itself, such as re-reading configuration files or subscribing to newly
+//! installed modules, runs when the main loop of the daemon polls for it, so
+//! it is free to call sysrepo.
+//!
+//! Only one hook can be installed at a time, and the previous SIGHUP handler
+//! is restored once it is dropped.
+
+use std::os::raw::c_int;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{ffi, Error, Result};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sighup(_: c_int) {
+    REQUESTED.store(true, Ordering::SeqCst);
+}
+
+/// A reload hook, run after SIGHUP or an explicit request.
+pub struct ReloadHook<F> {
+    hook: F,
+    previous: libc::sigaction,
+}
+
+impl<F> ReloadHook<F>
+where
+    F: FnMut() -> Result<()>,
+{
+    /// Install the SIGHUP handler in place of the current one.
+    ///
+    /// Fails with `SR_ERR_EXISTS` if another hook is installed.
+    pub fn new(hook: F) -> Result<Self> {
+        if INSTALLED.swap(true, Ordering::SeqCst) {
+            return Err(Error::with_message(
+                ffi::sr_error_t::SR_ERR_EXISTS,
+                "a reload hook is already installed",
+            ));
+        }
+        let mut previous: libc::sigaction = unsafe { std::mem::zeroed() };
+        let rc = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = on_sighup as extern "C" fn(c_int) as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(libc::SIGHUP, &action, &mut previous)
+        };
+        if rc != 0 {
+            INSTALLED.store(false, Ordering::SeqCst);
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_SYS));
+        }
+        REQUESTED.store(false, Ordering::SeqCst);
+        Ok(ReloadHook { hook, previous })
+    }
+
+    /// Request a reload, as SIGHUP does.
+    pub fn request(&self) {
+        REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    /// Run the hook if a reload was requested, returning whether it ran.
+    ///
+    /// Requests made while the hook runs cause another reload on the next
+    /// poll. With the `systemd` feature, systemd is notified of the reload.
+    pub fn poll(&mut self) -> Result<bool> {
+        if !REQUESTED.swap(false, Ordering::SeqCst) {
+            return Ok(false);
+        }
+        #[cfg(feature = "systemd")]
+        crate::systemd::reloading()?;
+        let res = (self.hook)();
+        #[cfg(feature = "systemd")]
+        crate::systemd::ready()?;
+        res.map(|()| true)
+    }
+}
+
+impl<F> Drop for ReloadHook<F> {
+    fn drop(&mut self) {
+        unsafe { libc::sigaction(libc::SIGHUP, &self.previous, ptr::null_mut()) };
+        INSTALLED.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,415 @@
+//! Async support, independent of the async runtime.
+//!
+//! Change streams use [`SubscriptionOptions::NO_THREAD`] and process their
+//! events when the runtime reports the event pipe of the subscription
+//! readable, instead of on a sysrepo thread. Async RPC handlers are spawned on
+//! the runtime while the sysrepo thread waits for them.
+//!
+//! The runtime is plugged in through [`Runtime`]. Implementations for tokio,
+//! smol and async-std are provided behind the features of the same names.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::io;
+use std::os::fd::RawFd;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Waker};
+use std::time::Duration;
+
+use futures_core::Stream;
+
+use crate::yang::data::DataTree;
+use crate::{
+    ffi, tree_to_items, ChangeContext, ChangeOperation, Error, Event, Item, Priority, Result,
+    RpcContext, Session, Subscription, SubscriptionOptions, ToCStr,
+};
+
+/// An async runtime, able to spawn tasks and wait for file descriptors to
+/// become readable.
+pub trait Runtime: Clone + 'static {
+    /// A file descriptor registered with the runtime.
+    type Fd: ReadableFd;
+
+    /// Run a task in the background, without waiting for it.
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static;
+
+    /// Run a task in the background, returning a handle to abort it.
+    fn spawn_abortable<F>(&self, future: F) -> AbortHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = AbortHandle::default();
+        self.spawn(Abortable {
+            future: Box::pin(future),
+            state: handle.state.clone(),
+        });
+        handle
+    }
+
+    /// Register a file descriptor to wait for it to become readable.
+    ///
+    /// The file descriptor stays open for as long as the registration lives,
+    /// but is not closed by it. It may be switched to non-blocking mode.
+    fn register(&self, fd: RawFd) -> io::Result<Self::Fd>;
+}
+
+/// A file descriptor registered with a [`Runtime`].
+pub trait ReadableFd: Unpin {
+    /// Poll the file descriptor for readability.
+    ///
+    /// Once ready is returned, the readiness is consumed and the next poll
+    /// waits for new data.
+    fn poll_readable(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+/// A handle to abort a task spawned with [`Runtime::spawn_abortable`].
+#[derive(Clone, Debug, Default)]
+pub struct AbortHandle {
+    state: Arc<AbortState>,
+}
+
+#[derive(Debug, Default)]
+struct AbortState {
+    aborted: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl AbortHandle {
+    /// Abort the task.
+    ///
+    /// The task is dropped the next time the runtime polls it, so code it is
+    /// already running, such as a blocking call, still completes first.
+    pub fn abort(&self) {
+        self.state.aborted.store(true, Ordering::SeqCst);
+        if let Some(waker) = self.state.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+struct Abortable<F> {
+    future: Pin<Box<F>>,
+    state: Arc<AbortState>,
+}
+
+impl<F: Future<Output = ()>> Future for Abortable<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        *this.state.waker.lock().unwrap() = Some(cx.waker().clone());
+        // Checked after storing the waker, so an abort is never missed.
+        if this.state.aborted.load(Ordering::SeqCst) {
+            return Poll::Ready(());
+        }
+        this.future.as_mut().poll(cx)
+    }
+}
+
+/// A single change of a [`ChangeBatch`].
+#[derive(Clone, Debug)]
+pub struct Change {
+    /// Path of the changed node.
+    pub path: String,
+    /// Canonical value of the changed node, if it is a term node.
+    pub value: Option<String>,
+    pub operation: OwnedChangeOperation,
+}
+
+/// An owned version of [`ChangeOperation`].
+#[derive(Clone, Debug)]
+pub enum OwnedChangeOperation {
+    Created,
+    CreatedLeafListUserOrdered {
+        previous_value: String,
+    },
+    CreatedListUserOrdered {
+        previous_key: String,
+    },
+    Modified {
+        previous_value: String,
+        previous_default: bool,
+    },
+    Deleted,
+    MovedLeafListUserOrdered {
+        previous_value: String,
+    },
+    MovedListUserOrdered {
+        previous_key: String,
+    },
+    Other(ffi::sr_change_oper_t::Type),
+}
+
+impl From<&ChangeOperation<'_>> for OwnedChangeOperation {
+    fn from(oper: &ChangeOperation<'_>) -> Self {
+        match *oper {
+            ChangeOperation::Created => OwnedChangeOperation::Created,
+            ChangeOperation::CreatedLeafListUserOrdered { previous_value } => {
+                OwnedChangeOperation::CreatedLeafListUserOrdered {
+                    previous_value: previous_value.to_owned(),
+                }
+            }
+            ChangeOperation::CreatedListUserOrdered { previous_key } => {
+                OwnedChangeOperation::CreatedListUserOrdered {
+                    previous_key: previous_key.to_owned(),
+                }
+            }
+            ChangeOperation::Modified {
+                previous_value,
+                previous_default,
+            } => OwnedChangeOperation::Modified {
+                previous_value: previous_value.to_owned(),
+                previous_default,
+            },
+            ChangeOperation::Deleted => OwnedChangeOperation::Deleted,
+            ChangeOperation::MovedLeafListUserOrdered { previous_value } => {
+                OwnedChangeOperation::MovedLeafListUserOrdered {
+                    previous_value: previous_value.to_owned(),
+                }
+            }
+            ChangeOperation::MovedListUserOrdered { previous_key } => {
+                OwnedChangeOperation::MovedListUserOrdered {
+                    previous_key: previous_key.to_owned(),
+                }
+            }
+            ChangeOperation::Other(oper) => OwnedChangeOperation::Other(oper),
+        }
+    }
+}
+
+/// All changes delivered to a module change subscription for one event.
+#[derive(Clone, Debug)]
+pub struct ChangeBatch {
+    pub module_name: String,
+    pub event: Event,
+    pub request_id: u32,
+    pub changes: Vec<Change>,
+}
+
+/// A stream of the changes made to a module.
+///
+/// The stream only observes changes, every event is accepted as soon as its
+/// changes are collected.
+pub struct ChangeStream<'a, R: Runtime> {
+    // Deregister the pipe before the subscription closes it.
+    pipe: R::Fd,
+    subscription: Subscription<'a>,
+    batches: Arc<Mutex<VecDeque<ChangeBatch>>>,
+}
+
+impl<'a, R: Runtime> ChangeStream<'a, R> {
+    /// Subscribe to changes of a module and return them as a stream, whose
+    /// events are processed on `runtime`.
+    pub fn new(
+        runtime: &R,
+        sess: &Session<'a>,
+        mod_name: &str,
+        xpath: Option<&str>,
+        priority: impl Into<Priority>,
+        options: SubscriptionOptions,
+    ) -> Result<Self> {
+        let batches = Arc::new(Mutex::new(VecDeque::new()));
+        let queue = batches.clone();
+        let callback = move |ctx: &ChangeContext| {
+            let changes = ctx.changes()?;
+            let changes = changes
+                .iter()
+                .map(|change| {
+                    let (node, oper) = change?;
+                    Ok(Change {
+                        path: node.reference().map(|n| n.path()).unwrap_or_default(),
+                        value: node.reference().and_then(|n| n.value_canonical()),
+                        operation: OwnedChangeOperation::from(&oper),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            queue.lock().unwrap().push_back(ChangeBatch {
+                module_name: ctx.module_name().to_owned(),
+                event: ctx.event(),
+                request_id: ctx.request_id(),
+                changes,
+            });
+            Ok(())
+        };
+
+        let subscription = sess.new_module_change_subscription(
+            mod_name,
+            xpath,
+            callback,
+            priority,
+            options | SubscriptionOptions::NO_THREAD,
+        )?;
+        let pipe = event_pipe(&subscription)?;
+        let pipe = runtime
+            .register(pipe)
+            .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_SYS))?;
+
+        Ok(ChangeStream {
+            pipe,
+            subscription,
+            batches,
+        })
+    }
+}
+
+impl<R: Runtime> Stream for ChangeStream<'_, R> {
+    type Item = Result<ChangeBatch>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Some(batch) = this.batches.lock().unwrap().pop_front() {
+                return Poll::Ready(Some(Ok(batch)));
+            }
+
+            if ready!(this.pipe.poll_readable(cx)).is_err() {
+                return Poll::Ready(Some(Err(Error::new(ffi::sr_error_t::SR_ERR_SYS))));
+            }
+            if let Err(e) = process_events(&this.subscription) {
+                return Poll::Ready(Some(Err(e)));
+            }
+        }
+    }
+}
+
+/// An RPC or action invocation passed to an async handler.
+#[derive(Clone, Debug)]
+pub struct RpcRequest {
+    pub sub_id: u32,
+    pub op_path: String,
+    pub event: Event,
+    pub request_id: u32,
+    pub input: Vec<Item>,
+}
+
+/// The reply of an async RPC or action handler.
+#[derive(Clone, Debug, Default)]
+pub struct RpcReply {
+    /// Output nodes, with absolute paths.
+    pub output: Vec<Item>,
+}
+
+impl<'a> Session<'a> {
+    /// Subscribe an async handler for an RPC or action.
+    ///
+    /// The handler is spawned on `runtime`. The sysrepo thread waits for the
+    /// handler for at most `timeout`, so events must not be processed from a
+    /// runtime thread. Once the timeout is reached, the RPC fails with
+    /// `SR_ERR_TIME_OUT` and the handler task is aborted, see
+    /// [`AbortHandle::abort`]; what the handler already did is not undone.
+    pub fn new_async_rpc_subscription<R, F, Fut>(
+        &self,
+        runtime: &R,
+        xpath: impl ToCStr,
+        handler: F,
+        timeout: Duration,
+        priority: impl Into<Priority>,
+        options: SubscriptionOptions,
+    ) -> Result<Subscription<'a>>
+    where
+        R: Runtime,
+        F: Fn(RpcRequest) -> Fut + 'static,
+        Fut: Future<Output = Result<RpcReply>> + Send + 'static,
+    {
+        let callback = async_rpc_callback(runtime.clone(), handler, timeout);
+        self.new_rpc_subscription(xpath, callback, priority, options)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn add_async_rpc_subscription<R, F, Fut>(
+        &self,
+        runtime: &R,
+        subscription: &mut Subscription<'a>,
+        xpath: impl ToCStr,
+        handler: F,
+        timeout: Duration,
+        priority: impl Into<Priority>,
+        options: SubscriptionOptions,
+    ) -> Result<()>
+    where
+        R: Runtime,
+        F: Fn(RpcRequest) -> Fut + 'static,
+        Fut: Future<Output = Result<RpcReply>> + Send + 'static,
+    {
+        let callback = async_rpc_callback(runtime.clone(), handler, timeout);
+        self.add_rpc_subscription(subscription, xpath, callback, priority, options)
+    }
+}
+
+fn async_rpc_callback<R, F, Fut>(
+    runtime: R,
+    handler: F,
+    timeout: Duration,
+) -> impl FnMut(&RpcContext, &mut DataTree) -> Result<()> + 'static
+where
+    R: Runtime,
+    F: Fn(RpcRequest) -> Fut + 'static,
+    Fut: Future<Output = Result<RpcReply>> + Send + 'static,
+{
+    move |ctx: &RpcContext, output: &mut DataTree| {
+        let request = RpcRequest {
+            sub_id: ctx.sub_id(),
+            op_path: ctx.op_path().to_owned(),
+            event: ctx.event(),
+            request_id: ctx.request_id(),
+            input: tree_to_items(ctx.input()),
+        };
+        let task = handler(request);
+        let (reply_tx, reply_rx) = mpsc::sync_channel(1);
+        let task = runtime.spawn_abortable(async move {
+            let _ = reply_tx.send(task.await);
+        });
+        let reply = match reply_rx.recv_timeout(timeout) {
+            Ok(reply) => reply?,
+            // The handler panicked.
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(Error::new(ffi::sr_error_t::SR_ERR_CALLBACK_FAILED))
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                task.abort();
+                return Err(Error::new(ffi::sr_error_t::SR_ERR_TIME_OUT));
+            }
+        };
+
+        for item in &reply.output {
+            output
+                .new_path(&item.xpath, item.value.as_string().as_deref(), true)
+                .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_LY))?;
+        }
+        Ok(())
+    }
+}
+
+/// The event pipe of a subscription, which becomes readable when there are
+/// events to process.
+fn event_pipe(subscription: &Subscription) -> Result<RawFd> {
+    let mut fd = -1;
+    let rc = unsafe { ffi::sr_subscription_get_event_pipe(subscription.subscr, &mut fd) };
+    let rc = rc as ffi::sr_error_t::Type;
+    if rc != ffi::sr_error_t::SR_ERR_OK {
+        Err(Error::new(rc))
+    } else {
+        Ok(fd)
+    }
+}
+
+fn process_events(subscription: &Subscription) -> Result<()> {
+    let rc = unsafe {
+        ffi::sr_subscription_process_events(
+            subscription.subscr,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+        )
+    };
+    let rc = rc as ffi::sr_error_t::Type;
+    if rc != ffi::sr_error_t::SR_ERR_OK {
+        Err(Error::new(rc))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+//! The smol [`Runtime`].
+
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsFd, BorrowedFd, RawFd};
+use std::task::{Context, Poll};
+
+use ::smol::Async;
+
+use crate::runtime::{ReadableFd, Runtime};
+
+/// The global smol executor.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SmolRuntime;
+
+impl Runtime for SmolRuntime {
+    type Fd = SmolFd;
+
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        ::smol::spawn(future).detach();
+    }
+
+    fn register(&self, fd: RawFd) -> io::Result<SmolFd> {
+        Async::new(Fd(fd)).map(SmolFd)
+    }
+}
+
+/// A file descriptor registered with a [`SmolRuntime`].
+pub struct SmolFd(Async<Fd>);
+
+impl ReadableFd for SmolFd {
+    fn poll_readable(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.0.poll_readable(cx)
+    }
+}
+
+struct Fd(RawFd);
+
+impl AsFd for Fd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        // The file descriptor outlives its registration.
+        unsafe { BorrowedFd::borrow_raw(self.0) }
+    }
+}
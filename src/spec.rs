@@ -0,0 +1,353 @@
+//! Declarative descriptions of subscriptions.
+//!
+//! A `SubscriptionSpec` describes what to subscribe to, one per line of text
+//! so they can be kept in configuration files:
+//!
+//! ```text
+//! # kind module [key=value]... [xpath=...]
+//! module-change ietf-interfaces priority=10 options=DONE_ONLY handler=ifaces
+//! rpc ietf-system handler=restart xpath=/ietf-system:system-restart
+//! oper-poll ietf-interfaces valid-ms=1000 xpath=/ietf-interfaces:interfaces
+//! ```
+//!
+//! The XPath is the rest of the line, so it may contain spaces. Callbacks are
+//! made by the factories registered in `Handlers` under the handler name, so
+//! the same specs can be subscribed again, for example after reconnecting.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ptr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::yang::data::DataTree;
+use crate::{
+    ffi, ChangeContext, Error, ModuleChangeHandler, NotifContext, Priority, ReplayWindow, Result,
+    RpcContext, RpcHandler, Session, Subscription, SubscriptionKind, SubscriptionOptions,
+};
+
+/// A boxed notification callback.
+pub type NotificationHandler = Box<dyn FnMut(&NotifContext, &DataTree)>;
+
+/// A boxed operational get callback.
+pub type OperGetHandler =
+    Box<dyn FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>>;
+
+/// The callback of a subscription, of the kind of its spec.
+pub enum Handler {
+    ModuleChange(ModuleChangeHandler),
+    OperationalGet(OperGetHandler),
+    Rpc(RpcHandler),
+    Notification(NotificationHandler),
+}
+
+impl Handler {
+    pub fn module_change<F>(callback: F) -> Self
+    where
+        F: FnMut(&ChangeContext) -> Result<()> + 'static,
+    {
+        Handler::ModuleChange(Box::new(callback))
+    }
+
+    pub fn operational_get<F>(callback: F) -> Self
+    where
+        F: FnMut(&Session, u32, &str, &str, Option<&str>, u32, &mut DataTree) -> Result<()>
+            + 'static,
+    {
+        Handler::OperationalGet(Box::new(callback))
+    }
+
+    pub fn rpc<F>(callback: F) -> Self
+    where
+        F: FnMut(&RpcContext, &mut DataTree) -> Result<()> + 'static,
+    {
+        Handler::Rpc(Box::new(callback))
+    }
+
+    pub fn notification<F>(callback: F) -> Self
+    where
+        F: FnMut(&NotifContext, &DataTree) + 'static,
+    {
+        Handler::Notification(Box::new(callback))
+    }
+}
+
+/// Factories of the callbacks of subscription specs, by handler name.
+#[derive(Default)]
+pub struct Handlers {
+    factories: HashMap<String, Box<dyn FnMut() -> Handler>>,
+}
+
+impl Handlers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a factory making the callbacks of a handler name, called
+    /// every time a spec with the name is subscribed.
+    pub fn register<F>(mut self, name: &str, factory: F) -> Self
+    where
+        F: FnMut() -> Handler + 'static,
+    {
+        self.factories.insert(name.to_owned(), Box::new(factory));
+        self
+    }
+
+    fn make(&mut self, name: Option<&str>) -> Result<Handler> {
+        let factory = name
+            .and_then(|name| self.factories.get_mut(name))
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+        Ok(factory())
+    }
+}
+
+/// A description of a subscription.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SubscriptionSpec {
+    pub kind: SubscriptionKind,
+    pub module: String,
+    /// The XPath subscribed to, required for RPCs and operational data.
+    pub xpath: Option<String>,
+    /// Priority of module change and RPC subscriptions.
+    pub priority: Priority,
+    pub options: SubscriptionOptions,
+    /// Name of the handler in `Handlers`, unused by operational poll
+    /// subscriptions.
+    pub handler: Option<String>,
+    /// How long polled operational data stay cached.
+    pub valid: Duration,
+}
+
+impl SubscriptionSpec {
+    pub fn new(kind: SubscriptionKind, module: &str) -> Self {
+        SubscriptionSpec {
+            kind,
+            module: module.to_owned(),
+            xpath: None,
+            priority: Priority::default(),
+            options: SubscriptionOptions::default(),
+            handler: None,
+            valid: Duration::ZERO,
+        }
+    }
+
+    /// Subscribe the spec into an existing subscription, making its callback
+    /// with `handlers`.
+    ///
+    /// Fails with `SR_ERR_NOT_FOUND` if the handler is not registered and
+    /// `SR_ERR_INVAL_ARG` if it is of another kind than the spec.
+    pub fn subscribe<'a>(
+        &self,
+        sess: &Session<'a>,
+        subscription: &mut Subscription<'a>,
+        handlers: &mut Handlers,
+    ) -> Result<()> {
+        let inval = Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+        let xpath = self.xpath.as_deref();
+        let options = self.options.clone();
+
+        if self.kind == SubscriptionKind::OperationalPoll {
+            let xpath = xpath.ok_or(inval)?;
+            return sess.add_oper_poll_subscription(
+                subscription,
+                &self.module,
+                xpath,
+                self.valid,
+                options,
+            );
+        }
+        match (self.kind, handlers.make(self.handler.as_deref())?) {
+            (SubscriptionKind::ModuleChange, Handler::ModuleChange(callback)) => sess
+                .add_module_change_subscription(
+                    subscription,
+                    &self.module,
+                    xpath,
+                    callback,
+                    self.priority,
+                    options,
+                ),
+            (SubscriptionKind::OperationalGet, Handler::OperationalGet(callback)) => {
+                let xpath = xpath.ok_or(inval)?;
+                sess.add_operational_get_subscription(
+                    subscription,
+                    &self.module,
+                    xpath,
+                    callback,
+                    options,
+                )
+            }
+            (SubscriptionKind::Rpc, Handler::Rpc(callback)) => {
+                let xpath = xpath.ok_or(inval)?;
+                sess.add_rpc_subscription(subscription, xpath, callback, self.priority, options)
+            }
+            (SubscriptionKind::Notification, Handler::Notification(callback)) => sess
+                .add_notification_subscription(
+                    subscription,
+                    &self.module,
+                    xpath,
+                    ReplayWindow::LiveOnly,
+                    callback,
+                    options,
+                ),
+            _ => Err(inval),
+        }
+    }
+}
+
+/// Subscribe all specs into a single subscription.
+pub fn subscribe_all<'a>(
+    sess: &Session<'a>,
+    specs: &[SubscriptionSpec],
+    handlers: &mut Handlers,
+) -> Result<Subscription<'a>> {
+    let mut subscription = Subscription::from_raw(sess.connection(), ptr::null_mut());
+    for spec in specs {
+        spec.subscribe(sess, &mut subscription, handlers)?;
+    }
+    Ok(subscription)
+}
+
+/// Parse the specs of a text, one per line.
+///
+/// Empty lines and lines starting with `#` are skipped.
+pub fn parse_specs(text: &str) -> Result<Vec<SubscriptionSpec>> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::parse)
+        .collect()
+}
+
+fn kind_name(kind: SubscriptionKind) -> &'static str {
+    match kind {
+        SubscriptionKind::ModuleChange => "module-change",
+        SubscriptionKind::OperationalGet => "oper-get",
+        SubscriptionKind::OperationalPoll => "oper-poll",
+        SubscriptionKind::Rpc => "rpc",
+        SubscriptionKind::Notification => "notification",
+    }
+}
+
+impl fmt::Display for SubscriptionSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", kind_name(self.kind), self.module)?;
+        if self.priority != Priority::default() {
+            write!(f, " priority={}", self.priority.0)?;
+        }
+        if !self.options.is_empty() {
+            let names: Vec<&str> = self.options.iter_names().map(|(name, _)| name).collect();
+            write!(f, " options={}", names.join("|"))?;
+        }
+        if let Some(handler) = &self.handler {
+            write!(f, " handler={}", handler)?;
+        }
+        if !self.valid.is_zero() {
+            write!(f, " valid-ms={}", self.valid.as_millis())?;
+        }
+        if let Some(xpath) = &self.xpath {
+            write!(f, " xpath={}", xpath)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for SubscriptionSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let inval = || Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+
+        let (s, xpath) = match s.find("xpath=") {
+            Some(i) => (&s[..i], Some(s[i + "xpath=".len()..].trim().to_owned())),
+            None => (s, None),
+        };
+        let mut words = s.split_whitespace();
+        let kind = match words.next().ok_or_else(inval)? {
+            "module-change" => SubscriptionKind::ModuleChange,
+            "oper-get" => SubscriptionKind::OperationalGet,
+            "oper-poll" => SubscriptionKind::OperationalPoll,
+            "rpc" => SubscriptionKind::Rpc,
+            "notification" => SubscriptionKind::Notification,
+            _ => return Err(inval()),
+        };
+        let mut spec = SubscriptionSpec::new(kind, words.next().ok_or_else(inval)?);
+        spec.xpath = xpath.filter(|xpath| !xpath.is_empty());
+
+        for word in words {
+            let (key, value) = word.split_once('=').ok_or_else(inval)?;
+            match key {
+                "priority" => spec.priority = Priority(value.parse().map_err(|_| inval())?),
+                "options" => {
+                    for name in value.split('|') {
+                        spec.options |= SubscriptionOptions::from_name(name).ok_or_else(inval)?;
+                    }
+                }
+                "handler" => spec.handler = Some(value.to_owned()),
+                "valid-ms" => {
+                    spec.valid = Duration::from_millis(value.parse().map_err(|_| inval())?)
+                }
+                _ => return Err(inval()),
+            }
+        }
+        Ok(spec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spec_parses() {
+        let spec: SubscriptionSpec =
+            "module-change ietf-interfaces priority=10 options=DONE_ONLY handler=ifaces xpath=/if:interfaces/interface[name='a b']"
+                .parse()
+                .unwrap();
+        assert_eq!(spec.kind, SubscriptionKind::ModuleChange);
+        assert_eq!(spec.module, "ietf-interfaces");
+        assert_eq!(spec.priority, Priority(10));
+        assert_eq!(spec.options, SubscriptionOptions::DONE_ONLY);
+        assert_eq!(spec.handler.as_deref(), Some("ifaces"));
+        assert_eq!(
+            spec.xpath.as_deref(),
+            Some("/if:interfaces/interface[name='a b']")
+        );
+    }
+
+    #[test]
+    fn spec_rejects_malformed() {
+        assert!("".parse::<SubscriptionSpec>().is_err());
+        assert!("rpc".parse::<SubscriptionSpec>().is_err());
+        assert!("unknown ietf-system".parse::<SubscriptionSpec>().is_err());
+        assert!("rpc ietf-system priority=high"
+            .parse::<SubscriptionSpec>()
+            .is_err());
+        assert!("rpc ietf-system options=NOPE"
+            .parse::<SubscriptionSpec>()
+            .is_err());
+        assert!("rpc ietf-system color=red"
+            .parse::<SubscriptionSpec>()
+            .is_err());
+    }
+
+    #[test]
+    fn spec_display_roundtrips() {
+        let mut spec = SubscriptionSpec::new(SubscriptionKind::OperationalPoll, "ietf-interfaces");
+        spec.options = SubscriptionOptions::UPDATE | SubscriptionOptions::PASSIVE;
+        spec.valid = Duration::from_millis(1000);
+        spec.xpath = Some("/ietf-interfaces:interfaces".to_owned());
+        assert_eq!(spec.to_string().parse::<SubscriptionSpec>().unwrap(), spec);
+
+        let spec = SubscriptionSpec::new(SubscriptionKind::Notification, "ietf-system");
+        assert_eq!(spec.to_string(), "notification ietf-system");
+    }
+
+    #[test]
+    fn specs_skip_comments_and_blank_lines() {
+        let specs =
+            parse_specs("# subscriptions\n\nrpc ietf-system handler=restart xpath=/sys:restart\n")
+                .unwrap();
+        assert_eq!(specs.len(), 1);
+        assert_eq!(specs[0].kind, SubscriptionKind::Rpc);
+    }
+}
@@ -0,0 +1,112 @@
+//! Counters of the work done through the bindings.
+//!
+//! The counters are global to the process and only kept when the `stats`
+//! feature is enabled. They help to find, for example, `get_data` called for
+//! every leaf instead of once for a subtree.
+//!
+//! The statistics of the callbacks of subscriptions, returned by
+//! `Subscription::stats`, are kept the same way.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// A snapshot of the counters.
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    /// Calls of the main sysrepo functions, by function name.
+    pub ffi_calls: BTreeMap<&'static str, u64>,
+    /// Acquisitions of the libyang context.
+    pub context_acquisitions: u64,
+    /// Data nodes returned by `Session::get_data`.
+    pub fetched_nodes: u64,
+    /// Callback invocations, by subscription ID.
+    pub callbacks: BTreeMap<u32, u64>,
+}
+
+static STATS: Mutex<Stats> = Mutex::new(Stats {
+    ffi_calls: BTreeMap::new(),
+    context_acquisitions: 0,
+    fetched_nodes: 0,
+    callbacks: BTreeMap::new(),
+});
+
+/// Statistics of the callback of a subscription.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SubscriptionStats {
+    /// Events the callback was called for.
+    pub events: u64,
+    /// Events the callback returned an error for.
+    pub errors: u64,
+    /// When the callback was last called.
+    pub last_event: Option<SystemTime>,
+    /// Time spent in the callback over all events.
+    pub total_duration: Duration,
+}
+
+impl SubscriptionStats {
+    /// Average time spent in the callback per event.
+    pub fn average_duration(&self) -> Duration {
+        if self.events == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_nanos((self.total_duration.as_nanos() / self.events as u128) as u64)
+    }
+}
+
+static SUBSCRIPTION_STATS: Mutex<BTreeMap<u32, SubscriptionStats>> = Mutex::new(BTreeMap::new());
+
+/// Get the current counters.
+pub fn snapshot() -> Stats {
+    STATS.lock().unwrap().clone()
+}
+
+/// Reset all counters to zero.
+pub fn reset() {
+    *STATS.lock().unwrap() = Stats::default();
+}
+
+pub(crate) fn ffi_call(name: &'static str) {
+    *STATS.lock().unwrap().ffi_calls.entry(name).or_insert(0) += 1;
+}
+
+pub(crate) fn context_acquired() {
+    STATS.lock().unwrap().context_acquisitions += 1;
+}
+
+pub(crate) fn fetched_nodes(count: u64) {
+    STATS.lock().unwrap().fetched_nodes += count;
+}
+
+pub(crate) fn callback(sub_id: u32) {
+    *STATS.lock().unwrap().callbacks.entry(sub_id).or_insert(0) += 1;
+}
+
+pub(crate) fn subscription_stats(sub_ids: &[u32]) -> BTreeMap<u32, SubscriptionStats> {
+    let stats = SUBSCRIPTION_STATS.lock().unwrap();
+    sub_ids
+        .iter()
+        .map(|id| (*id, stats.get(id).copied().unwrap_or_default()))
+        .collect()
+}
+
+pub(crate) fn all_subscription_stats() -> BTreeMap<u32, SubscriptionStats> {
+    SUBSCRIPTION_STATS.lock().unwrap().clone()
+}
+
+pub(crate) fn event(sub_id: u32, start: Instant, failed: bool) {
+    let duration = start.elapsed();
+    let mut stats = SUBSCRIPTION_STATS.lock().unwrap();
+    let stats = stats.entry(sub_id).or_default();
+    stats.events += 1;
+    stats.errors += failed as u64;
+    stats.last_event = Some(SystemTime::now());
+    stats.total_duration += duration;
+}
+
+pub(crate) fn unsubscribed(sub_ids: &[u32]) {
+    let mut stats = SUBSCRIPTION_STATS.lock().unwrap();
+    for id in sub_ids {
+        stats.remove(id);
+    }
+}
@@ -0,0 +1,76 @@
+//! Notifications of the state of a daemon to systemd, as `sd_notify(3)`.
+//!
+//! These let daemons run as `Type=notify` services with a `WatchdogSec`
+//! without depending on libsystemd. Outside of systemd, when
+//! `NOTIFY_SOCKET` is not set, the notifications do nothing.
+
+use std::env;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::net::UnixDatagram;
+use std::time::Duration;
+
+use crate::{sys_err, Result};
+
+/// Send a notification of newline separated `VARIABLE=value` assignments.
+///
+/// Returns whether the notification was sent, that is whether the process
+/// runs under systemd.
+pub fn notify(state: &str) -> Result<bool> {
+    let Some(path) = env::var_os("NOTIFY_SOCKET") else {
+        return Ok(false);
+    };
+    let socket = UnixDatagram::unbound().map_err(sys_err)?;
+    match path.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name).map_err(sys_err)?;
+            socket.send_to_addr(state.as_bytes(), &addr)
+        }
+        #[cfg(not(target_os = "linux"))]
+        Some(_) => return Err(Error::new(ffi::sr_error_t::SR_ERR_UNSUPPORTED)),
+        None => socket.send_to(state.as_bytes(), &path),
+    }
+    .map_err(sys_err)?;
+    Ok(true)
+}
+
+/// Notify that the daemon finished starting up.
+pub fn ready() -> Result<bool> {
+    notify("READY=1")
+}
+
+/// Notify that the daemon is reloading its configuration. Send `ready` once
+/// it is done.
+pub fn reloading() -> Result<bool> {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    let usec = now.tv_sec as u64 * 1_000_000 + now.tv_nsec as u64 / 1_000;
+    notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", usec))
+}
+
+/// Notify that the daemon is shutting down.
+pub fn stopping() -> Result<bool> {
+    notify("STOPPING=1")
+}
+
+/// Keep the watchdog from restarting the daemon.
+pub fn watchdog() -> Result<bool> {
+    notify("WATCHDOG=1")
+}
+
+/// The watchdog timeout of the daemon, if systemd has one for it.
+///
+/// `watchdog` should be called at least every half of it.
+pub fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(std::process::id()) {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
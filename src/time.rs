@@ -0,0 +1,56 @@
+//! Conversions between `SystemTime` and the C `timespec` used by sysrepo.
+//!
+//! The plain conversions saturate or wrap like the casts of the standard
+//! library, while the checked ones fail on times that cannot be represented.
+
+use std::time::{Duration, SystemTime};
+
+pub use crate::yang::ffi::timespec;
+
+/// Convert a time to a `timespec`. Times before the epoch become the epoch.
+pub fn to_timespec(time: SystemTime) -> timespec {
+    let d = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default();
+    timespec {
+        tv_sec: d.as_secs() as _,
+        tv_nsec: d.subsec_nanos() as _,
+    }
+}
+
+/// Convert a time to a `timespec`, or `None` if it is before the epoch or
+/// too far in the future.
+pub fn checked_to_timespec(time: SystemTime) -> Option<timespec> {
+    let d = time.duration_since(SystemTime::UNIX_EPOCH).ok()?;
+    Some(timespec {
+        tv_sec: d.as_secs().try_into().ok()?,
+        // Always below a billion, so it fits a 32-bit `c_long`.
+        tv_nsec: d.subsec_nanos() as _,
+    })
+}
+
+/// Convert a `timespec` to a time. Nanoseconds out of range are clamped, and
+/// times that are not representable become the epoch.
+pub fn from_timespec(ts: &timespec) -> SystemTime {
+    // `time_t` is only 32 bits on some targets.
+    #[allow(clippy::unnecessary_cast)]
+    let secs = Duration::from_secs(ts.tv_sec.unsigned_abs() as u64);
+    let nanos = Duration::from_nanos(ts.tv_nsec.clamp(0, 999_999_999) as u64);
+    let time = if ts.tv_sec < 0 {
+        SystemTime::UNIX_EPOCH.checked_sub(secs)
+    } else {
+        SystemTime::UNIX_EPOCH.checked_add(secs)
+    };
+    time.and_then(|time| time.checked_add(nanos))
+        .unwrap_or(SystemTime::UNIX_EPOCH)
+}
+
+/// Convert a `timespec` to a time, or `None` if it is before the epoch, not
+/// normalized or not representable.
+pub fn checked_from_timespec(ts: &timespec) -> Option<SystemTime> {
+    let secs = u64::try_from(ts.tv_sec).ok()?;
+    let nanos = u32::try_from(ts.tv_nsec)
+        .ok()
+        .filter(|&nanos| nanos < 1_000_000_000)?;
+    SystemTime::UNIX_EPOCH.checked_add(Duration::new(secs, nanos))
+}
@@ -0,0 +1,65 @@
+//! The tokio [`Runtime`].
+
+use std::future::Future;
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::task::{ready, Context, Poll};
+
+use ::tokio::io::unix::AsyncFd;
+use ::tokio::runtime::Handle;
+
+use crate::runtime::{ReadableFd, Runtime};
+
+/// A tokio runtime, referred to by its handle.
+#[derive(Clone, Debug)]
+pub struct TokioRuntime(Handle);
+
+impl TokioRuntime {
+    /// The runtime the caller runs in.
+    ///
+    /// Panics if called outside of a tokio runtime.
+    pub fn current() -> Self {
+        TokioRuntime(Handle::current())
+    }
+}
+
+impl From<Handle> for TokioRuntime {
+    fn from(handle: Handle) -> Self {
+        TokioRuntime(handle)
+    }
+}
+
+impl Runtime for TokioRuntime {
+    type Fd = TokioFd;
+
+    fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.0.spawn(future);
+    }
+
+    fn register(&self, fd: RawFd) -> io::Result<TokioFd> {
+        let _guard = self.0.enter();
+        AsyncFd::new(Fd(fd)).map(TokioFd)
+    }
+}
+
+/// A file descriptor registered with a [`TokioRuntime`].
+pub struct TokioFd(AsyncFd<Fd>);
+
+impl ReadableFd for TokioFd {
+    fn poll_readable(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut guard = ready!(self.0.poll_read_ready(cx))?;
+        guard.clear_ready();
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct Fd(RawFd);
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
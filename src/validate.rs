@@ -0,0 +1,116 @@
+//! Declarative validation of configuration changes.
+//!
+//! A `Validator` holds constraints on the configuration of a module. It is
+//! subscribed to the changes of the module and checks the configuration as it
+//! would be after every change, rejecting the change with the message of the
+//! first violated constraint.
+
+use std::time::Duration;
+
+use crate::yang::data::{Data, DataTree};
+use crate::{
+    ffi, ChangeContext, Error, Event, GetOptions, Priority, Result, Session, Subscription,
+    SubscriptionOptions,
+};
+
+/// A constraint on the configuration of a module, returning the violation
+/// message if it does not hold.
+pub type Constraint = Box<dyn FnMut(&DataTree) -> std::result::Result<(), String>>;
+
+/// Constraints checked before changes of a module are applied.
+#[derive(Default)]
+pub struct Validator {
+    constraints: Vec<Constraint>,
+}
+
+impl Validator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a constraint on the configuration of the module, which is passed
+    /// as it would be after the change.
+    pub fn check<F>(mut self, constraint: F) -> Self
+    where
+        F: FnMut(&DataTree) -> std::result::Result<(), String> + 'static,
+    {
+        self.constraints.push(Box::new(constraint));
+        self
+    }
+
+    /// Add a constraint that every node selected by the XPath `node` has a
+    /// node selected by the XPath `required`, evaluated from the node.
+    ///
+    /// For example `requires("/m:server/tls", "../certificate")`.
+    pub fn requires(self, node: &str, required: &str) -> Self {
+        let node = node.to_owned();
+        let required = required.to_owned();
+        self.check(move |tree| {
+            let nodes = tree
+                .find_xpath(&node)
+                .map_err(|_| format!("invalid XPath {}", node))?;
+            for n in nodes {
+                let found = n
+                    .find_xpath(&required)
+                    .map_err(|_| format!("invalid XPath {}", required))?
+                    .next()
+                    .is_some();
+                if !found {
+                    return Err(format!("{} requires {}", n.path(), required));
+                }
+            }
+            Ok(())
+        })
+    }
+
+    /// Subscribe the constraints to the changes of a module.
+    ///
+    /// See `Priority` for how `priority` orders the subscriptions of an
+    /// event. A rejected change fails with `SR_ERR_VALIDATION_FAILED`.
+    pub fn subscribe<'a>(
+        mut self,
+        sess: &Session<'a>,
+        mod_name: &str,
+        priority: Priority,
+    ) -> Result<Subscription<'a>> {
+        let xpath = format!("/{}:*", mod_name);
+        let callback = move |ctx: &ChangeContext| {
+            if ctx.event() != Event::Change {
+                return Ok(());
+            }
+            let sess = ctx.session();
+            let data = match sess.get_data(&xpath, None, Duration::ZERO, GetOptions::default()) {
+                Ok(data) => Some(data),
+                Err(Error {
+                    errcode: ffi::sr_error_t::SR_ERR_NOT_FOUND,
+                    ..
+                }) => None,
+                Err(e) => return Err(e),
+            };
+            let ctx = sess
+                .get_context()
+                .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+            let empty = DataTree::new(&ctx);
+            let managed = data.as_ref().map(|data| data.tree());
+            let tree: &DataTree = match &managed {
+                Some(tree) => tree,
+                None => &empty,
+            };
+
+            for constraint in &mut self.constraints {
+                if let Err(message) = constraint(tree) {
+                    sess.set_error_message(&message)?;
+                    return Err(Error::new(ffi::sr_error_t::SR_ERR_VALIDATION_FAILED));
+                }
+            }
+            Ok(())
+        };
+        sess.new_module_change_subscription(
+            mod_name,
+            None::<&str>,
+            callback,
+            priority,
+            SubscriptionOptions::default(),
+        )
+    }
+}
@@ -0,0 +1,710 @@
+//! Conversion between sysrepo changes and YANG Patch (RFC 8072) documents.
+//!
+//! Edit targets are RESTCONF data resource identifiers relative to the
+//! datastore root, such as `/ietf-interfaces:interfaces/interface=eth0`.
+
+use std::ffi::CStr;
+use std::fmt::Write;
+use std::ptr;
+use std::time::Duration;
+
+use crate::yang::context::Context;
+use crate::yang::data::{
+    Data, DataFormat, DataNodeRef, DataParserFlags, DataPrinterFlags, DataTree, DataValidationFlags,
+};
+use crate::yang::schema::{SchemaModule, SchemaNodeKind};
+use crate::yang::utils::Binding;
+use crate::{
+    ffi, str_to_cstring, xpath_literal, ChangeOperation, Changes, EditOptions, Error, Result,
+    Session,
+};
+
+/// A single edit of a YANG Patch document.
+struct Edit {
+    operation: &'static str,
+    target: String,
+    point: Option<String>,
+    where_: Option<&'static str>,
+    /// The value, already printed in the format of the document.
+    value: Option<String>,
+}
+
+/// Build a YANG Patch document from a change set.
+///
+/// Changes of the descendants of created or deleted nodes are part of the
+/// edits of those nodes. Only `DataFormat::JSON` and `DataFormat::XML` are
+/// supported.
+pub fn from_changes(changes: &Changes, patch_id: &str, format: DataFormat) -> Result<String> {
+    if !matches!(format, DataFormat::JSON | DataFormat::XML) {
+        return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+    }
+
+    let mut edits = Vec::new();
+    let mut covered: Vec<String> = Vec::new();
+    for change in changes {
+        let (tree, oper) = change?;
+        let Some(node) = tree.reference() else {
+            continue;
+        };
+        let path = node.path();
+        if covered.iter().any(|parent| {
+            path.strip_prefix(parent.as_str())
+                .is_some_and(|rest| rest.starts_with('/'))
+        }) {
+            continue;
+        }
+
+        let target = restconf_path(&node);
+        let edit = match oper {
+            ChangeOperation::Created => {
+                covered.push(path);
+                Edit {
+                    operation: "create",
+                    target,
+                    point: None,
+                    where_: None,
+                    value: Some(print_value(&node, format)?),
+                }
+            }
+            ChangeOperation::CreatedLeafListUserOrdered {
+                previous_value: previous,
+            }
+            | ChangeOperation::CreatedListUserOrdered {
+                previous_key: previous,
+            } => {
+                covered.push(path);
+                let (point, where_) = position(&node, previous);
+                Edit {
+                    operation: "insert",
+                    target,
+                    point,
+                    where_: Some(where_),
+                    value: Some(print_value(&node, format)?),
+                }
+            }
+            ChangeOperation::Modified { .. } => Edit {
+                operation: "replace",
+                target,
+                point: None,
+                where_: None,
+                value: Some(print_value(&node, format)?),
+            },
+            ChangeOperation::Deleted => {
+                covered.push(path);
+                Edit {
+                    operation: "delete",
+                    target,
+                    point: None,
+                    where_: None,
+                    value: None,
+                }
+            }
+            ChangeOperation::MovedLeafListUserOrdered {
+                previous_value: previous,
+            }
+            | ChangeOperation::MovedListUserOrdered {
+                previous_key: previous,
+            } => {
+                let (point, where_) = position(&node, previous);
+                Edit {
+                    operation: "move",
+                    target,
+                    point,
+                    where_: Some(where_),
+                    value: None,
+                }
+            }
+            ChangeOperation::Other(_) => {
+                return Err(Error::new(ffi::sr_error_t::SR_ERR_UNSUPPORTED))
+            }
+        };
+        edits.push(edit);
+    }
+
+    Ok(match format {
+        DataFormat::XML => to_xml(patch_id, &edits),
+        _ => to_json(patch_id, &edits),
+    })
+}
+
+impl Session<'_> {
+    /// Apply a YANG Patch document to the datastore of the session.
+    ///
+    /// Either all edits are applied or none. The `ietf-yang-patch` module
+    /// must be installed in sysrepo.
+    ///
+    /// The timeout is rounded to the nearest millisecond.
+    pub fn apply_yang_patch(
+        &mut self,
+        patch: &str,
+        format: DataFormat,
+        timeout: Duration,
+    ) -> Result<()> {
+        let result = self
+            .edit_yang_patch(patch, format)
+            .and_then(|_| self.apply_changes(timeout));
+        if result.is_err() {
+            let _ = self.discard_changes();
+        }
+        result
+    }
+
+    fn edit_yang_patch(&mut self, patch: &str, format: DataFormat) -> Result<()> {
+        let ctx = self
+            .get_context()
+            .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+        let not_found = Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND);
+        let module = ctx
+            .get_module_implemented("ietf-yang-patch")
+            .ok_or(not_found.clone())?;
+        let ext = module
+            .extensions()
+            .find(|ext| ext.argument().as_deref() == Some("yang-patch"))
+            .ok_or(not_found)?;
+
+        let tree = DataTree::parse_ext_string(
+            &ext,
+            patch,
+            format,
+            DataParserFlags::STRICT,
+            DataValidationFlags::empty(),
+        )
+        .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG))?;
+        let Some(root) = tree.reference() else {
+            return Ok(());
+        };
+        for edit in root.children().filter(|n| n.schema().name() == "edit") {
+            self.yang_patch_edit(&ctx, &edit, format)?;
+        }
+        Ok(())
+    }
+
+    fn yang_patch_edit(
+        &mut self,
+        ctx: &Context,
+        edit: &DataNodeRef<'_>,
+        format: DataFormat,
+    ) -> Result<()> {
+        let inval = || Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+        let child = |name: &str| edit.children().find(|n| n.schema().name() == name);
+        let leaf = |name: &str| child(name).and_then(|n| n.value_canonical());
+
+        let operation = leaf("operation").ok_or_else(inval)?;
+        let targets = restconf_to_xpath(ctx, &leaf("target").ok_or_else(inval)?)?;
+        let target = targets.last().ok_or_else(inval)?;
+        let where_ = leaf("where");
+        let point = match leaf("point") {
+            Some(point) => restconf_to_xpath(ctx, &point)?.pop(),
+            None => None,
+        };
+
+        match operation.as_str() {
+            "delete" => self.delete_item(&target.xpath, EditOptions::STRICT),
+            "remove" => self.delete_item(&target.xpath, EditOptions::default()),
+            "move" => {
+                let position = match where_.as_deref().unwrap_or("last") {
+                    "before" => ffi::sr_move_position_t::SR_MOVE_BEFORE,
+                    "after" => ffi::sr_move_position_t::SR_MOVE_AFTER,
+                    "first" => ffi::sr_move_position_t::SR_MOVE_FIRST,
+                    "last" => ffi::sr_move_position_t::SR_MOVE_LAST,
+                    _ => return Err(inval()),
+                };
+                let path = str_to_cstring(&target.xpath)?;
+                let keys = point
+                    .as_ref()
+                    .and_then(|p| p.keys.as_deref())
+                    .map(str_to_cstring)
+                    .transpose()?;
+                let value = point
+                    .as_ref()
+                    .and_then(|p| p.value.as_deref())
+                    .map(str_to_cstring)
+                    .transpose()?;
+                let rc = unsafe {
+                    ffi::sr_move_item(
+                        self.sess,
+                        path.as_ptr(),
+                        position,
+                        keys.as_deref().map_or(ptr::null(), |k| k.as_ptr()),
+                        value.as_deref().map_or(ptr::null(), |v| v.as_ptr()),
+                        ptr::null(),
+                        0,
+                    )
+                };
+                let rc = rc as ffi::sr_error_t::Type;
+                if rc != ffi::sr_error_t::SR_ERR_OK {
+                    Err(Error::new(rc))
+                } else {
+                    Ok(())
+                }
+            }
+            "create" | "merge" | "replace" | "insert" => {
+                let value = child("value").ok_or_else(inval)?;
+                let (value, value_format) = any_value(&value, format)?;
+                let parent = targets.len().checked_sub(2).map(|i| &targets[i].xpath);
+                let tree = parse_value(ctx, parent.map(String::as_str), &value, value_format)?;
+
+                // Mark the parsed nodes with the edit operation.
+                let nodes = match (parent, tree.reference()) {
+                    (Some(parent), Some(_)) => tree
+                        .find_path(parent)
+                        .map(|parent| {
+                            parent
+                                .children()
+                                .filter(|n| !n.schema().is_list_key())
+                                .map(|n| n.as_raw())
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    (None, Some(first)) => first.inclusive_siblings().map(|n| n.as_raw()).collect(),
+                    (_, None) => Vec::new(),
+                };
+                let netconf = ctx
+                    .get_module_implemented("ietf-netconf")
+                    .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+                let yang = ctx
+                    .get_module_implemented("yang")
+                    .ok_or(Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+                let nc_operation = if operation == "insert" {
+                    "create"
+                } else {
+                    operation.as_str()
+                };
+                for node in nodes {
+                    new_meta(&netconf, node, "operation", nc_operation)?;
+                    if operation == "insert" {
+                        let where_ = where_.as_deref().unwrap_or("last");
+                        new_meta(&yang, node, "insert", where_)?;
+                        if let Some(keys) = point.as_ref().and_then(|p| p.keys.as_deref()) {
+                            new_meta(&yang, node, "key", keys)?;
+                        }
+                        if let Some(value) = point.as_ref().and_then(|p| p.value.as_deref()) {
+                            new_meta(&yang, node, "value", value)?;
+                        }
+                    }
+                }
+
+                let merge = str_to_cstring("merge")?;
+                let root = tree.reference().map_or(ptr::null_mut(), |n| n.as_raw());
+                let rc = unsafe { ffi::sr_edit_batch(self.sess, root, merge.as_ptr()) };
+                let rc = rc as ffi::sr_error_t::Type;
+                if rc != ffi::sr_error_t::SR_ERR_OK {
+                    Err(Error::new(rc))
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Err(inval()),
+        }
+    }
+}
+
+/// A node addressed by a RESTCONF path, converted to XPath.
+struct Target {
+    xpath: String,
+    /// Key predicates of a list instance.
+    keys: Option<String>,
+    /// Value of a leaf-list instance.
+    value: Option<String>,
+}
+
+/// Convert a RESTCONF data resource identifier to XPath, returning every
+/// node on the path.
+fn restconf_to_xpath(ctx: &Context, path: &str) -> Result<Vec<Target>> {
+    let inval = || Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+
+    let mut targets = Vec::new();
+    let mut schema_path = String::new();
+    let mut xpath = String::new();
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        let (name, values) = match segment.split_once('=') {
+            Some((name, values)) => (name, Some(values)),
+            None => (segment, None),
+        };
+        let name = percent_decode(name)?;
+        schema_path.push('/');
+        schema_path.push_str(&name);
+        xpath.push('/');
+        xpath.push_str(&name);
+
+        let mut keys = None;
+        let mut value = None;
+        if let Some(values) = values {
+            let values = values
+                .split(',')
+                .map(percent_decode)
+                .collect::<Result<Vec<_>>>()?;
+            let schema = ctx
+                .find_path(&schema_path)
+                .map_err(|_| Error::new(ffi::sr_error_t::SR_ERR_NOT_FOUND))?;
+            match schema.kind() {
+                SchemaNodeKind::List => {
+                    let names = schema
+                        .list_keys()
+                        .map(|key| key.name().to_owned())
+                        .collect::<Vec<_>>();
+                    if names.len() != values.len() {
+                        return Err(inval());
+                    }
+                    let predicates = names
+                        .iter()
+                        .zip(&values)
+                        .map(|(name, value)| format!("[{}={}]", name, xpath_literal(value)))
+                        .collect::<String>();
+                    xpath.push_str(&predicates);
+                    keys = Some(predicates);
+                }
+                SchemaNodeKind::LeafList => {
+                    let [v] = values.as_slice() else {
+                        return Err(inval());
+                    };
+                    let _ = write!(xpath, "[.={}]", xpath_literal(v));
+                    value = Some(v.clone());
+                }
+                _ => return Err(inval()),
+            }
+        }
+        targets.push(Target {
+            xpath: xpath.clone(),
+            keys,
+            value,
+        });
+    }
+    Ok(targets)
+}
+
+/// Get the content of an anydata node as a string in a data format.
+///
+/// Parsed content is printed in `format`, the format of the patch.
+fn any_value(node: &DataNodeRef<'_>, format: DataFormat) -> Result<(String, DataFormat)> {
+    let any = node.as_raw() as *mut crate::yang::ffi::lyd_node_any;
+    unsafe {
+        match (*any).value_type {
+            crate::yang::ffi::LYD_ANYDATA_VALUETYPE::LYD_ANYDATA_DATATREE => {
+                let tree = (*any).value.tree;
+                if tree.is_null() {
+                    return Ok((String::new(), format));
+                }
+                let mut out = ptr::null_mut();
+                let ret = crate::yang::ffi::lyd_print_mem(
+                    &mut out,
+                    tree,
+                    format as u32,
+                    DataPrinterFlags::WITH_SIBLINGS.bits() | DataPrinterFlags::SHRINK.bits(),
+                );
+                if ret != crate::yang::ffi::LY_ERR::LY_SUCCESS || out.is_null() {
+                    return Err(Error::new(ffi::sr_error_t::SR_ERR_LY));
+                }
+                let value = CStr::from_ptr(out).to_string_lossy().into_owned();
+                libc::free(out as *mut _);
+                Ok((value, format))
+            }
+            crate::yang::ffi::LYD_ANYDATA_VALUETYPE::LYD_ANYDATA_XML
+            | crate::yang::ffi::LYD_ANYDATA_VALUETYPE::LYD_ANYDATA_STRING => Ok((
+                CStr::from_ptr((*any).value.xml)
+                    .to_string_lossy()
+                    .into_owned(),
+                DataFormat::XML,
+            )),
+            crate::yang::ffi::LYD_ANYDATA_VALUETYPE::LYD_ANYDATA_JSON => Ok((
+                CStr::from_ptr((*any).value.json)
+                    .to_string_lossy()
+                    .into_owned(),
+                DataFormat::JSON,
+            )),
+            _ => Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG)),
+        }
+    }
+}
+
+/// Parse an edit value, below the node at `parent` if it is not top-level.
+fn parse_value<'a>(
+    ctx: &'a Context,
+    parent: Option<&str>,
+    value: &str,
+    format: DataFormat,
+) -> Result<DataTree<'a>> {
+    let ly_err = |_| Error::new(ffi::sr_error_t::SR_ERR_LY);
+    let mut tree = DataTree::new(ctx);
+    let parent = match parent {
+        Some(parent) => tree
+            .new_path(parent, None, false)
+            .map_err(ly_err)?
+            .map_or(ptr::null_mut(), |n| n.as_raw()),
+        None => ptr::null_mut(),
+    };
+
+    let value = str_to_cstring(value)?;
+    let mut ly_in = ptr::null_mut();
+    let mut first = ptr::null_mut();
+    let ret = unsafe {
+        let ret = crate::yang::ffi::ly_in_new_memory(value.as_ptr(), &mut ly_in);
+        if ret != crate::yang::ffi::LY_ERR::LY_SUCCESS {
+            return Err(Error::new(ffi::sr_error_t::SR_ERR_NO_MEMORY));
+        }
+        let ret = crate::yang::ffi::lyd_parse_data(
+            module_context(ctx)?,
+            parent,
+            ly_in,
+            format as u32,
+            DataParserFlags::NO_VALIDATION.bits() | DataParserFlags::STRICT.bits(),
+            0,
+            &mut first,
+        );
+        crate::yang::ffi::ly_in_free(ly_in, 0);
+        ret
+    };
+    if ret != crate::yang::ffi::LY_ERR::LY_SUCCESS {
+        return Err(Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG));
+    }
+
+    if parent.is_null() {
+        // The parsed nodes are not connected to the empty tree.
+        tree = unsafe { DataTree::from_raw(ctx, first) };
+    }
+    Ok(tree)
+}
+
+/// Get the raw pointer of a context, through one of its modules.
+fn module_context(ctx: &Context) -> Result<*const crate::yang::ffi::ly_ctx> {
+    let module = ctx
+        .modules(false)
+        .next()
+        .ok_or(Error::new(ffi::sr_error_t::SR_ERR_INTERNAL))?;
+    Ok(unsafe { (*module.as_raw()).ctx })
+}
+
+/// Add a metadata instance to a node.
+fn new_meta(
+    module: &SchemaModule<'_>,
+    node: *mut crate::yang::ffi::lyd_node,
+    name: &str,
+    value: &str,
+) -> Result<()> {
+    let name = str_to_cstring(name)?;
+    let value = str_to_cstring(value)?;
+    let ret = unsafe {
+        crate::yang::ffi::lyd_new_meta(
+            ptr::null(),
+            node,
+            module.as_raw(),
+            name.as_ptr(),
+            value.as_ptr(),
+            0,
+            ptr::null_mut(),
+        )
+    };
+    if ret != crate::yang::ffi::LY_ERR::LY_SUCCESS {
+        Err(Error::new(ffi::sr_error_t::SR_ERR_LY))
+    } else {
+        Ok(())
+    }
+}
+
+/// Print a node and its descendants, without the change metadata.
+fn print_value(node: &DataNodeRef<'_>, format: DataFormat) -> Result<String> {
+    let ly_err = |_| Error::new(ffi::sr_error_t::SR_ERR_LY);
+    let value = node.duplicate(false).map_err(ly_err)?;
+    for node in value.traverse() {
+        unsafe { crate::yang::ffi::lyd_free_meta_siblings((*node.as_raw()).meta) };
+    }
+    value
+        .print_string(format, DataPrinterFlags::SHRINK)
+        .map_err(ly_err)
+}
+
+/// Get the RESTCONF data resource identifier of a node.
+fn restconf_path(node: &DataNodeRef<'_>) -> String {
+    let mut nodes = node.inclusive_ancestors().collect::<Vec<_>>();
+    nodes.reverse();
+
+    let mut path = String::new();
+    let mut prev_module = String::new();
+    for node in nodes {
+        let schema = node.schema();
+        let module = schema.module().name().to_owned();
+        path.push('/');
+        if module != prev_module {
+            let _ = write!(path, "{}:", module);
+        }
+        path.push_str(schema.name());
+        match schema.kind() {
+            SchemaNodeKind::List => {
+                let keys = node
+                    .list_keys()
+                    .map(|key| percent_encode(&key.value_canonical().unwrap_or_default()))
+                    .collect::<Vec<_>>();
+                if !keys.is_empty() {
+                    let _ = write!(path, "={}", keys.join(","));
+                }
+            }
+            SchemaNodeKind::LeafList => {
+                let value = node.value_canonical().unwrap_or_default();
+                let _ = write!(path, "={}", percent_encode(&value));
+            }
+            _ => {}
+        }
+        prev_module = module;
+    }
+    path
+}
+
+/// Get the point and where of a user-ordered node, from the value or keys
+/// of its preceding instance.
+fn position(node: &DataNodeRef<'_>, previous: &str) -> (Option<String>, &'static str) {
+    if previous.is_empty() {
+        return (None, "first");
+    }
+
+    let target = restconf_path(node);
+    // Cut the key values or value of this instance.
+    let (parent, last) = target.rsplit_once('/').unwrap_or_default();
+    let name = last.split('=').next().unwrap_or_default();
+    let values = if node.schema().kind() == SchemaNodeKind::List {
+        predicate_values(previous)
+    } else {
+        vec![previous.to_owned()]
+    };
+    let values = values
+        .iter()
+        .map(|value| percent_encode(value))
+        .collect::<Vec<_>>();
+    let point = format!("{}/{}={}", parent, name, values.join(","));
+    (Some(point), "after")
+}
+
+/// Get the values of XPath key predicates such as `[name='eth0'][unit='1']`.
+fn predicate_values(predicates: &str) -> Vec<String> {
+    let mut values = Vec::new();
+    let mut rest = predicates;
+    while let Some(start) = rest.find(['\'', '"']) {
+        let quote = rest[start..].chars().next().unwrap();
+        let value = &rest[start + 1..];
+        let Some(end) = value.find(quote) else {
+            break;
+        };
+        values.push(value[..end].to_owned());
+        rest = &value[end + 1..];
+    }
+    values
+}
+
+/// Percent-encode a key value for use in a RESTCONF path.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", b);
+        }
+    }
+    encoded
+}
+
+/// Decode a percent-encoded part of a RESTCONF path.
+fn percent_decode(value: &str) -> Result<String> {
+    let inval = || Error::new(ffi::sr_error_t::SR_ERR_INVAL_ARG);
+    let mut decoded = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(b) = bytes.next() {
+        if b == b'%' {
+            let hex = [
+                bytes.next().ok_or_else(inval)?,
+                bytes.next().ok_or_else(inval)?,
+            ];
+            let hex = std::str::from_utf8(&hex).map_err(|_| inval())?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| inval())?);
+        } else {
+            decoded.push(b);
+        }
+    }
+    String::from_utf8(decoded).map_err(|_| inval())
+}
+
+fn to_json(patch_id: &str, edits: &[Edit]) -> String {
+    let mut doc = String::new();
+    let _ = write!(
+        doc,
+        "{{\"ietf-yang-patch:yang-patch\":{{\"patch-id\":{},\"edit\":[",
+        json_string(patch_id)
+    );
+    for (i, edit) in edits.iter().enumerate() {
+        if i > 0 {
+            doc.push(',');
+        }
+        let _ = write!(
+            doc,
+            "{{\"edit-id\":\"{}\",\"operation\":\"{}\",\"target\":{}",
+            i + 1,
+            edit.operation,
+            json_string(&edit.target)
+        );
+        if let Some(point) = &edit.point {
+            let _ = write!(doc, ",\"point\":{}", json_string(point));
+        }
+        if let Some(where_) = edit.where_ {
+            let _ = write!(doc, ",\"where\":\"{}\"", where_);
+        }
+        if let Some(value) = &edit.value {
+            let _ = write!(doc, ",\"value\":{}", value);
+        }
+        doc.push('}');
+    }
+    doc.push_str("]}}");
+    doc
+}
+
+fn to_xml(patch_id: &str, edits: &[Edit]) -> String {
+    let mut doc = String::new();
+    let _ = write!(
+        doc,
+        "<yang-patch xmlns=\"urn:ietf:params:xml:ns:yang:ietf-yang-patch\"><patch-id>{}</patch-id>",
+        xml_escape(patch_id)
+    );
+    for (i, edit) in edits.iter().enumerate() {
+        let _ = write!(
+            doc,
+            "<edit><edit-id>{}</edit-id><operation>{}</operation><target>{}</target>",
+            i + 1,
+            edit.operation,
+            xml_escape(&edit.target)
+        );
+        if let Some(point) = &edit.point {
+            let _ = write!(doc, "<point>{}</point>", xml_escape(point));
+        }
+        if let Some(where_) = edit.where_ {
+            let _ = write!(doc, "<where>{}</where>", where_);
+        }
+        if let Some(value) = &edit.value {
+            let _ = write!(doc, "<value>{}</value>", value);
+        }
+        doc.push_str("</edit>");
+    }
+    doc.push_str("</yang-patch>");
+    doc
+}
+
+fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(escaped, "\\u{:04x}", c as u32);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
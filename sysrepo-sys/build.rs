@@ -15,8 +15,27 @@ fn main() {
         _ => panic!("One and only one of the yang* features must be set"),
     };
 
-    let bindings = bindgen::Builder::default()
-        .header("wrapper.h")
+    let mut builder = bindgen::Builder::default().header("wrapper.h");
+    if env::var("CARGO_FEATURE_SUBSCRIBED_NOTIFICATIONS").is_ok() {
+        builder = builder
+            .header_contents(
+                "subscribed_notifications.h",
+                "#include <sysrepo/subscribed_notifications.h>",
+            )
+            .allowlist_item("srsn_.*")
+            .allowlist_item("SRSN_.*");
+    }
+    if env::var("CARGO_FEATURE_ERROR_FORMAT").is_ok() {
+        builder = builder.header_contents("error_format.h", "#include <sysrepo/error_format.h>");
+    }
+    if env::var("CARGO_FEATURE_NETCONF_ACM").is_ok() {
+        builder = builder.header_contents("netconf_acm.h", "#include <sysrepo/netconf_acm.h>");
+    }
+    if env::var("CARGO_FEATURE_XPATH").is_ok() {
+        builder = builder.header_contents("xpath.h", "#include <sysrepo/xpath.h>");
+    }
+
+    let bindings = builder
         .derive_default(true)
         .size_t_is_usize(false)
         .default_enum_style(bindgen::EnumVariation::ModuleConsts)